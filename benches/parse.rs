@@ -0,0 +1,124 @@
+use std::convert::TryFrom;
+use std::io::{Cursor, Read};
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+use ergo_handshake::encoding::vlq::{ReadVlqExt, SliceReader, TryFromVlq, TryIntoVlq, WriteVlqExt};
+use ergo_handshake::features::{Features, PeerFeature};
+use ergo_handshake::messages::Handshake;
+use ergo_handshake::models::{PeerAddr, ShortString, Version};
+
+// real app handshake with public address, mode and session id features
+const HS_HEX: &str = "dee2aca3fb2e076572676f726566040005126572676f2d6d61696e6e65742d342e302e310108d5efc1d0c64602100400010001030e01000204eecc9582ffaaafeeaa01";
+const PEERS_NUM: usize = 5000;
+
+// Parsing path as it was before borrowed decoding: input is copied into the cursor and each field into its own buffer
+mod allocating {
+    use super::*;
+
+    type BoxResult<T> = Result<T, Box<dyn std::error::Error>>;
+
+    fn read_data(reader: &mut impl Read, len: usize) -> BoxResult<Vec<u8>> {
+        let mut buf = vec![0; len];
+        reader.read_exact(&mut buf)?;
+        Ok(buf)
+    }
+
+    fn read_short_string(reader: &mut Cursor<Vec<u8>>) -> BoxResult<ShortString> {
+        let len = reader.get_u8()?;
+        Ok(ShortString::try_from(read_data(reader, len as usize)?)?)
+    }
+
+    pub fn read_peer_addr(reader: &mut impl Read) -> BoxResult<PeerAddr> {
+        let len = reader.get_u8()?;
+        Ok(PeerAddr::try_from_vlq(&read_data(reader, len as usize)?)?)
+    }
+
+    pub fn parse_hs(data: &[u8]) -> BoxResult<Handshake> {
+        let mut reader = Cursor::new(data.to_vec());
+        let _timestamp = reader.get_u64()?;
+        let agent_name = read_short_string(&mut reader)?;
+        let version = {
+            let mut v = Version::default();
+            reader.read_exact(&mut v.0)?;
+            v
+        };
+        let peer_name = read_short_string(&mut reader)?;
+        let pub_address = if reader.get_u8()? == 1 {
+            // skipping public address length excess bytes
+            let len = reader.get_u8()? - 2;
+            Some(PeerAddr::try_from_vlq(&read_data(&mut reader, len as usize)?)?)
+        } else {
+            None
+        };
+        let features = {
+            let num = reader.get_u8()?;
+            let mut features = Vec::with_capacity(num as usize);
+            for _ in 0..num {
+                let id = reader.get_u8()?;
+                let len = reader.get_u16()?;
+                let data = read_data(&mut reader, len as usize)?;
+                features.push(PeerFeature::try_from((id, data.as_slice()))?);
+            }
+            Some(Features::try_new(features)?)
+        };
+        Ok(Handshake { agent_name, version, peer_name, pub_address, features })
+    }
+}
+
+fn peer_list_bytes() -> Vec<u8> {
+    let mut buf = Vec::new();
+    for i in 0..PEERS_NUM {
+        let addr = PeerAddr(([10, 0, (i / 256) as u8, (i % 256) as u8], 9030).into());
+        let data = addr.try_into_vlq().expect("internal error: can't serialize peer addr");
+        buf.put_u8(data.len() as u8).expect("internal error: can't write to vec");
+        buf.extend_from_slice(&data);
+    }
+    buf
+}
+
+fn parse_peer_list(data: &[u8]) -> Vec<PeerAddr> {
+    let mut reader = SliceReader::new(data);
+    let mut peers = Vec::with_capacity(PEERS_NUM);
+    while !reader.remaining().is_empty() {
+        let len = reader.get_u8().expect("internal error: can't read peer addr len");
+        let data = reader.read_slice(len as usize).expect("internal error: can't read peer addr");
+        peers.push(PeerAddr::try_from_vlq(data).expect("internal error: can't parse peer addr"));
+    }
+    peers
+}
+
+fn parse_peer_list_allocating(data: &[u8]) -> Vec<PeerAddr> {
+    let mut reader = Cursor::new(data.to_vec());
+    let mut peers = Vec::with_capacity(PEERS_NUM);
+    while (reader.position() as usize) < data.len() {
+        peers.push(allocating::read_peer_addr(&mut reader).expect("internal error: can't parse peer addr"));
+    }
+    peers
+}
+
+fn bench_handshake(c: &mut Criterion) {
+    let data = hex::decode(HS_HEX).expect("internal error: invalid hex str");
+    assert_eq!(
+        Handshake::parse(&data).expect("internal error: can't parse hs"),
+        allocating::parse_hs(&data).expect("internal error: can't parse hs")
+    );
+
+    let mut group = c.benchmark_group("handshake_parse");
+    group.bench_function("slice", |b| b.iter(|| Handshake::parse(black_box(&data))));
+    group.bench_function("allocating", |b| b.iter(|| allocating::parse_hs(black_box(&data))));
+    group.finish();
+}
+
+fn bench_peer_list(c: &mut Criterion) {
+    let data = peer_list_bytes();
+    assert_eq!(parse_peer_list(&data), parse_peer_list_allocating(&data));
+
+    let mut group = c.benchmark_group("peer_list_parse");
+    group.bench_function("slice", |b| b.iter(|| parse_peer_list(black_box(&data))));
+    group.bench_function("allocating", |b| b.iter(|| parse_peer_list_allocating(black_box(&data))));
+    group.finish();
+}
+
+criterion_group!(benches, bench_handshake, bench_peer_list);
+criterion_main!(benches);
@@ -1,8 +1,7 @@
-use std::convert::TryFrom;
-
+use ergo_handshake::features::Mode;
 use ergo_handshake::handshaking;
-use ergo_handshake::models::{ShortString, Version};
 use ergo_handshake::messages::Handshake;
+use ergo_handshake::models::Version;
 
 fn main() {
     // Run locally ergo node
@@ -13,13 +12,11 @@ fn main() {
 }
 
 fn my_default_hs() -> Handshake {
-    let short_string = |s: &str| ShortString::try_from(s.to_string().into_bytes()).expect("invalid short string");
-
-    Handshake {
-        agent_name: short_string("ergoref"),
-        version: Version([3, 3, 6]),
-        peer_name: short_string("ergo-mainnet"),
-        pub_address: None,
-        features: None
-    }
-}
\ No newline at end of file
+    Handshake::builder()
+        .agent_name("ergoref")
+        .version(Version([3, 3, 6]))
+        .node_name("ergo-mainnet")
+        .mode(Mode { state_type: 0, is_verifying: true, nipopow_suffix_len: None, blocks_to_keep: -1 })
+        .build()
+        .expect("invalid handshake")
+}
@@ -0,0 +1,9 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use ergo_handshake::messages::Handshake;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = Handshake::parse(data);
+    let _ = Handshake::annotate(data).map(|annotation| annotation.to_string());
+});
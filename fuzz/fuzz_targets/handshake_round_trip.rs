@@ -0,0 +1,12 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use ergo_handshake::messages::Handshake;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(hs) = Handshake::parse(data) {
+        let serialized = hs.serialize().expect("parsed handshake can't be serialized");
+        let reparsed = Handshake::parse(&serialized).expect("serialized handshake can't be parsed");
+        assert_eq!(hs, reparsed);
+    }
+});
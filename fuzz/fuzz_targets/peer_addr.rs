@@ -0,0 +1,12 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use ergo_handshake::encoding::vlq::{TryFromVlq, TryIntoVlq};
+use ergo_handshake::models::PeerAddr;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(value) = PeerAddr::try_from_vlq(data) {
+        let serialized = value.try_into_vlq().expect("parsed value can't be serialized");
+        assert_eq!(PeerAddr::try_from_vlq(&serialized).ok(), Some(value));
+    }
+});
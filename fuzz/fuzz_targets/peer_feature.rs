@@ -0,0 +1,19 @@
+#![no_main]
+use std::convert::TryFrom;
+
+use libfuzzer_sys::fuzz_target;
+
+use ergo_handshake::encoding::vlq::TryIntoVlq;
+use ergo_handshake::features::PeerFeature;
+
+// First byte is the feature id, the rest is the feature payload
+fuzz_target!(|data: &[u8]| {
+    if let Some((&id, payload)) = data.split_first() {
+        if let Ok(feature) = PeerFeature::try_from((id, payload)) {
+            if let Some(id) = feature.get_id() {
+                let serialized = feature.try_into_vlq().expect("parsed feature can't be serialized");
+                assert_eq!(PeerFeature::try_from((id, serialized.as_slice())).ok(), Some(feature));
+            }
+        }
+    }
+});
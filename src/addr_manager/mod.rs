@@ -0,0 +1,406 @@
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+
+use crate::features::PeerFeature;
+use crate::messages::{Handshake, Peers};
+use crate::models::{NetGroup, PeerAddr};
+use crate::utils::{random_u64, siphash24};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AddrManagerConfig {
+    // Secret key of bucket hashes, so attackers can't predict buckets. Fixed key makes bucketing reproducible
+    pub key: [u64; 2],
+    pub new_buckets: usize,
+    pub tried_buckets: usize,
+    pub bucket_size: usize,
+    // Count of new buckets, which addresses from one source netgroup are spread over
+    pub new_buckets_per_source_group: usize,
+    // Count of tried buckets, which addresses of one netgroup are spread over
+    pub tried_buckets_per_group: usize,
+    // Failed attempts, after which new address can be replaced by another one
+    pub max_failures: u32,
+}
+
+impl Default for AddrManagerConfig {
+    fn default() -> Self {
+        AddrManagerConfig {
+            key: [random_u64(), random_u64()],
+            new_buckets: 1024,
+            tried_buckets: 256,
+            bucket_size: 64,
+            new_buckets_per_source_group: 64,
+            tried_buckets_per_group: 8,
+            max_failures: 3,
+        }
+    }
+}
+
+/// Addresses for outbound connections, which are protected from flooding by one network.
+///
+/// Addresses are placed in bitcoin-style buckets. Unconnected ones are in "new" buckets chosen by the netgroups
+/// of the address and of the peer, which sent it, so one source fills only a few buckets. Successfully connected
+/// ones are moved to "tried" buckets chosen by the address netgroup. Address, which bucket slot is taken, is dropped.
+#[derive(Debug, Clone)]
+pub struct AddrManager {
+    config: AddrManagerConfig,
+    entries: HashMap<PeerAddr, AddrEntry>,
+    new_table: Vec<Option<PeerAddr>>,
+    tried_table: Vec<Option<PeerAddr>>,
+    // Makes each selection order different
+    select_nonce: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AddrEntry {
+    pub addr: PeerAddr,
+    // Ip of the peer, which sent the address
+    pub source: IpAddr,
+    pub is_tried: bool,
+    // Failed connection attempts since the last successful one
+    pub failures: u32,
+    slot: usize,
+}
+
+impl AddrManager {
+    pub fn new(config: AddrManagerConfig) -> Self {
+        // zero sizes would make bucket indexes undefined
+        let config = AddrManagerConfig {
+            new_buckets: config.new_buckets.max(1),
+            tried_buckets: config.tried_buckets.max(1),
+            bucket_size: config.bucket_size.max(1),
+            new_buckets_per_source_group: config.new_buckets_per_source_group.max(1),
+            tried_buckets_per_group: config.tried_buckets_per_group.max(1),
+            ..config
+        };
+        AddrManager {
+            new_table: vec![None; config.new_buckets * config.bucket_size],
+            tried_table: vec![None; config.tried_buckets * config.bucket_size],
+            entries: HashMap::new(),
+            select_nonce: 0,
+            config,
+        }
+    }
+
+    pub fn config(&self) -> &AddrManagerConfig {
+        &self.config
+    }
+
+    pub fn get(&self, addr: &PeerAddr) -> Option<&AddrEntry> {
+        self.entries.get(addr)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Adds address received from the `source` peer to a new bucket, returns whether it was added.
+    ///
+    /// Known and unroutable addresses are ignored. Address is dropped, if its slot is taken by another one, which doesn't fail.
+    pub fn add(&mut self, addr: PeerAddr, source: IpAddr) -> bool {
+        if !addr.is_routable() || self.entries.contains_key(&addr) {
+            return false;
+        }
+        let slot = self.new_slot(&addr, source);
+        if let Some(occupant) = self.new_table[slot].take() {
+            match self.entries.get(&occupant) {
+                Some(entry) if entry.failures < self.config.max_failures => {
+                    self.new_table[slot] = Some(occupant);
+                    return false;
+                }
+                _ => {
+                    self.entries.remove(&occupant);
+                }
+            }
+        }
+        self.new_table[slot] = Some(addr.clone());
+        let entry = AddrEntry {
+            addr: addr.clone(),
+            source,
+            is_tried: false,
+            failures: 0,
+            slot,
+        };
+        self.entries.insert(addr, entry);
+        true
+    }
+
+    /// Adds declared public and `LocalAddr` feature addresses of the handshake, returns count of the added ones.
+    pub fn add_handshake(&mut self, hs: &Handshake, source: IpAddr) -> usize {
+        let local_addrs = hs.features.iter().flat_map(|f| f.iter()).filter_map(|f| match f {
+            PeerFeature::LocalAddr(addr) => Some(addr.clone()),
+            _ => None,
+        });
+        hs.pub_address
+            .iter()
+            .cloned()
+            .chain(local_addrs)
+            .filter(|addr| self.add(addr.clone(), source))
+            .count()
+    }
+
+    /// Adds addresses of peer specs from `Peers` message sent by the `source` peer.
+    pub fn add_peers(&mut self, peers: &Peers, source: IpAddr) -> usize {
+        peers.peers.iter().map(|spec| self.add_handshake(spec, source)).sum()
+    }
+
+    /// Moves the connected address to a tried bucket. Address, which takes the slot, is moved back to a new bucket.
+    pub fn mark_good(&mut self, addr: &PeerAddr) -> bool {
+        let entry = match self.entries.get_mut(addr) {
+            Some(entry) => entry,
+            None => return false,
+        };
+        entry.failures = 0;
+        if entry.is_tried {
+            return true;
+        }
+        if self.new_table[entry.slot].as_ref() == Some(addr) {
+            self.new_table[entry.slot] = None;
+        }
+
+        let slot = self.tried_slot(addr);
+        if let Some(evicted) = self.tried_table[slot].take() {
+            self.move_to_new(evicted);
+        }
+        self.tried_table[slot] = Some(addr.clone());
+        if let Some(entry) = self.entries.get_mut(addr) {
+            entry.is_tried = true;
+            entry.slot = slot;
+        }
+        true
+    }
+
+    pub fn mark_failed(&mut self, addr: &PeerAddr) -> bool {
+        match self.entries.get_mut(addr) {
+            Some(entry) => {
+                entry.failures = entry.failures.saturating_add(1);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn remove(&mut self, addr: &PeerAddr) -> Option<AddrEntry> {
+        let entry = self.entries.remove(addr)?;
+        let table = if entry.is_tried { &mut self.tried_table } else { &mut self.new_table };
+        if table[entry.slot].as_ref() == Some(addr) {
+            table[entry.slot] = None;
+        }
+        Some(entry)
+    }
+
+    /// Picks up to `count` addresses from distinct netgroups, alternating tried and new ones.
+    ///
+    /// Order is pseudo-random, but it's determined by the key and the number of previous selections.
+    /// Failing addresses are picked last.
+    pub fn select(&mut self, count: usize) -> Vec<PeerAddr> {
+        self.select_excluding(count, &HashSet::new())
+    }
+
+    /// Same as [`select`](Self::select), but skips the `excluded` netgroups, i.e. the ones of already connected peers.
+    pub fn select_excluding(&mut self, count: usize, excluded: &HashSet<NetGroup>) -> Vec<PeerAddr> {
+        self.select_nonce = self.select_nonce.wrapping_add(1);
+        let nonce = self.select_nonce.to_le_bytes();
+        let order = |e: &AddrEntry| {
+            let is_failing = e.failures >= self.config.max_failures;
+            (is_failing, self.hash(&[b"S", &nonce, &addr_bytes(&e.addr)]))
+        };
+        let sorted = |is_tried: bool| {
+            let mut entries = self.entries.values().filter(|e| e.is_tried == is_tried).collect::<Vec<_>>();
+            entries.sort_by_cached_key(|e| order(e));
+            entries.into_iter()
+        };
+        let (mut tried, mut new) = (sorted(true), sorted(false));
+
+        let mut groups = excluded.clone();
+        let mut selected = Vec::new();
+        while selected.len() < count {
+            let (t, n) = (tried.next(), new.next());
+            if t.is_none() && n.is_none() {
+                break;
+            }
+            for entry in t.into_iter().chain(n) {
+                if selected.len() < count && groups.insert(entry.addr.netgroup()) {
+                    selected.push(entry.addr.clone());
+                }
+            }
+        }
+        selected
+    }
+
+    // Previous occupant of the new slot is dropped
+    fn move_to_new(&mut self, addr: PeerAddr) {
+        let source = match self.entries.get(&addr) {
+            Some(entry) => entry.source,
+            None => return,
+        };
+        let slot = self.new_slot(&addr, source);
+        if let Some(occupant) = self.new_table[slot].replace(addr.clone()) {
+            self.entries.remove(&occupant);
+        }
+        if let Some(entry) = self.entries.get_mut(&addr) {
+            entry.is_tried = false;
+            entry.slot = slot;
+        }
+    }
+
+    fn new_slot(&self, addr: &PeerAddr, source: IpAddr) -> usize {
+        let (group, source_group) = (addr.netgroup().to_bytes(), NetGroup::of(source).to_bytes());
+        let per_source = self.hash(&[b"N1", &group, &source_group]) % self.config.new_buckets_per_source_group as u64;
+        let bucket = self.hash(&[b"N2", &source_group, &per_source.to_le_bytes()]) % self.config.new_buckets as u64;
+        self.slot(b"NP", bucket, addr)
+    }
+
+    fn tried_slot(&self, addr: &PeerAddr) -> usize {
+        let per_group = self.hash(&[b"T1", &addr_bytes(addr)]) % self.config.tried_buckets_per_group as u64;
+        let bucket = self.hash(&[b"T2", &addr.netgroup().to_bytes(), &per_group.to_le_bytes()]) % self.config.tried_buckets as u64;
+        self.slot(b"TP", bucket, addr)
+    }
+
+    fn slot(&self, tag: &[u8], bucket: u64, addr: &PeerAddr) -> usize {
+        let position = self.hash(&[tag, &bucket.to_le_bytes(), &addr_bytes(addr)]) % self.config.bucket_size as u64;
+        bucket as usize * self.config.bucket_size + position as usize
+    }
+
+    // Parts are length-prefixed, so different splits of the same bytes give different hashes
+    fn hash(&self, parts: &[&[u8]]) -> u64 {
+        let mut data = Vec::new();
+        for part in parts {
+            data.push(part.len() as u8);
+            data.extend_from_slice(part);
+        }
+        siphash24(self.config.key, &data)
+    }
+}
+
+fn addr_bytes(addr: &PeerAddr) -> Vec<u8> {
+    let ip = match addr.0.ip().to_canonical() {
+        IpAddr::V4(ip) => ip.octets().to_vec(),
+        IpAddr::V6(ip) => ip.octets().to_vec(),
+    };
+    [ip, addr.0.port().to_be_bytes().to_vec()].concat()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::convert::TryFrom;
+    use std::net::{Ipv4Addr, SocketAddr};
+
+    use crate::features::Features;
+    use crate::models::{ShortString, Version};
+
+    use super::*;
+
+    fn config() -> AddrManagerConfig {
+        AddrManagerConfig {
+            key: [1, 2],
+            new_buckets: 64,
+            tried_buckets: 16,
+            bucket_size: 4,
+            new_buckets_per_source_group: 8,
+            tried_buckets_per_group: 2,
+            max_failures: 3,
+        }
+    }
+
+    fn addr(a: u8, b: u8, c: u8) -> PeerAddr {
+        PeerAddr(SocketAddr::from(([a, b, c, 1], 9030)))
+    }
+
+    fn ip(a: u8) -> IpAddr {
+        IpAddr::V4(Ipv4Addr::new(a, 0, 0, 1))
+    }
+
+    #[test]
+    fn test_source_flood_takes_few_buckets() {
+        let mut manager = AddrManager::new(config());
+        for a in 1..=250 {
+            for c in 0..4 {
+                manager.add(addr(a, 0, c), ip(1));
+            }
+        }
+        let buckets = manager.entries.values().map(|e| e.slot / 4).collect::<HashSet<_>>();
+        assert!(buckets.len() <= 8);
+        assert!(manager.len() <= 8 * 4);
+
+        // addresses from other sources still get slots
+        let added = (2..=20).filter(|s| manager.add(addr(*s, 1, 0), ip(*s))).count();
+        assert!(added > 10, "only {} addresses are added", added);
+    }
+
+    #[test]
+    fn test_select_spreads_across_netgroups() {
+        let mut manager = AddrManager::new(config());
+        for a in 1..=3 {
+            for c in 0..10 {
+                manager.add(addr(a, 0, c), ip(a + 100));
+            }
+        }
+        let selected = manager.select(10);
+        assert_eq!(selected.len(), 3);
+        let groups = selected.iter().map(|a| a.netgroup()).collect::<HashSet<_>>();
+        assert_eq!(groups.len(), 3);
+        assert_eq!(manager.select(2).len(), 2);
+    }
+
+    #[test]
+    fn test_deterministic_with_key() {
+        let fill = || {
+            let mut manager = AddrManager::new(config());
+            for a in 1..=40 {
+                manager.add(addr(a, a, 0), ip(a % 5));
+            }
+            manager.mark_good(&addr(7, 7, 0));
+            manager
+        };
+        let (mut first, mut second) = (fill(), fill());
+        assert_eq!(first.select(20), second.select(20));
+        assert!(first.get(&addr(7, 7, 0)).is_some_and(|e| e.is_tried));
+
+        let mut other_key = AddrManager::new(AddrManagerConfig { key: [3, 4], ..config() });
+        for a in 1..=40 {
+            other_key.add(addr(a, a, 0), ip(a % 5));
+        }
+        assert_ne!(other_key.select(20), second.select(20));
+    }
+
+    #[test]
+    fn test_failing_addresses_are_replaced() {
+        let mut manager = AddrManager::new(AddrManagerConfig { new_buckets: 1, bucket_size: 1, ..config() });
+        assert!(manager.add(addr(1, 0, 0), ip(1)));
+        assert!(!manager.add(addr(2, 0, 0), ip(1)));
+        for _ in 0..3 {
+            manager.mark_failed(&addr(1, 0, 0));
+        }
+        assert!(manager.add(addr(2, 0, 0), ip(1)));
+        assert!(manager.get(&addr(1, 0, 0)).is_none());
+
+        // tried address frees its new slot
+        assert!(manager.mark_good(&addr(2, 0, 0)));
+        assert!(manager.add(addr(3, 0, 0), ip(1)));
+        assert_eq!(manager.len(), 2);
+    }
+
+    #[test]
+    fn test_add_peers() {
+        let spec = |a: u8| {
+            let features = Features::try_new(vec![PeerFeature::LocalAddr(addr(a, 1, 0))]).expect("internal error: invalid features");
+            Handshake {
+                agent_name: ShortString::try_from("ergoref".as_bytes()).expect("internal error: invalid short string"),
+                version: Version([4, 0, 5]),
+                peer_name: ShortString::try_from("node".as_bytes()).expect("internal error: invalid short string"),
+                pub_address: Some(addr(a, 0, 0)),
+                features: Some(features),
+            }
+        };
+        let mut manager = AddrManager::new(config());
+        let peers = Peers { peers: vec![spec(1), spec(2)] };
+        assert_eq!(manager.add_peers(&peers, ip(50)), 4);
+        assert_eq!(manager.add_peers(&peers, ip(50)), 0);
+        assert_eq!(manager.get(&addr(2, 1, 0)).map(|e| e.source), Some(ip(50)));
+    }
+}
@@ -0,0 +1,278 @@
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+use crate::features::SessionId;
+use crate::hs::session_id;
+use crate::messages::Handshake;
+use crate::models::{PeerAddr, ShortString};
+use crate::{handshaking_with, HandshakeConfig};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VerificationStatus {
+    // The same node answered on the declared address
+    Verified,
+    // Another node answered on the declared address
+    Mismatch,
+    // Handshaking with the declared address failed
+    Unreachable,
+}
+
+impl VerificationStatus {
+    pub fn is_verified(&self) -> bool {
+        *self == VerificationStatus::Verified
+    }
+}
+
+/// Checks peers' declared public addresses by dialing them back before they are gossiped further.
+///
+/// Node on the declared address should answer with the same session id as the peer. Node names are
+/// compared, if either handshake has no session id. Node, which answered on the address, is cached for `ttl`,
+/// so each claimant of the address is compared with it, not only the first one.
+///
+/// Unroutable addresses aren't dialed, so peers can't make us connect to loopback or our internal network.
+#[derive(Debug)]
+pub struct AddressVerifier {
+    hs: Handshake,
+    config: HandshakeConfig,
+    ttl: Duration,
+    capacity: usize,
+    allow_unroutable: bool,
+    cache: Mutex<HashMap<PeerAddr, DialedNode>>,
+}
+
+// Node answered on the address, `None` identity if handshaking failed
+#[derive(Debug, Clone)]
+struct DialedNode {
+    identity: Option<NodeIdentity>,
+    checked_at: Instant,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct NodeIdentity {
+    session_id: Option<SessionId>,
+    peer_name: ShortString,
+}
+
+impl NodeIdentity {
+    fn of(hs: &Handshake) -> Self {
+        NodeIdentity {
+            session_id: session_id(hs).cloned(),
+            peer_name: hs.peer_name.clone(),
+        }
+    }
+
+    // Names are compared, if either node has no session id
+    fn is_same_node(&self, other: &NodeIdentity) -> bool {
+        match (self.session_id.as_ref(), other.session_id.as_ref()) {
+            (Some(this), Some(other)) => this == other,
+            _ => self.peer_name == other.peer_name,
+        }
+    }
+}
+
+impl DialedNode {
+    fn status(&self, peer_hs: &Handshake) -> VerificationStatus {
+        match self.identity.as_ref() {
+            Some(identity) if identity.is_same_node(&NodeIdentity::of(peer_hs)) => VerificationStatus::Verified,
+            Some(_) => VerificationStatus::Mismatch,
+            None => VerificationStatus::Unreachable,
+        }
+    }
+}
+
+impl AddressVerifier {
+    pub const DEFAULT_TTL: Duration = Duration::from_secs(60 * 60);
+    pub const DEFAULT_CAPACITY: usize = 4096;
+
+    /// Verifier, which dials back with `hs` handshake.
+    pub fn new(hs: Handshake, config: HandshakeConfig) -> Self {
+        AddressVerifier {
+            hs,
+            config,
+            ttl: Self::DEFAULT_TTL,
+            capacity: Self::DEFAULT_CAPACITY,
+            allow_unroutable: false,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Bounds cached results, the oldest one is evicted, when the cache is full.
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    /// Unroutable addresses are dialed too. Useful only in local networks and tests.
+    pub fn with_unroutable_allowed(mut self, allow_unroutable: bool) -> Self {
+        self.allow_unroutable = allow_unroutable;
+        self
+    }
+
+    /// Verifies the public address declared in the peer's handshake, `None` if there is no address.
+    ///
+    /// Cached node of the address is compared with the peer, if it isn't expired. Otherwise the call blocks
+    /// for the dial back handshaking.
+    pub fn verify(&self, peer_hs: &Handshake) -> Option<VerificationStatus> {
+        let addr = peer_hs.pub_address.as_ref()?;
+        if !self.allow_unroutable && !addr.is_routable() {
+            return Some(VerificationStatus::Unreachable);
+        }
+        if let Some(status) = self.status(peer_hs) {
+            return Some(status);
+        }
+        // lock isn't held during handshaking, so concurrent calls can verify the same address twice
+        let identity = handshaking_with(addr.0, self.hs.clone(), &self.config)
+            .ok()
+            .map(|(_conn, dialed_hs)| NodeIdentity::of(&dialed_hs));
+        let dialed = DialedNode { identity, checked_at: Instant::now() };
+        let status = dialed.status(peer_hs);
+        self.insert(addr.clone(), dialed);
+        Some(status)
+    }
+
+    /// Cached status of the peer's declared address, `None` if it wasn't verified or the result is expired.
+    pub fn status(&self, peer_hs: &Handshake) -> Option<VerificationStatus> {
+        self.status_at(peer_hs, Instant::now())
+    }
+
+    pub fn is_verified(&self, peer_hs: &Handshake) -> bool {
+        self.status(peer_hs).is_some_and(|s| s.is_verified())
+    }
+
+    /// Removes expired results.
+    pub fn prune(&self) {
+        let now = Instant::now();
+        self.lock().retain(|_, dialed| now.saturating_duration_since(dialed.checked_at) < self.ttl);
+    }
+
+    fn insert(&self, addr: PeerAddr, dialed: DialedNode) {
+        let now = dialed.checked_at;
+        let mut cache = self.lock();
+        if !cache.contains_key(&addr) && cache.len() >= self.capacity {
+            cache.retain(|_, dialed| now.saturating_duration_since(dialed.checked_at) < self.ttl);
+        }
+        if !cache.contains_key(&addr) && cache.len() >= self.capacity {
+            let oldest = cache.iter().min_by_key(|(_, dialed)| dialed.checked_at).map(|(addr, _)| addr.clone());
+            if let Some(oldest) = oldest {
+                cache.remove(&oldest);
+            }
+        }
+        if self.capacity > 0 {
+            cache.insert(addr, dialed);
+        }
+    }
+
+    fn status_at(&self, peer_hs: &Handshake, now: Instant) -> Option<VerificationStatus> {
+        let addr = peer_hs.pub_address.as_ref()?;
+        self.lock()
+            .get(addr)
+            .filter(|dialed| now.saturating_duration_since(dialed.checked_at) < self.ttl)
+            .map(|dialed| dialed.status(peer_hs))
+    }
+
+    // Cache entries are replaced as a whole, so they are valid after a panic in another thread
+    fn lock(&self) -> MutexGuard<'_, HashMap<PeerAddr, DialedNode>> {
+        self.cache.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{SocketAddr, TcpListener};
+
+    use crate::testing::{create_hs, MockBehavior, MockPeer};
+
+    use super::*;
+
+    fn declaring(mut hs: Handshake, addr: PeerAddr) -> Handshake {
+        hs.pub_address = Some(addr);
+        hs
+    }
+
+    // mock peers listen on loopback
+    fn verifier() -> AddressVerifier {
+        AddressVerifier::new(create_hs("verifier", 1), HandshakeConfig::new().with_timeout(Duration::from_secs(2))).with_unroutable_allowed(true)
+    }
+
+    #[test]
+    fn test_verified_address() {
+        let node = MockPeer::spawn(create_hs("node", 2), MockBehavior::Honest).expect("internal error: can't spawn mock peer");
+        let claim = declaring(create_hs("node", 2), PeerAddr(node.addr()));
+        let verifier = verifier();
+        assert_eq!(verifier.status(&claim), None);
+        assert_eq!(verifier.verify(&claim), Some(VerificationStatus::Verified));
+        assert!(verifier.is_verified(&claim));
+
+        // cached result doesn't dial again
+        assert_eq!(verifier.verify(&claim), Some(VerificationStatus::Verified));
+        assert_eq!(node.received().len(), 1);
+        assert_eq!(verifier.status_at(&claim, Instant::now() + AddressVerifier::DEFAULT_TTL), None);
+    }
+
+    #[test]
+    fn test_claimants_of_the_same_address() {
+        let node = MockPeer::spawn(create_hs("node", 2), MockBehavior::Honest).expect("internal error: can't spawn mock peer");
+        let owner = declaring(create_hs("node", 2), PeerAddr(node.addr()));
+        let spoofer = declaring(create_hs("spoofer", 3), PeerAddr(node.addr()));
+
+        // spoofer's mismatch doesn't mark the owner, and owner's verification doesn't vouch for the spoofer
+        let verifier = verifier();
+        assert_eq!(verifier.verify(&spoofer), Some(VerificationStatus::Mismatch));
+        assert_eq!(verifier.verify(&owner), Some(VerificationStatus::Verified));
+        assert_eq!(verifier.verify(&spoofer), Some(VerificationStatus::Mismatch));
+        assert!(!verifier.is_verified(&spoofer));
+        assert_eq!(node.received().len(), 1);
+    }
+
+    #[test]
+    fn test_mismatched_address() {
+        let node = MockPeer::spawn(create_hs("node", 2), MockBehavior::Honest).expect("internal error: can't spawn mock peer");
+        let claim = declaring(create_hs("node", 3), PeerAddr(node.addr()));
+        assert_eq!(verifier().verify(&claim), Some(VerificationStatus::Mismatch));
+    }
+
+    #[test]
+    fn test_unreachable_address() {
+        let addr = {
+            let listener = TcpListener::bind("127.0.0.1:0").expect("internal error: can't bind listener");
+            listener.local_addr().expect("internal error: no local addr")
+        };
+        let verifier = verifier();
+        let claim = declaring(create_hs("node", 2), PeerAddr(addr));
+        assert_eq!(verifier.verify(&claim), Some(VerificationStatus::Unreachable));
+        assert!(!verifier.is_verified(&claim));
+        assert_eq!(verifier.verify(&create_hs("node", 2)), None);
+    }
+
+    #[test]
+    fn test_unroutable_address_isnt_dialed() {
+        let node = MockPeer::spawn(create_hs("node", 2), MockBehavior::Honest).expect("internal error: can't spawn mock peer");
+        let verifier = AddressVerifier::new(create_hs("verifier", 1), HandshakeConfig::new());
+        for addr in [node.addr(), "192.168.1.5:9030".parse().expect("internal error: invalid socket addr")].iter() {
+            let claim = declaring(create_hs("node", 2), PeerAddr(*addr));
+            assert_eq!(verifier.verify(&claim), Some(VerificationStatus::Unreachable));
+        }
+        assert!(node.received().is_empty());
+    }
+
+    #[test]
+    fn test_cache_capacity() {
+        let verifier = verifier().with_capacity(2);
+        let now = Instant::now();
+        let addr = |port| PeerAddr(SocketAddr::from(([1, 1, 1, 1], port)));
+        let node = create_hs("node", 2);
+        for port in 1..=3 {
+            let identity = Some(NodeIdentity::of(&node));
+            verifier.insert(addr(port), DialedNode { identity, checked_at: now + Duration::from_secs(u64::from(port)) });
+        }
+        assert_eq!(verifier.lock().len(), 2);
+        assert_eq!(verifier.status_at(&declaring(node.clone(), addr(1)), now), None);
+        assert_eq!(verifier.status_at(&declaring(node, addr(3)), now + Duration::from_secs(3)), Some(VerificationStatus::Verified));
+    }
+}
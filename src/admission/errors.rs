@@ -0,0 +1,17 @@
+use std::net::IpAddr;
+
+use thiserror::Error;
+
+use crate::reputation::Subnet;
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum AdmissionError {
+    #[error("All {0} inbound slots are taken")]
+    TooManyInbound(usize),
+    #[error("Too many connections from {0}")]
+    TooManyFromIp(IpAddr),
+    #[error("Too many connections from subnet {0}")]
+    TooManyFromSubnet(Subnet),
+    #[error("Too many handshakes from {0}")]
+    RateLimited(IpAddr),
+}
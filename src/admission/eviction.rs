@@ -0,0 +1,59 @@
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::net::SocketAddr;
+use std::time::Instant;
+
+use crate::messages::Handshake;
+use crate::reputation::Subnet;
+
+/// Admitted inbound peer as it's seen by the [`EvictionStrategy`].
+#[derive(Debug, Clone)]
+pub struct InboundPeer {
+    pub addr: SocketAddr,
+    pub admitted_at: Instant,
+    // Set by the permit owner, when handshaking is done
+    pub handshake: Option<Handshake>,
+}
+
+/// Picks an admitted peer to disconnect, when all inbound slots are taken.
+pub trait EvictionStrategy: Debug + Send + Sync {
+    /// Index of the peer to evict in favor of the new one or `None`, if the new one should be rejected.
+    fn select(&self, peers: &[InboundPeer], candidate: SocketAddr) -> Option<usize>;
+}
+
+/// New peers are rejected, when inbound slots are taken.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoEviction;
+
+impl EvictionStrategy for NoEviction {
+    fn select(&self, _peers: &[InboundPeer], _candidate: SocketAddr) -> Option<usize> {
+        None
+    }
+}
+
+/// Evicts the most recently admitted peer of the subnet with most connections.
+///
+/// Peers from less crowded subnets are preferred, so a few parties can't hold all slots.
+/// Candidate is rejected, if its subnet is the crowded one.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EvictFromCrowdedSubnet;
+
+impl EvictionStrategy for EvictFromCrowdedSubnet {
+    fn select(&self, peers: &[InboundPeer], candidate: SocketAddr) -> Option<usize> {
+        let mut counts = HashMap::new();
+        for peer in peers {
+            *counts.entry(Subnet::of(peer.addr.ip())).or_insert(0usize) += 1;
+        }
+        let (crowded, count) = counts.into_iter().max_by_key(|(_, count)| *count)?;
+        let candidate_count = peers.iter().filter(|p| Subnet::of(p.addr.ip()) == Subnet::of(candidate.ip())).count();
+        if candidate_count + 1 >= count {
+            return None;
+        }
+        peers
+            .iter()
+            .enumerate()
+            .filter(|(_, p)| Subnet::of(p.addr.ip()) == crowded)
+            .max_by_key(|(_, p)| p.admitted_at)
+            .map(|(i, _)| i)
+    }
+}
@@ -0,0 +1,269 @@
+use std::collections::{HashMap, VecDeque};
+use std::net::{IpAddr, Shutdown, SocketAddr, TcpStream};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+use crate::messages::Handshake;
+use crate::reputation::Subnet;
+
+use errors as admission_errors;
+pub use admission_errors::*;
+pub use eviction::{EvictFromCrowdedSubnet, EvictionStrategy, InboundPeer, NoEviction};
+
+mod errors;
+mod eviction;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AdmissionConfig {
+    pub max_inbound: usize,
+    pub max_per_ip: usize,
+    // Subnets are /24 for IPv4 and /48 for IPv6
+    pub max_per_subnet: usize,
+    // Handshakes allowed from one ip during `rate_window`, rejected ones are counted too
+    pub max_handshakes_per_ip: usize,
+    pub rate_window: Duration,
+}
+
+impl Default for AdmissionConfig {
+    fn default() -> Self {
+        AdmissionConfig {
+            max_inbound: 64,
+            max_per_ip: 2,
+            max_per_subnet: 8,
+            max_handshakes_per_ip: 10,
+            rate_window: Duration::from_secs(60),
+        }
+    }
+}
+
+/// Decides whether an inbound connection gets a slot before its handshake is read.
+///
+/// Clones share the slots. A slot is held by the [`InboundPermit`] and is freed, when the permit is dropped.
+#[derive(Debug, Clone)]
+pub struct InboundAdmission {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    config: AdmissionConfig,
+    eviction: Box<dyn EvictionStrategy>,
+    state: Mutex<AdmissionState>,
+}
+
+#[derive(Debug, Default)]
+struct AdmissionState {
+    next_id: u64,
+    slots: Vec<Slot>,
+    attempts: HashMap<IpAddr, VecDeque<Instant>>,
+}
+
+#[derive(Debug)]
+struct Slot {
+    id: u64,
+    peer: InboundPeer,
+    // Clone of the admitted stream, which is shut down on eviction
+    conn: Option<TcpStream>,
+}
+
+impl InboundAdmission {
+    pub fn new(config: AdmissionConfig) -> Self {
+        Self::with_eviction(config, NoEviction)
+    }
+
+    pub fn with_eviction<E: EvictionStrategy + 'static>(config: AdmissionConfig, eviction: E) -> Self {
+        let inner = Inner {
+            config,
+            eviction: Box::new(eviction),
+            state: Mutex::new(AdmissionState::default()),
+        };
+        InboundAdmission { inner: Arc::new(inner) }
+    }
+
+    pub fn config(&self) -> &AdmissionConfig {
+        &self.inner.config
+    }
+
+    /// Takes a slot for the connection from `addr`. Connection is kept to be shut down, if it's evicted.
+    pub fn admit(&self, addr: SocketAddr, conn: Option<TcpStream>) -> Result<InboundPermit, AdmissionError> {
+        self.admit_at(addr, conn, Instant::now())
+    }
+
+    pub fn inbound_count(&self) -> usize {
+        self.inner.lock().slots.len()
+    }
+
+    pub fn peers(&self) -> Vec<InboundPeer> {
+        self.inner.lock().slots.iter().map(|s| s.peer.clone()).collect()
+    }
+
+    fn admit_at(&self, addr: SocketAddr, conn: Option<TcpStream>, now: Instant) -> Result<InboundPermit, AdmissionError> {
+        let config = &self.inner.config;
+        let ip = addr.ip().to_canonical();
+        let subnet = Subnet::of(ip);
+        let mut state = self.inner.lock();
+
+        state.attempts.retain(|_, attempts| {
+            while attempts.front().is_some_and(|t| now.duration_since(*t) >= config.rate_window) {
+                attempts.pop_front();
+            }
+            !attempts.is_empty()
+        });
+        let attempts = state.attempts.entry(ip).or_default();
+        attempts.push_back(now);
+        if attempts.len() > config.max_handshakes_per_ip {
+            return Err(AdmissionError::RateLimited(ip));
+        }
+
+        if state.slots.iter().filter(|s| s.peer.addr.ip().to_canonical() == ip).count() >= config.max_per_ip {
+            return Err(AdmissionError::TooManyFromIp(ip));
+        }
+        if state.slots.iter().filter(|s| Subnet::of(s.peer.addr.ip()) == subnet).count() >= config.max_per_subnet {
+            return Err(AdmissionError::TooManyFromSubnet(subnet));
+        }
+        if state.slots.len() >= config.max_inbound {
+            let peers = state.slots.iter().map(|s| s.peer.clone()).collect::<Vec<_>>();
+            let evicted = self
+                .inner
+                .eviction
+                .select(&peers, addr)
+                .filter(|i| *i < state.slots.len())
+                .ok_or(AdmissionError::TooManyInbound(config.max_inbound))?;
+            if let Some(conn) = state.slots.remove(evicted).conn {
+                let _ = conn.shutdown(Shutdown::Both);
+            }
+        }
+
+        let id = state.next_id;
+        state.next_id = state.next_id.wrapping_add(1);
+        let peer = InboundPeer {
+            addr,
+            admitted_at: now,
+            handshake: None,
+        };
+        state.slots.push(Slot { id, peer, conn });
+        Ok(InboundPermit {
+            id,
+            inner: Arc::clone(&self.inner),
+        })
+    }
+}
+
+impl Inner {
+    // Slots are updated without intermediate states, so they are valid after a panic in another thread
+    fn lock(&self) -> MutexGuard<'_, AdmissionState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Inbound slot, which is freed on drop.
+#[derive(Debug)]
+pub struct InboundPermit {
+    id: u64,
+    inner: Arc<Inner>,
+}
+
+impl InboundPermit {
+    /// Makes peer's handshake visible to the eviction strategy.
+    pub fn set_handshake(&self, hs: &Handshake) {
+        if let Some(slot) = self.inner.lock().slots.iter_mut().find(|s| s.id == self.id) {
+            slot.peer.handshake = Some(hs.clone());
+        }
+    }
+
+    /// Whether the slot was given to another peer.
+    pub fn is_evicted(&self) -> bool {
+        !self.inner.lock().slots.iter().any(|s| s.id == self.id)
+    }
+}
+
+impl Drop for InboundPermit {
+    fn drop(&mut self) {
+        self.inner.lock().slots.retain(|s| s.id != self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, SocketAddrV4};
+
+    use super::*;
+
+    fn addr(c: u8, d: u8) -> SocketAddr {
+        SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(10, 0, c, d), 9030))
+    }
+
+    fn config() -> AdmissionConfig {
+        AdmissionConfig {
+            max_inbound: 4,
+            max_per_ip: 1,
+            max_per_subnet: 2,
+            max_handshakes_per_ip: 3,
+            rate_window: Duration::from_secs(60),
+        }
+    }
+
+    #[test]
+    fn test_per_ip_and_subnet_caps() {
+        let admission = InboundAdmission::new(config());
+        let now = Instant::now();
+        let _first = admission.admit_at(addr(0, 1), None, now).expect("internal error: not admitted");
+        let res = admission.admit_at(addr(0, 1), None, now);
+        assert_eq!(res.err(), Some(AdmissionError::TooManyFromIp(addr(0, 1).ip())));
+
+        let second = admission.admit_at(addr(0, 2), None, now).expect("internal error: not admitted");
+        let res = admission.admit_at(addr(0, 3), None, now);
+        assert_eq!(res.err(), Some(AdmissionError::TooManyFromSubnet(Subnet::of(addr(0, 3).ip()))));
+
+        // dropped permit frees the slot
+        drop(second);
+        assert!(admission.admit_at(addr(0, 3), None, now).is_ok());
+    }
+
+    #[test]
+    fn test_max_inbound() {
+        let admission = InboundAdmission::new(config());
+        let now = Instant::now();
+        let _permits = (0..4)
+            .map(|c| admission.admit_at(addr(c, 1), None, now).expect("internal error: not admitted"))
+            .collect::<Vec<_>>();
+        assert_eq!(admission.inbound_count(), 4);
+        let res = admission.admit_at(addr(4, 1), None, now);
+        assert_eq!(res.err(), Some(AdmissionError::TooManyInbound(4)));
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let admission = InboundAdmission::new(config());
+        let now = Instant::now();
+        for _ in 0..3 {
+            assert!(admission.admit_at(addr(0, 1), None, now).is_ok());
+        }
+        let res = admission.admit_at(addr(0, 1), None, now);
+        assert_eq!(res.err(), Some(AdmissionError::RateLimited(addr(0, 1).ip())));
+        assert!(admission.admit_at(addr(0, 1), None, now + Duration::from_secs(60)).is_ok());
+    }
+
+    #[test]
+    fn test_evict_from_crowded_subnet() {
+        let admission = InboundAdmission::with_eviction(config(), EvictFromCrowdedSubnet);
+        let now = Instant::now();
+        let crowded = (1..3)
+            .map(|d| admission.admit_at(addr(0, d), None, now + Duration::from_secs(d as u64)))
+            .collect::<Result<Vec<_>, _>>()
+            .expect("internal error: not admitted");
+        let _others = (1..3)
+            .map(|c| admission.admit_at(addr(c, 1), None, now))
+            .collect::<Result<Vec<_>, _>>()
+            .expect("internal error: not admitted");
+
+        // peer from the subnet with one connection isn't evicted in favor of the same one
+        let res = admission.admit_at(addr(1, 2), None, now);
+        assert_eq!(res.err(), Some(AdmissionError::TooManyInbound(4)));
+
+        let _new = admission.admit_at(addr(5, 1), None, now).expect("internal error: not admitted");
+        assert!(!crowded[0].is_evicted());
+        assert!(crowded[1].is_evicted());
+        assert_eq!(admission.inbound_count(), 4);
+    }
+}
@@ -0,0 +1,231 @@
+//! `proptest` strategies for the wire types.
+//!
+//! Generated values are canonical, i.e. they are equal to themselves after a serialize-parse round trip:
+//! no unrecognized features, socket addresses without IPv6 flow info and scope id,
+//! and handshake features are either `None` or not empty.
+
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+
+use proptest::collection::vec;
+use proptest::prelude::*;
+
+use crate::features::{Features, Mode, PeerFeature, SessionId};
+use crate::messages::Handshake;
+use crate::models::{MagicBytes, PeerAddr, ShortString, Version};
+
+impl Arbitrary for ShortString {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+        let any_chars = vec(any::<char>(), 0..=ShortString::MAX_SIZE).prop_map(|chars| {
+            let mut s = String::new();
+            for c in chars {
+                if s.len() + c.len_utf8() > ShortString::MAX_SIZE {
+                    break;
+                }
+                s.push(c)
+            }
+            s
+        });
+        // max size strings of 1, 3 and 4 bytes wide chars
+        let max_size = prop_oneof!["[a-z]{255}", "[\u{800}-\u{fff}]{85}", "[\u{10000}-\u{10fff}]{63}[a-z]{3}"];
+        prop_oneof![4 => any_chars, 1 => max_size]
+            .prop_filter_map("too long short string", |s| ShortString::from_str(&s).ok())
+            .boxed()
+    }
+}
+
+impl Arbitrary for Version {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+        any::<[u8; Version::SIZE]>().prop_map(Version).boxed()
+    }
+}
+
+impl Arbitrary for MagicBytes {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+        prop_oneof![Just(MagicBytes::MAINNET), any::<[u8; MagicBytes::SIZE]>().prop_map(MagicBytes)].boxed()
+    }
+}
+
+impl Arbitrary for PeerAddr {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+        let ip = prop_oneof![
+            any::<[u8; 4]>().prop_map(IpAddr::from),
+            any::<[u8; 16]>().prop_map(IpAddr::from),
+        ];
+        (ip, any::<u16>())
+            .prop_map(|(ip, port)| PeerAddr(SocketAddr::new(ip, port)))
+            .boxed()
+    }
+}
+
+impl Arbitrary for Mode {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+        (any::<u8>(), any::<bool>(), any::<Option<u32>>(), any::<i32>())
+            .prop_map(|(state_type, is_verifying, nipopow_suffix_len, blocks_to_keep)| Mode {
+                state_type,
+                is_verifying,
+                nipopow_suffix_len,
+                blocks_to_keep,
+            })
+            .boxed()
+    }
+}
+
+impl Arbitrary for SessionId {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+        (any::<MagicBytes>(), any::<i64>())
+            .prop_map(|(magic, session_id)| SessionId { magic, session_id })
+            .boxed()
+    }
+}
+
+// Unrecognized features can't be serialized, so they aren't generated
+impl Arbitrary for PeerFeature {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+        prop_oneof![
+            any::<Mode>().prop_map(PeerFeature::Mode),
+            any::<PeerAddr>().prop_map(PeerFeature::LocalAddr),
+            any::<SessionId>().prop_map(PeerFeature::SessionId),
+        ]
+        .boxed()
+    }
+}
+
+impl Arbitrary for Features {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+        features_strategy(0).boxed()
+    }
+}
+
+impl Arbitrary for Handshake {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+        (
+            any::<ShortString>(),
+            any::<Version>(),
+            any::<ShortString>(),
+            any::<Option<PeerAddr>>(),
+            proptest::option::of(features_strategy(1)),
+        )
+            .prop_map(|(agent_name, version, peer_name, pub_address, features)| Handshake {
+                agent_name,
+                version,
+                peer_name,
+                pub_address,
+                features,
+            })
+            .boxed()
+    }
+}
+
+// Mostly short feature lists, sometimes the max sized one
+fn features_strategy(min_len: usize) -> impl Strategy<Value = Features> {
+    prop_oneof![
+        4 => vec(any::<PeerFeature>(), min_len..=8),
+        1 => vec(any::<PeerFeature>(), Features::MAX_LEN),
+    ]
+    .prop_filter_map("too much features", |features| Features::try_new(features).ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use proptest::strategy::ValueTree;
+
+    use crate::encoding::vlq::{TryFromVlq, TryIntoVlq};
+
+    use super::*;
+
+    proptest! {
+        #[test]
+        fn test_short_string_round_trip(s in any::<ShortString>()) {
+            prop_assert!(s.len() <= ShortString::MAX_SIZE);
+            prop_assert_eq!(ShortString::try_from(s.as_bytes()).expect("internal error: can't parse short string"), s);
+        }
+
+        #[test]
+        fn test_version_round_trip(version in any::<Version>()) {
+            prop_assert_eq!(Version::from_str(&version.to_string()).expect("internal error: can't parse version"), version);
+        }
+
+        #[test]
+        fn test_peer_addr_round_trip(addr in any::<PeerAddr>()) {
+            let data = addr.try_into_vlq().expect("internal error: can't serialize peer addr");
+            prop_assert_eq!(PeerAddr::try_from_vlq(&data).expect("internal error: can't parse peer addr"), addr);
+        }
+
+        #[test]
+        fn test_mode_round_trip(mode in any::<Mode>()) {
+            let data = mode.try_into_vlq().expect("internal error: can't serialize mode");
+            prop_assert_eq!(Mode::try_from_vlq(&data).expect("internal error: can't parse mode"), mode);
+        }
+
+        #[test]
+        fn test_session_id_round_trip(session_id in any::<SessionId>()) {
+            let data = session_id.try_into_vlq().expect("internal error: can't serialize session id");
+            prop_assert_eq!(SessionId::try_from_vlq(&data).expect("internal error: can't parse session id"), session_id);
+        }
+
+        #[test]
+        fn test_features_round_trip(features in any::<Features>()) {
+            for feature in features.iter() {
+                let data = feature.try_into_vlq().expect("internal error: can't serialize feature");
+                let id = feature.get_id().expect("internal error: unrecognized feature");
+                let parsed = PeerFeature::try_from((id, data.as_slice())).expect("internal error: can't parse feature");
+                prop_assert_eq!(&parsed, feature);
+            }
+        }
+
+        #[test]
+        fn test_handshake_round_trip(hs in any::<Handshake>()) {
+            let data = hs.serialize().expect("internal error: can't serialize hs");
+            prop_assert_eq!(Handshake::parse(&data).expect("internal error: can't parse hs"), hs);
+        }
+    }
+
+    #[test]
+    fn test_max_sized_handshake_round_trip() {
+        let mut runner = proptest::test_runner::TestRunner::default();
+        let strategy = (any::<ShortString>(), vec(any::<PeerFeature>(), Features::MAX_LEN));
+        let name = "ы".repeat(127) + "a";
+        for _ in 0..16 {
+            let (agent_name, features) = strategy.new_tree(&mut runner).expect("internal error: can't generate values").current();
+            let hs = Handshake {
+                agent_name,
+                version: Version([255, 255, 255]),
+                peer_name: ShortString::from_str(&name).expect("internal error: invalid short string"),
+                pub_address: Some(PeerAddr(SocketAddr::from(([0xff; 16], u16::MAX)))),
+                features: Some(Features::try_new(features).expect("internal error: invalid features")),
+            };
+            let data = hs.serialize().expect("internal error: can't serialize hs");
+            assert_eq!(Handshake::parse(&data).expect("internal error: can't parse hs"), hs);
+        }
+    }
+}
@@ -0,0 +1,69 @@
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Write};
+use std::process;
+
+use clap::Parser;
+
+use ergo_handshake::capture::{CaptureConfig, CaptureDecoder, CaptureError, CaptureEvent, PacketReader};
+use ergo_handshake::models::MagicBytes;
+
+// Exit codes, one per failure kind
+const EXIT_INVALID_INPUT: i32 = 2;
+const EXIT_IO: i32 = 3;
+const EXIT_PARSE: i32 = 5;
+
+#[derive(Parser)]
+#[command(name = "ergo-capture", version, about = "Decodes Ergo P2P traffic of pcap and pcapng captures as JSON lines")]
+struct Cli {
+    /// Capture file, i.e. written by `tcpdump -w`
+    file: String,
+    /// Listening port of the captured nodes
+    #[arg(long, default_value_t = CaptureConfig::MAINNET_PORT)]
+    port: u16,
+    /// Network magic bytes in hex
+    #[arg(long, default_value = "01000204")]
+    magic: String,
+}
+
+fn main() {
+    let cli = Cli::parse();
+    if let Err((code, msg)) = run(cli) {
+        eprintln!("error: {}", msg);
+        process::exit(code);
+    }
+}
+
+type CliResult = Result<(), (i32, String)>;
+
+fn run(cli: Cli) -> CliResult {
+    let magic = cli.magic.parse::<MagicBytes>().map_err(|e| (EXIT_INVALID_INPUT, e.to_string()))?;
+    let file = File::open(&cli.file).map_err(|e| (EXIT_IO, format!("can't open {}: {}", cli.file, e)))?;
+    let packets = PacketReader::new(BufReader::new(file)).map_err(|e| (exit_code(&e), e.to_string()))?;
+
+    let mut decoder = CaptureDecoder::new(CaptureConfig { port: cli.port, magic });
+    let mut out = BufWriter::new(io::stdout().lock());
+    for packet in packets {
+        let packet = packet.map_err(|e| (exit_code(&e), e.to_string()))?;
+        print_events(&mut out, &decoder.push_packet(&packet))?;
+    }
+    print_events(&mut out, &decoder.finish())?;
+    out.flush().map_err(|e| (EXIT_IO, e.to_string()))
+}
+
+fn print_events(out: &mut impl Write, events: &[CaptureEvent]) -> CliResult {
+    for event in events {
+        let line = serde_json::to_string(event).map_err(|e| (EXIT_IO, format!("can't encode event as json: {}", e)))?;
+        writeln!(out, "{}", line).map_err(|e| (EXIT_IO, e.to_string()))?;
+    }
+    Ok(())
+}
+
+fn exit_code(err: &CaptureError) -> i32 {
+    match err {
+        CaptureError::FailedIoOp(_) => EXIT_IO,
+        CaptureError::UnknownFormat(_)
+        | CaptureError::InvalidBlock(_)
+        | CaptureError::TooLargePacket(_, _)
+        | CaptureError::UnknownInterface(_) => EXIT_PARSE,
+    }
+}
@@ -0,0 +1,197 @@
+use std::net::SocketAddr;
+use std::process;
+
+use clap::{Args, Parser, Subcommand};
+
+use ergo_handshake::features::{Mode, PeerFeature, SessionId};
+use ergo_handshake::messages::Handshake;
+use ergo_handshake::models::{MagicBytes, PeerAddr, Version};
+use ergo_handshake::{handshaking, HandshakingError};
+
+// Exit codes, one per failure kind
+const EXIT_INVALID_INPUT: i32 = 2;
+const EXIT_IO: i32 = 3;
+const EXIT_SERIALIZE: i32 = 4;
+const EXIT_PARSE: i32 = 5;
+const EXIT_REJECTED: i32 = 6;
+const EXIT_BANNED: i32 = 7;
+const EXIT_WRONG_NETWORK: i32 = 8;
+const EXIT_SELF_CONNECTION: i32 = 9;
+const EXIT_UNROUTABLE_ADDR: i32 = 10;
+const EXIT_PROXY: i32 = 11;
+
+#[derive(Parser)]
+#[command(name = "ergo-hs", version, about = "Probes Ergo nodes with P2P handshakes")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Handshakes with a node and prints its handshake
+    Probe {
+        /// Node address, i.e. 213.239.193.208:9030
+        addr: String,
+        #[arg(long)]
+        json: bool,
+        #[command(flatten)]
+        hs: HsArgs,
+    },
+    /// Parses hex encoded handshake bytes
+    Decode {
+        hex: String,
+        #[arg(long, conflicts_with = "annotate")]
+        json: bool,
+        /// Prints annotated hex dump of the handshake
+        #[arg(long)]
+        annotate: bool,
+    },
+    /// Builds handshake and prints its hex encoded bytes
+    Encode {
+        #[command(flatten)]
+        hs: HsArgs,
+    },
+}
+
+#[derive(Args)]
+struct HsArgs {
+    #[arg(long, default_value = "ergoref")]
+    agent_name: String,
+    /// Protocol version in "major.minor.patch" form
+    #[arg(long, default_value = "4.0.5")]
+    version: String,
+    #[arg(long, default_value = "ergo-hs")]
+    node_name: String,
+    #[arg(long)]
+    pub_address: Option<SocketAddr>,
+    /// Doesn't send mode feature
+    #[arg(long)]
+    no_mode: bool,
+    #[arg(long, default_value_t = 0)]
+    state_type: u8,
+    #[arg(long, default_value_t = true, action = clap::ArgAction::Set)]
+    verifying: bool,
+    #[arg(long)]
+    nipopow_suffix_len: Option<u32>,
+    #[arg(long, default_value_t = -1, allow_negative_numbers = true)]
+    blocks_to_keep: i32,
+    #[arg(long)]
+    local_address: Option<SocketAddr>,
+    #[arg(long, allow_negative_numbers = true)]
+    session_id: Option<i64>,
+    /// Network magic bytes in hex used in session id feature
+    #[arg(long, default_value = "01000204")]
+    magic: String,
+}
+
+fn main() {
+    let cli = Cli::parse();
+    let res = match cli.command {
+        Command::Probe { addr, json, hs } => probe(&addr, json, hs),
+        Command::Decode { hex, json, annotate } => decode(&hex, json, annotate),
+        Command::Encode { hs } => encode(hs),
+    };
+    if let Err((code, msg)) = res {
+        eprintln!("error: {}", msg);
+        process::exit(code);
+    }
+}
+
+type CliResult = Result<(), (i32, String)>;
+
+fn probe(addr: &str, json: bool, hs_args: HsArgs) -> CliResult {
+    let hs = hs_args.build()?;
+    let (_conn, peer_hs) = handshaking(addr, hs).map_err(|e| (exit_code(&e), e.to_string()))?;
+    print_hs(&peer_hs, json)
+}
+
+fn decode(hex_str: &str, json: bool, annotate: bool) -> CliResult {
+    let data = hex::decode(hex_str.trim()).map_err(|e| (EXIT_INVALID_INPUT, format!("invalid hex: {}", e)))?;
+    if annotate {
+        let annotation = Handshake::annotate(&data).map_err(|e| (EXIT_PARSE, e.to_string()))?;
+        print!("{}", annotation);
+        return Ok(());
+    }
+    let hs = Handshake::parse(&data).map_err(|e| (EXIT_PARSE, e.to_string()))?;
+    print_hs(&hs, json)
+}
+
+fn encode(hs_args: HsArgs) -> CliResult {
+    let hs = hs_args.build()?;
+    let data = hs.serialize().map_err(|e| (EXIT_SERIALIZE, e.to_string()))?;
+    println!("{}", hex::encode(data));
+    Ok(())
+}
+
+fn exit_code(err: &HandshakingError) -> i32 {
+    match err {
+        HandshakingError::FailedIoOp(_) => EXIT_IO,
+        HandshakingError::MessageSerializeError(_) => EXIT_SERIALIZE,
+        HandshakingError::MessageParseError(_) => EXIT_PARSE,
+        HandshakingError::Rejected(_) => EXIT_REJECTED,
+        HandshakingError::Banned(_) => EXIT_BANNED,
+        HandshakingError::WrongNetwork(_) => EXIT_WRONG_NETWORK,
+        HandshakingError::SelfConnection => EXIT_SELF_CONNECTION,
+        HandshakingError::UnroutableAddr(_) => EXIT_UNROUTABLE_ADDR,
+        HandshakingError::ProxyFailed(_) => EXIT_PROXY,
+    }
+}
+
+impl HsArgs {
+    fn build(self) -> Result<Handshake, (i32, String)> {
+        let invalid_input = |e: &dyn ToString| (EXIT_INVALID_INPUT, e.to_string());
+
+        let version = self.version.parse::<Version>().map_err(|e| invalid_input(&e))?;
+        let mut builder = Handshake::builder()
+            .agent_name(&self.agent_name)
+            .version(version)
+            .node_name(&self.node_name);
+        if let Some(addr) = self.pub_address {
+            builder = builder.declared_address(addr);
+        }
+        if !self.no_mode {
+            builder = builder.mode(Mode {
+                state_type: self.state_type,
+                is_verifying: self.verifying,
+                nipopow_suffix_len: self.nipopow_suffix_len,
+                blocks_to_keep: self.blocks_to_keep,
+            });
+        }
+        if let Some(addr) = self.local_address {
+            builder = builder.local_address(addr);
+        }
+        if let Some(session_id) = self.session_id {
+            let magic = self.magic.parse::<MagicBytes>().map_err(|e| invalid_input(&e))?;
+            builder = builder.session_id(SessionId { magic, session_id });
+        }
+        builder.build().map_err(|e| invalid_input(&e))
+    }
+}
+
+fn print_hs(hs: &Handshake, json: bool) -> CliResult {
+    if json {
+        let s = serde_json::to_string(hs).map_err(|e| (EXIT_SERIALIZE, format!("can't encode handshake as json: {}", e)))?;
+        println!("{}", s);
+        return Ok(());
+    }
+    println!("agent:          {}", hs.agent_name.as_str());
+    println!("version:        {}", hs.version);
+    println!("node name:      {}", hs.peer_name.as_str());
+    match hs.pub_address.as_ref() {
+        Some(PeerAddr(addr)) => println!("public address: {}", addr),
+        None => println!("public address: -"),
+    }
+    for feature in hs.features.iter().flat_map(|f| f.iter()) {
+        match feature {
+            PeerFeature::Mode(mode) => println!(
+                "mode:           state type {}, verifying {}, nipopow suffix len {:?}, blocks to keep {}",
+                mode.state_type, mode.is_verifying, mode.nipopow_suffix_len, mode.blocks_to_keep
+            ),
+            PeerFeature::LocalAddr(PeerAddr(addr)) => println!("local address:  {}", addr),
+            PeerFeature::SessionId(s) => println!("session id:     {} (magic {})", s.session_id, s.magic),
+            PeerFeature::Unrecognized => {}
+        }
+    }
+    Ok(())
+}
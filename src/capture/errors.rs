@@ -0,0 +1,17 @@
+use std::io;
+
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum CaptureError {
+    #[error("Failed IO operation: {0}")]
+    FailedIoOp(#[from] io::Error),
+    #[error("Unknown capture file magic {0:#010x}, expected pcap or pcapng")]
+    UnknownFormat(u32),
+    #[error("Invalid {0} block")]
+    InvalidBlock(&'static str),
+    #[error("Packet of {0} bytes exceeds maximum {1}")]
+    TooLargePacket(usize, usize),
+    #[error("Packet refers to unknown interface {0}")]
+    UnknownInterface(u32),
+}
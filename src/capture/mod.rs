@@ -0,0 +1,424 @@
+//! Offline decoding of P2P traffic captured by tcpdump: TCP streams on the Ergo port are rebuilt,
+//! the first frame of each direction is decoded as a handshake and the following ones as messages.
+
+use std::collections::{BTreeMap, HashMap};
+use std::io::Read;
+use std::net::SocketAddr;
+
+use bytes::BytesMut;
+use tokio_util::codec::Decoder;
+
+use crate::codec::{P2pCodec, P2pFrame};
+use crate::messages::Handshake;
+use crate::models::MagicBytes;
+
+use errors as capture_errors;
+pub use capture_errors::*;
+pub use pcap::{Packet, PacketReader};
+
+mod errors;
+mod packet;
+mod pcap;
+
+// Segments after a gap are dropped above the limit, so a lost segment doesn't hold the whole capture
+const MAX_PENDING_SEGMENTS: usize = 1024;
+
+/// Options of [`CaptureDecoder`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CaptureConfig {
+    // Listening port of the captured nodes, connections to it are decoded
+    pub port: u16,
+    pub magic: MagicBytes,
+}
+
+impl CaptureConfig {
+    pub const MAINNET_PORT: u16 = 9030;
+}
+
+impl Default for CaptureConfig {
+    fn default() -> Self {
+        CaptureConfig {
+            port: Self::MAINNET_PORT,
+            magic: MagicBytes::MAINNET,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize), serde(rename_all = "snake_case"))]
+pub enum Direction {
+    // From the dialing peer to the listening one
+    ClientToServer,
+    ServerToClient,
+}
+
+/// Decoded part of the captured stream.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct CaptureEvent {
+    // Capture time of the packet, which completed the frame, in seconds since the unix epoch
+    pub time: f64,
+    pub client: SocketAddr,
+    pub server: SocketAddr,
+    pub direction: Direction,
+    #[cfg_attr(feature = "serde", serde(flatten))]
+    pub frame: CapturedFrame,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize), serde(tag = "frame", rename_all = "snake_case"))]
+pub enum CapturedFrame {
+    Handshake { handshake: Handshake },
+    Message { code: u8, name: Option<&'static str>, body_len: usize },
+    // Direction isn't decoded after the error
+    Error { error: String },
+}
+
+/// Name of the reference node message with the code.
+pub fn message_name(code: u8) -> Option<&'static str> {
+    match code {
+        1 => Some("GetPeers"),
+        2 => Some("Peers"),
+        22 => Some("RequestModifier"),
+        33 => Some("Modifier"),
+        55 => Some("Inv"),
+        65 => Some("SyncInfo"),
+        90 => Some("GetNipopowProof"),
+        91 => Some("NipopowProof"),
+        _ => None,
+    }
+}
+
+/// Reads the whole pcap or pcapng capture and decodes its streams.
+pub fn decode_capture<R: Read>(reader: R, config: CaptureConfig) -> Result<Vec<CaptureEvent>, CaptureError> {
+    let mut decoder = CaptureDecoder::new(config);
+    let mut events = Vec::new();
+    for packet in PacketReader::new(reader)? {
+        events.extend(decoder.push_packet(&packet?));
+    }
+    events.extend(decoder.finish());
+    Ok(events)
+}
+
+/// Rebuilds TCP streams from captured packets and decodes their frames as soon as they're complete.
+#[derive(Debug)]
+pub struct CaptureDecoder {
+    config: CaptureConfig,
+    // Keyed by client and server addresses
+    streams: HashMap<(SocketAddr, SocketAddr), Stream>,
+}
+
+#[derive(Debug)]
+struct Stream {
+    to_server: HalfStream,
+    to_client: HalfStream,
+    last_time: f64,
+}
+
+#[derive(Debug)]
+struct HalfStream {
+    // Sequence number of the first payload byte
+    initial_seq: Option<u32>,
+    // Payload length, which is received without gaps
+    received: u64,
+    pending: BTreeMap<u64, Vec<u8>>,
+    buf: BytesMut,
+    codec: P2pCodec,
+    is_failed: bool,
+    // FIN or RST is seen
+    is_closed: bool,
+}
+
+impl CaptureDecoder {
+    pub fn new(config: CaptureConfig) -> Self {
+        CaptureDecoder {
+            config,
+            streams: HashMap::new(),
+        }
+    }
+
+    /// Events of frames completed by the packet. Packets of other ports and protocols are skipped.
+    pub fn push_packet(&mut self, packet: &Packet) -> Vec<CaptureEvent> {
+        let segment = match packet::parse_tcp_segment(packet.link_type, &packet.data) {
+            Some(segment) => segment,
+            None => return Vec::new(),
+        };
+        let (client, server, direction) = if segment.dst.port() == self.config.port {
+            (segment.src, segment.dst, Direction::ClientToServer)
+        } else if segment.src.port() == self.config.port {
+            (segment.dst, segment.src, Direction::ServerToClient)
+        } else {
+            return Vec::new();
+        };
+
+        let magic = &self.config.magic;
+        // SYN of the client starts a new connection on the reused addresses
+        if segment.is_syn && direction == Direction::ClientToServer {
+            self.streams.remove(&(client, server));
+        }
+        let stream = self.streams.entry((client, server)).or_insert_with(|| Stream::new(magic));
+        stream.last_time = packet.time;
+        let half = match direction {
+            Direction::ClientToServer => &mut stream.to_server,
+            Direction::ServerToClient => &mut stream.to_client,
+        };
+        let frames = half.push(segment.seq, segment.is_syn, segment.payload);
+        half.is_closed |= segment.is_closing;
+        let events = frames
+            .into_iter()
+            .map(|frame| CaptureEvent { time: packet.time, client, server, direction, frame })
+            .collect();
+        if stream.to_server.is_done() && stream.to_client.is_done() {
+            self.streams.remove(&(client, server));
+        }
+        events
+    }
+
+    /// Errors of streams, which ended in the middle of a frame or with missing segments.
+    pub fn finish(self) -> Vec<CaptureEvent> {
+        let mut events = Vec::new();
+        for ((client, server), stream) in self.streams {
+            let halves = [(Direction::ClientToServer, stream.to_server), (Direction::ServerToClient, stream.to_client)];
+            for (direction, half) in halves.iter() {
+                if let Some(error) = half.incomplete_error() {
+                    let frame = CapturedFrame::Error { error };
+                    events.push(CaptureEvent { time: stream.last_time, client, server, direction: *direction, frame });
+                }
+            }
+        }
+        events.sort_by(|a, b| a.time.total_cmp(&b.time));
+        events
+    }
+}
+
+impl Stream {
+    fn new(magic: &MagicBytes) -> Self {
+        Stream {
+            to_server: HalfStream::new(magic),
+            to_client: HalfStream::new(magic),
+            last_time: 0.0,
+        }
+    }
+}
+
+impl HalfStream {
+    fn new(magic: &MagicBytes) -> Self {
+        HalfStream {
+            initial_seq: None,
+            received: 0,
+            pending: BTreeMap::new(),
+            buf: BytesMut::new(),
+            codec: P2pCodec::new(magic.clone()),
+            is_failed: false,
+            is_closed: false,
+        }
+    }
+
+    // Without SYN in the capture the stream is decoded from the first seen segment
+    fn push(&mut self, seq: u32, is_syn: bool, payload: &[u8]) -> Vec<CapturedFrame> {
+        if is_syn {
+            self.initial_seq = Some(seq.wrapping_add(1));
+        }
+        if self.is_failed || payload.is_empty() {
+            return Vec::new();
+        }
+        let initial_seq = *self.initial_seq.get_or_insert(seq);
+        let offset = u64::from(seq.wrapping_sub(initial_seq));
+
+        if offset > self.received {
+            if self.pending.len() >= MAX_PENDING_SEGMENTS {
+                return vec![self.fail(format!("segment at offset {} is missing", self.received))];
+            }
+            self.pending.insert(offset, payload.to_vec());
+            return Vec::new();
+        }
+        self.append(offset, payload);
+        while let Some(entry) = self.pending.first_entry() {
+            if *entry.key() > self.received {
+                break;
+            }
+            let (offset, payload) = entry.remove_entry();
+            self.append(offset, &payload);
+        }
+        self.decode_frames()
+    }
+
+    // Retransmitted bytes are skipped
+    fn append(&mut self, offset: u64, payload: &[u8]) {
+        let end = offset + payload.len() as u64;
+        if end > self.received {
+            let new_bytes = &payload[(self.received - offset) as usize..];
+            self.buf.extend_from_slice(new_bytes);
+            self.received = end;
+        }
+    }
+
+    fn decode_frames(&mut self) -> Vec<CapturedFrame> {
+        let mut frames = Vec::new();
+        loop {
+            match self.codec.decode(&mut self.buf) {
+                Ok(Some(P2pFrame::Handshake(handshake))) => frames.push(CapturedFrame::Handshake { handshake }),
+                Ok(Some(P2pFrame::Message(msg))) => frames.push(CapturedFrame::Message {
+                    code: msg.code,
+                    name: message_name(msg.code),
+                    body_len: msg.body.len(),
+                }),
+                Ok(None) => return frames,
+                Err(e) => {
+                    frames.push(self.fail(e.to_string()));
+                    return frames;
+                }
+            }
+        }
+    }
+
+    fn fail(&mut self, error: String) -> CapturedFrame {
+        self.is_failed = true;
+        self.buf.clear();
+        self.pending.clear();
+        CapturedFrame::Error { error }
+    }
+
+    // Closed direction without undecoded bytes
+    fn is_done(&self) -> bool {
+        self.is_closed && (self.is_failed || (self.buf.is_empty() && self.pending.is_empty()))
+    }
+
+    fn incomplete_error(&self) -> Option<String> {
+        if self.is_failed {
+            return None;
+        }
+        match (self.buf.len(), self.pending.len()) {
+            (0, 0) => None,
+            (_, 0) => Some(format!("stream ended with {} undecoded bytes", self.buf.len())),
+            (_, n) => Some(format!("segment at offset {} is missing, {} segments after it are undecoded", self.received, n)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::BufMut;
+    use tokio_util::codec::Encoder;
+
+    use crate::codec::Message;
+
+    use super::packet::tests::tcp_frame;
+    use super::*;
+
+    // real app handshake with public address, mode and session id features
+    const HS_HEX: &str = "dee2aca3fb2e076572676f726566040005126572676f2d6d61696e6e65742d342e302e310108d5efc1d0c64602100400010001030e01000204eecc9582ffaaafeeaa01";
+
+    const SYN: u8 = 0x02;
+    const FIN: u8 = 0x01;
+
+    fn addr(s: &str) -> SocketAddr {
+        s.parse().expect("internal error: invalid socket addr")
+    }
+
+    fn hs_bytes() -> Vec<u8> {
+        hex::decode(HS_HEX).expect("internal error: invalid hex str")
+    }
+
+    fn msg_bytes(code: u8, body: &[u8]) -> Vec<u8> {
+        let mut buf = BytesMut::new();
+        P2pCodec::new(MagicBytes::MAINNET)
+            .encode(Message::new(code, body.to_vec()), &mut buf)
+            .expect("internal error: can't encode message");
+        buf.to_vec()
+    }
+
+    fn pcap(packets: &[Vec<u8>]) -> Vec<u8> {
+        let mut file = Vec::new();
+        for field in [0xa1b2_c3d4u32, 0x0004_0002, 0, 0, 65535, 1].iter() {
+            file.put_u32_le(*field);
+        }
+        for (i, data) in packets.iter().enumerate() {
+            file.put_u32_le(100 + i as u32);
+            file.put_u32_le(0);
+            file.put_u32_le(data.len() as u32);
+            file.put_u32_le(data.len() as u32);
+            file.extend_from_slice(data);
+        }
+        file
+    }
+
+    fn frames(events: &[CaptureEvent], direction: Direction) -> Vec<&CapturedFrame> {
+        events.iter().filter(|e| e.direction == direction).map(|e| &e.frame).collect()
+    }
+
+    #[test]
+    fn test_decode_reordered_and_retransmitted_segments() {
+        let (client, server) = (addr("10.0.0.1:50000"), addr("10.0.0.2:9030"));
+        let mut to_server = hs_bytes();
+        to_server.extend(msg_bytes(1, &[]));
+        to_server.extend(msg_bytes(2, &[1, 2, 3]));
+        let (first, rest) = to_server.split_at(30);
+        let second_seq = 1001 + first.len() as u32;
+        let to_client = hs_bytes();
+
+        let packets = vec![
+            tcp_frame(client, server, 1000, SYN, &[]),
+            tcp_frame(server, client, 5000, SYN, &[]),
+            // second part arrives first, then the first part is sent twice
+            tcp_frame(client, server, second_seq, 0, rest),
+            tcp_frame(client, server, 1001, 0, first),
+            tcp_frame(client, server, 1001, 0, first),
+            tcp_frame(server, client, 5001, 0, &to_client),
+            tcp_frame(addr("10.0.0.3:1"), addr("10.0.0.4:2"), 1, 0, &[1, 2, 3]),
+            tcp_frame(client, server, second_seq + rest.len() as u32, FIN, &[]),
+        ];
+        let events = decode_capture(&pcap(&packets)[..], CaptureConfig::default()).expect("internal error: can't decode capture");
+
+        let hs = Handshake::parse(&hs_bytes()).expect("internal error: can't parse hs");
+        let expected = [
+            CapturedFrame::Handshake { handshake: hs.clone() },
+            CapturedFrame::Message { code: 1, name: Some("GetPeers"), body_len: 0 },
+            CapturedFrame::Message { code: 2, name: Some("Peers"), body_len: 3 },
+        ];
+        assert_eq!(frames(&events, Direction::ClientToServer), expected.iter().collect::<Vec<_>>());
+        assert_eq!(frames(&events, Direction::ServerToClient), vec![&CapturedFrame::Handshake { handshake: hs }]);
+        assert!(events.iter().all(|e| e.client == client && e.server == server));
+        assert_eq!(events[0].time, 103.0);
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        let (client, server) = (addr("10.0.0.1:50000"), addr("10.0.0.2:9030"));
+        let mut to_server = hs_bytes();
+        let mut corrupted = msg_bytes(2, &[1, 2, 3]);
+        corrupted[0] = 2;
+        to_server.extend(corrupted);
+        let truncated = &hs_bytes()[..20];
+
+        let packets = vec![
+            tcp_frame(client, server, 1000, SYN, &[]),
+            tcp_frame(client, server, 1001, 0, &to_server),
+            tcp_frame(server, client, 7, 0, truncated),
+        ];
+        let events = decode_capture(&pcap(&packets)[..], CaptureConfig::default()).expect("internal error: can't decode capture");
+
+        let to_server = frames(&events, Direction::ClientToServer);
+        assert_eq!(to_server.len(), 2);
+        assert!(matches!(to_server[1], CapturedFrame::Error { error } if error.contains("magic")));
+        let to_client = frames(&events, Direction::ServerToClient);
+        assert_eq!(to_client, vec![&CapturedFrame::Error { error: "stream ended with 20 undecoded bytes".to_string() }]);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_json_event() {
+        let event = CaptureEvent {
+            time: 1.5,
+            client: addr("10.0.0.1:50000"),
+            server: addr("10.0.0.2:9030"),
+            direction: Direction::ClientToServer,
+            frame: CapturedFrame::Message { code: 1, name: message_name(1), body_len: 0 },
+        };
+        let json = serde_json::to_string(&event).expect("internal error: can't encode event");
+        assert_eq!(
+            json,
+            r#"{"time":1.5,"client":"10.0.0.1:50000","server":"10.0.0.2:9030","direction":"client_to_server","frame":"message","code":1,"name":"GetPeers","body_len":0}"#
+        );
+    }
+}
@@ -0,0 +1,140 @@
+use std::convert::TryFrom;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+// LINKTYPE_* values of the supported captures
+const LINKTYPE_NULL: u16 = 0;
+const LINKTYPE_ETHERNET: u16 = 1;
+const LINKTYPE_RAW: u16 = 101;
+const LINKTYPE_LINUX_SLL: u16 = 113;
+const LINKTYPE_IPV4: u16 = 228;
+const LINKTYPE_IPV6: u16 = 229;
+const LINKTYPE_LINUX_SLL2: u16 = 276;
+
+const ETHERTYPE_VLAN: u16 = 0x8100;
+const IP_PROTO_TCP: u8 = 6;
+
+const TCP_FIN: u8 = 0x01;
+const TCP_SYN: u8 = 0x02;
+const TCP_RST: u8 = 0x04;
+
+/// TCP segment of the captured packet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct TcpSegment<'a> {
+    pub src: SocketAddr,
+    pub dst: SocketAddr,
+    pub seq: u32,
+    pub is_syn: bool,
+    // FIN or RST, the direction won't have more data
+    pub is_closing: bool,
+    pub payload: &'a [u8],
+}
+
+/// Extracts the TCP segment, fragmented and non TCP packets are skipped.
+pub(super) fn parse_tcp_segment(link_type: u16, data: &[u8]) -> Option<TcpSegment<'_>> {
+    let ip_packet = match link_type {
+        LINKTYPE_ETHERNET => strip_ethernet(data)?,
+        // address family in the host byte order of the capturing machine
+        LINKTYPE_NULL => data.get(4..)?,
+        LINKTYPE_RAW | LINKTYPE_IPV4 | LINKTYPE_IPV6 => data,
+        LINKTYPE_LINUX_SLL => data.get(16..)?,
+        LINKTYPE_LINUX_SLL2 => data.get(20..)?,
+        _ => return None,
+    };
+    let (src_ip, dst_ip, tcp_packet) = match ip_packet.first()? >> 4 {
+        4 => parse_ipv4(ip_packet)?,
+        6 => parse_ipv6(ip_packet)?,
+        _ => return None,
+    };
+    parse_tcp(src_ip, dst_ip, tcp_packet)
+}
+
+// Version of IP header is checked instead of the ethertype
+fn strip_ethernet(data: &[u8]) -> Option<&[u8]> {
+    let ether_type = u16::from_be_bytes(<[u8; 2]>::try_from(data.get(12..14)?).ok()?);
+    match ether_type {
+        ETHERTYPE_VLAN => data.get(18..),
+        _ => data.get(14..),
+    }
+}
+
+fn parse_ipv4(packet: &[u8]) -> Option<(IpAddr, IpAddr, &[u8])> {
+    let header_len = usize::from(packet.first()? & 0x0f) * 4;
+    let total_len = usize::from(u16::from_be_bytes([*packet.get(2)?, *packet.get(3)?]));
+    let fragment = u16::from_be_bytes([*packet.get(6)?, *packet.get(7)?]);
+    // more fragments flag or fragment offset
+    if fragment & 0x3fff != 0 || *packet.get(9)? != IP_PROTO_TCP {
+        return None;
+    }
+    let src = Ipv4Addr::from(<[u8; 4]>::try_from(packet.get(12..16)?).ok()?);
+    let dst = Ipv4Addr::from(<[u8; 4]>::try_from(packet.get(16..20)?).ok()?);
+    // ethernet frames are padded up to the minimum size
+    let payload = packet.get(header_len..total_len.min(packet.len()))?;
+    Some((src.into(), dst.into(), payload))
+}
+
+// Extension headers aren't supported
+fn parse_ipv6(packet: &[u8]) -> Option<(IpAddr, IpAddr, &[u8])> {
+    if *packet.get(6)? != IP_PROTO_TCP {
+        return None;
+    }
+    let payload_len = usize::from(u16::from_be_bytes([*packet.get(4)?, *packet.get(5)?]));
+    let src = Ipv6Addr::from(<[u8; 16]>::try_from(packet.get(8..24)?).ok()?);
+    let dst = Ipv6Addr::from(<[u8; 16]>::try_from(packet.get(24..40)?).ok()?);
+    let payload = packet.get(40..(40 + payload_len).min(packet.len()))?;
+    Some((src.into(), dst.into(), payload))
+}
+
+fn parse_tcp(src_ip: IpAddr, dst_ip: IpAddr, packet: &[u8]) -> Option<TcpSegment<'_>> {
+    let src_port = u16::from_be_bytes([*packet.first()?, *packet.get(1)?]);
+    let dst_port = u16::from_be_bytes([*packet.get(2)?, *packet.get(3)?]);
+    let seq = u32::from_be_bytes(<[u8; 4]>::try_from(packet.get(4..8)?).ok()?);
+    let header_len = usize::from(packet.get(12)? >> 4) * 4;
+    let flags = *packet.get(13)?;
+    Some(TcpSegment {
+        src: SocketAddr::new(src_ip, src_port),
+        dst: SocketAddr::new(dst_ip, dst_port),
+        seq,
+        is_syn: flags & TCP_SYN != 0,
+        is_closing: flags & (TCP_FIN | TCP_RST) != 0,
+        payload: packet.get(header_len..)?,
+    })
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+
+    /// Ethernet frame with IPv4 TCP segment.
+    pub(in crate::capture) fn tcp_frame(src: SocketAddr, dst: SocketAddr, seq: u32, flags: u8, payload: &[u8]) -> Vec<u8> {
+        let ip = |addr: SocketAddr| match addr.ip() {
+            IpAddr::V4(ip) => ip.octets(),
+            IpAddr::V6(_) => [0; 4],
+        };
+        let mut frame = vec![0u8; 12];
+        frame.extend_from_slice(&[0x08, 0x00]);
+        frame.extend_from_slice(&[0x45, 0]);
+        frame.extend_from_slice(&((20 + 20 + payload.len()) as u16).to_be_bytes());
+        frame.extend_from_slice(&[0, 0, 0x40, 0, 64, IP_PROTO_TCP, 0, 0]);
+        frame.extend_from_slice(&ip(src));
+        frame.extend_from_slice(&ip(dst));
+        frame.extend_from_slice(&src.port().to_be_bytes());
+        frame.extend_from_slice(&dst.port().to_be_bytes());
+        frame.extend_from_slice(&seq.to_be_bytes());
+        frame.extend_from_slice(&[0, 0, 0, 0, 0x50, flags, 0xff, 0xff, 0, 0, 0, 0]);
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    #[test]
+    fn test_parse_ethernet_ipv4_tcp() {
+        let (src, dst) = ("10.0.0.1:50000".parse().expect("internal error: invalid socket addr"), "10.0.0.2:9030".parse().expect("internal error: invalid socket addr"));
+        let mut frame = tcp_frame(src, dst, 7, TCP_SYN, &[1, 2, 3]);
+        // ethernet padding isn't a part of the payload
+        frame.extend_from_slice(&[0; 6]);
+
+        let segment = parse_tcp_segment(LINKTYPE_ETHERNET, &frame).expect("internal error: can't parse segment");
+        assert_eq!(segment, TcpSegment { src, dst, seq: 7, is_syn: true, is_closing: false, payload: &[1, 2, 3] });
+        assert_eq!(parse_tcp_segment(LINKTYPE_RAW, &frame[14..]), Some(segment));
+        assert_eq!(parse_tcp_segment(LINKTYPE_ETHERNET, &frame[..30]), None);
+    }
+}
@@ -0,0 +1,314 @@
+use std::convert::TryFrom;
+use std::io::{ErrorKind, Read};
+
+use super::CaptureError;
+
+const PCAP_MICROS_MAGIC: u32 = 0xa1b2_c3d4;
+const PCAP_NANOS_MAGIC: u32 = 0xa1b2_3c4d;
+const PCAPNG_SECTION_HEADER: u32 = 0x0a0d_0d0a;
+const PCAPNG_BYTE_ORDER_MAGIC: u32 = 0x1a2b_3c4d;
+const PCAPNG_INTERFACE_DESCRIPTION: u32 = 1;
+const PCAPNG_SIMPLE_PACKET: u32 = 3;
+const PCAPNG_ENHANCED_PACKET: u32 = 6;
+const PCAPNG_OPTION_TSRESOL: u16 = 9;
+const MAX_BLOCK_SIZE: usize = 16 * 1024 * 1024;
+
+/// Packet read from a capture file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Packet {
+    // Seconds since the unix epoch
+    pub time: f64,
+    // LINKTYPE_* value of the interface, which captured the packet
+    pub link_type: u16,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone)]
+struct Interface {
+    link_type: u16,
+    units_per_sec: f64,
+}
+
+#[derive(Debug)]
+enum Format {
+    Pcap(Interface),
+    Pcapng(Vec<Interface>),
+}
+
+/// Reads packets of pcap and pcapng files. Format and byte order are detected by the file magic.
+#[derive(Debug)]
+pub struct PacketReader<R> {
+    reader: R,
+    format: Format,
+    is_big_endian: bool,
+}
+
+impl<R: Read> PacketReader<R> {
+    pub fn new(mut reader: R) -> Result<Self, CaptureError> {
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        match u32::from_le_bytes(magic) {
+            PCAPNG_SECTION_HEADER => {
+                let mut packet_reader = PacketReader { reader, format: Format::Pcapng(Vec::new()), is_big_endian: false };
+                packet_reader.read_section_header()?;
+                Ok(packet_reader)
+            }
+            magic_le => {
+                let (is_big_endian, units_per_sec) = match (magic_le, u32::from_be_bytes(magic)) {
+                    (PCAP_MICROS_MAGIC, _) => (false, 1e6),
+                    (PCAP_NANOS_MAGIC, _) => (false, 1e9),
+                    (_, PCAP_MICROS_MAGIC) => (true, 1e6),
+                    (_, PCAP_NANOS_MAGIC) => (true, 1e9),
+                    _ => return Err(CaptureError::UnknownFormat(u32::from_be_bytes(magic))),
+                };
+                // version, time zone, accuracy, snapshot length and link type
+                let mut header = [0u8; 20];
+                reader.read_exact(&mut header)?;
+                let link_type = u32_at(&header, 16, is_big_endian).unwrap_or_default() as u16;
+                let interface = Interface { link_type, units_per_sec };
+                Ok(PacketReader { reader, format: Format::Pcap(interface), is_big_endian })
+            }
+        }
+    }
+
+    /// Next packet or `None` at the end of the file.
+    pub fn next_packet(&mut self) -> Result<Option<Packet>, CaptureError> {
+        match self.format {
+            Format::Pcap(_) => self.next_pcap_packet(),
+            Format::Pcapng(_) => self.next_pcapng_packet(),
+        }
+    }
+
+    fn next_pcap_packet(&mut self) -> Result<Option<Packet>, CaptureError> {
+        let mut header = [0u8; 16];
+        if !read_exact_or_eof(&mut self.reader, &mut header)? {
+            return Ok(None);
+        }
+        let field = |offset| u32_at(&header, offset, self.is_big_endian).unwrap_or_default();
+        let (secs, frac, captured_len) = (field(0), field(4), field(8) as usize);
+        if captured_len > MAX_BLOCK_SIZE {
+            return Err(CaptureError::TooLargePacket(captured_len, MAX_BLOCK_SIZE));
+        }
+        let mut data = vec![0u8; captured_len];
+        self.reader.read_exact(&mut data)?;
+
+        let Format::Pcap(interface) = &self.format else {
+            return Ok(None);
+        };
+        let time = f64::from(secs) + f64::from(frac) / interface.units_per_sec;
+        Ok(Some(Packet { time, link_type: interface.link_type, data }))
+    }
+
+    fn next_pcapng_packet(&mut self) -> Result<Option<Packet>, CaptureError> {
+        loop {
+            let mut block_type = [0u8; 4];
+            if !read_exact_or_eof(&mut self.reader, &mut block_type)? {
+                return Ok(None);
+            }
+            let block_type = u32_at(&block_type, 0, self.is_big_endian).unwrap_or_default();
+            if block_type == PCAPNG_SECTION_HEADER {
+                self.read_section_header()?;
+                continue;
+            }
+            let body = self.read_block_body()?;
+            let Format::Pcapng(interfaces) = &mut self.format else {
+                return Ok(None);
+            };
+            match block_type {
+                PCAPNG_INTERFACE_DESCRIPTION => interfaces.push(parse_interface(&body, self.is_big_endian)?),
+                PCAPNG_ENHANCED_PACKET => return parse_enhanced_packet(&body, interfaces, self.is_big_endian).map(Some),
+                PCAPNG_SIMPLE_PACKET => return parse_simple_packet(&body, interfaces, self.is_big_endian).map(Some),
+                // statistics, name resolution and custom blocks
+                _ => {}
+            }
+        }
+    }
+
+    // New section has its own byte order and interfaces. Block type is already read
+    fn read_section_header(&mut self) -> Result<(), CaptureError> {
+        let mut header = [0u8; 8];
+        self.reader.read_exact(&mut header)?;
+        self.is_big_endian = match u32_at(&header, 4, false) {
+            Some(PCAPNG_BYTE_ORDER_MAGIC) => false,
+            Some(magic) if magic.swap_bytes() == PCAPNG_BYTE_ORDER_MAGIC => true,
+            _ => return Err(CaptureError::InvalidBlock("section header")),
+        };
+        let block_len = u32_at(&header, 0, self.is_big_endian).unwrap_or_default() as usize;
+        let rest_len = block_len.checked_sub(12).ok_or(CaptureError::InvalidBlock("section header"))?;
+        if rest_len > MAX_BLOCK_SIZE {
+            return Err(CaptureError::TooLargePacket(rest_len, MAX_BLOCK_SIZE));
+        }
+        let mut rest = vec![0u8; rest_len];
+        self.reader.read_exact(&mut rest)?;
+        self.format = Format::Pcapng(Vec::new());
+        Ok(())
+    }
+
+    // Block without type and both length fields
+    fn read_block_body(&mut self) -> Result<Vec<u8>, CaptureError> {
+        let mut len = [0u8; 4];
+        self.reader.read_exact(&mut len)?;
+        let block_len = u32_at(&len, 0, self.is_big_endian).unwrap_or_default() as usize;
+        let body_len = block_len.checked_sub(12).ok_or(CaptureError::InvalidBlock("pcapng"))?;
+        if body_len > MAX_BLOCK_SIZE {
+            return Err(CaptureError::TooLargePacket(body_len, MAX_BLOCK_SIZE));
+        }
+        let mut body = vec![0u8; body_len + 4];
+        self.reader.read_exact(&mut body)?;
+        body.truncate(body_len);
+        Ok(body)
+    }
+}
+
+impl<R: Read> Iterator for PacketReader<R> {
+    type Item = Result<Packet, CaptureError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_packet().transpose()
+    }
+}
+
+fn parse_interface(body: &[u8], is_big_endian: bool) -> Result<Interface, CaptureError> {
+    let link_type = u16_at(body, 0, is_big_endian).ok_or(CaptureError::InvalidBlock("interface description"))?;
+    let mut units_per_sec = 1e6;
+    let mut offset = 8;
+    while let (Some(code), Some(len)) = (u16_at(body, offset, is_big_endian), u16_at(body, offset + 2, is_big_endian)) {
+        let value = body.get(offset + 4..offset + 4 + len as usize).unwrap_or_default();
+        if let (PCAPNG_OPTION_TSRESOL, [resolution]) = (code, value) {
+            // the highest bit selects a power of 2 instead of a power of 10
+            let exp = i32::from(resolution & 0x7f);
+            units_per_sec = match resolution & 0x80 {
+                0 => 10f64.powi(exp),
+                _ => 2f64.powi(exp),
+            };
+        }
+        if code == 0 {
+            break;
+        }
+        // option values are padded to 32 bits
+        offset += 4 + (len as usize).div_ceil(4) * 4;
+    }
+    Ok(Interface { link_type, units_per_sec })
+}
+
+fn parse_enhanced_packet(body: &[u8], interfaces: &[Interface], is_big_endian: bool) -> Result<Packet, CaptureError> {
+    let field = |offset| u32_at(body, offset, is_big_endian).ok_or(CaptureError::InvalidBlock("enhanced packet"));
+    let interface_id = field(0)?;
+    let interface = usize::try_from(interface_id)
+        .ok()
+        .and_then(|id| interfaces.get(id))
+        .ok_or(CaptureError::UnknownInterface(interface_id))?;
+    let timestamp = (u64::from(field(4)?) << 32) | u64::from(field(8)?);
+    let captured_len = field(12)? as usize;
+    let data = body.get(20..20 + captured_len).ok_or(CaptureError::InvalidBlock("enhanced packet"))?;
+    Ok(Packet {
+        time: timestamp as f64 / interface.units_per_sec,
+        link_type: interface.link_type,
+        data: data.to_vec(),
+    })
+}
+
+// Simple packets have no timestamp and belong to the first interface
+fn parse_simple_packet(body: &[u8], interfaces: &[Interface], is_big_endian: bool) -> Result<Packet, CaptureError> {
+    let interface = interfaces.first().ok_or(CaptureError::UnknownInterface(0))?;
+    let original_len = u32_at(body, 0, is_big_endian).ok_or(CaptureError::InvalidBlock("simple packet"))? as usize;
+    let data = body.get(4..).unwrap_or_default();
+    Ok(Packet {
+        time: 0.0,
+        link_type: interface.link_type,
+        data: data[..original_len.min(data.len())].to_vec(),
+    })
+}
+
+// Returns `false`, if the reader is at the end
+fn read_exact_or_eof(reader: &mut impl Read, buf: &mut [u8]) -> Result<bool, CaptureError> {
+    match reader.read_exact(buf) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn u16_at(data: &[u8], offset: usize, is_big_endian: bool) -> Option<u16> {
+    let bytes = <[u8; 2]>::try_from(data.get(offset..offset + 2)?).ok()?;
+    Some(if is_big_endian { u16::from_be_bytes(bytes) } else { u16::from_le_bytes(bytes) })
+}
+
+pub(super) fn u32_at(data: &[u8], offset: usize, is_big_endian: bool) -> Option<u32> {
+    let bytes = <[u8; 4]>::try_from(data.get(offset..offset + 4)?).ok()?;
+    Some(if is_big_endian { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pcap_file(is_big_endian: bool, packets: &[(u32, u32, &[u8])]) -> Vec<u8> {
+        let put = |buf: &mut Vec<u8>, v: u32| buf.extend_from_slice(&if is_big_endian { v.to_be_bytes() } else { v.to_le_bytes() });
+        let mut file = Vec::new();
+        put(&mut file, PCAP_MICROS_MAGIC);
+        put(&mut file, 0x0004_0002);
+        put(&mut file, 0);
+        put(&mut file, 0);
+        put(&mut file, 65535);
+        put(&mut file, 1);
+        for (secs, micros, data) in packets {
+            put(&mut file, *secs);
+            put(&mut file, *micros);
+            put(&mut file, data.len() as u32);
+            put(&mut file, data.len() as u32);
+            file.extend_from_slice(data);
+        }
+        file
+    }
+
+    #[test]
+    fn test_pcap_both_byte_orders() {
+        for is_big_endian in [false, true].iter() {
+            let file = pcap_file(*is_big_endian, &[(10, 500_000, &[1, 2, 3]), (11, 0, &[4])]);
+            let packets = PacketReader::new(&file[..])
+                .expect("internal error: can't read pcap header")
+                .collect::<Result<Vec<_>, _>>()
+                .expect("internal error: can't read packets");
+            assert_eq!(packets.len(), 2);
+            assert_eq!(packets[0], Packet { time: 10.5, link_type: 1, data: vec![1, 2, 3] });
+            assert_eq!(packets[1].data, vec![4]);
+        }
+    }
+
+    #[test]
+    fn test_pcapng_blocks() {
+        let block = |block_type: u32, body: &[u8]| {
+            let len = (12 + body.len()) as u32;
+            let mut block = block_type.to_le_bytes().to_vec();
+            block.extend_from_slice(&len.to_le_bytes());
+            block.extend_from_slice(body);
+            block.extend_from_slice(&len.to_le_bytes());
+            block
+        };
+        let mut section = PCAPNG_BYTE_ORDER_MAGIC.to_le_bytes().to_vec();
+        section.extend_from_slice(&[1, 0, 0, 0, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]);
+        // ethernet interface with millisecond timestamps
+        let interface = [1, 0, 0, 0, 0, 0, 0, 0, 9, 0, 1, 0, 3, 0, 0, 0, 0, 0, 0, 0];
+        let mut packet = vec![0, 0, 0, 0, 0, 0, 0, 0];
+        packet.extend_from_slice(&1500u32.to_le_bytes());
+        packet.extend_from_slice(&[2, 0, 0, 0, 2, 0, 0, 0, 7, 8, 0, 0]);
+
+        let mut file = block(PCAPNG_SECTION_HEADER, &section);
+        file.extend(block(PCAPNG_INTERFACE_DESCRIPTION, &interface));
+        file.extend(block(5, &[0; 8]));
+        file.extend(block(PCAPNG_ENHANCED_PACKET, &packet));
+
+        let packets = PacketReader::new(&file[..])
+            .expect("internal error: can't read section header")
+            .collect::<Result<Vec<_>, _>>()
+            .expect("internal error: can't read packets");
+        assert_eq!(packets, vec![Packet { time: 1.5, link_type: 1, data: vec![7, 8] }]);
+    }
+
+    #[test]
+    fn test_unknown_format() {
+        let res = PacketReader::new(&[0u8, 1, 2, 3][..]);
+        assert!(matches!(res, Err(CaptureError::UnknownFormat(0x0001_0203))));
+    }
+}
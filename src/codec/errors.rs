@@ -0,0 +1,26 @@
+use std::io;
+
+use thiserror::Error;
+
+use crate::messages::{HsSpecReaderError, HsSpecWriterError};
+use crate::models::MagicBytes;
+
+#[derive(Error, Debug)]
+pub enum CodecError {
+    #[error("Failed IO operation: {0}")]
+    FailedIoOp(#[from] io::Error),
+    #[error("Failed handshake message parse: {0}")]
+    CannotParseHandshake(#[from] HsSpecReaderError),
+    #[error("Failed handshake message serialization: {0}")]
+    CannotSerializeHandshake(#[from] HsSpecWriterError),
+    #[error("Handshake isn't received in the first {0} bytes")]
+    TooLargeHandshake(usize),
+    #[error("Message has magic bytes {0}, expected {1}")]
+    InvalidMagicBytes(MagicBytes, MagicBytes),
+    #[error("Message body length {0} exceeds maximum {1}")]
+    TooLargeMessage(u64, usize),
+    #[error("Message body length {0} is negative")]
+    NegativeLength(i32),
+    #[error("Message with code {0} has invalid checksum")]
+    InvalidChecksum(u8),
+}
@@ -0,0 +1,27 @@
+use blake2::{Blake2b256, Digest};
+use bytes::Bytes;
+
+/// P2P message sent after handshake. Its framing is: magic bytes, message code, body length
+/// as 4 big-endian bytes and, if body isn't empty, body checksum followed by body.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub code: u8,
+    pub body: Bytes,
+}
+
+impl Message {
+    pub const HEADER_SIZE: usize = 4 + 1 + 4;
+    pub const CHECKSUM_SIZE: usize = 4;
+
+    pub fn new(code: u8, body: impl Into<Bytes>) -> Self {
+        Message { code, body: body.into() }
+    }
+
+    // First bytes of body's blake2b256 hash
+    pub(crate) fn checksum(body: &[u8]) -> [u8; Self::CHECKSUM_SIZE] {
+        let hash = Blake2b256::digest(body);
+        let mut checksum = [0; Self::CHECKSUM_SIZE];
+        checksum.copy_from_slice(&hash[..Self::CHECKSUM_SIZE]);
+        checksum
+    }
+}
@@ -0,0 +1,9 @@
+pub use codec_errors::*;
+pub use message::Message;
+pub use p2p::{P2pCodec, P2pFrame};
+
+use errors as codec_errors;
+
+mod errors;
+mod message;
+mod p2p;
@@ -0,0 +1,270 @@
+use std::convert::TryFrom;
+
+use bytes::{Buf, BufMut, BytesMut};
+use tokio_util::codec::{Decoder, Encoder};
+
+use crate::messages::Handshake;
+use crate::models::MagicBytes;
+
+use super::{CodecError, Message};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum P2pFrame {
+    Handshake(Handshake),
+    Message(Message),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Phase {
+    Handshake,
+    Messages,
+}
+
+/// Codec for a P2P connection: the first decoded frame is the peer's handshake, the following ones are messages.
+///
+/// Bytes received after the handshake are kept in the buffer and decoded as messages.
+#[derive(Debug)]
+pub struct P2pCodec {
+    magic: MagicBytes,
+    phase: Phase,
+    max_handshake_size: usize,
+    max_message_size: usize,
+}
+
+impl P2pCodec {
+    pub const DEFAULT_MAX_HANDSHAKE_SIZE: usize = 8 * 1024;
+    // Reference node default `maxPacketSize`
+    pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 1024 * 1024;
+
+    pub fn new(magic: MagicBytes) -> Self {
+        P2pCodec {
+            magic,
+            phase: Phase::Handshake,
+            max_handshake_size: Self::DEFAULT_MAX_HANDSHAKE_SIZE,
+            max_message_size: Self::DEFAULT_MAX_MESSAGE_SIZE,
+        }
+    }
+
+    pub fn with_max_handshake_size(mut self, size: usize) -> Self {
+        self.max_handshake_size = size;
+        self
+    }
+
+    pub fn with_max_message_size(mut self, size: usize) -> Self {
+        self.max_message_size = size;
+        self
+    }
+
+    pub fn is_handshaked(&self) -> bool {
+        self.phase == Phase::Messages
+    }
+
+    fn decode_handshake(&mut self, src: &mut BytesMut) -> Result<Option<Handshake>, CodecError> {
+        match Handshake::parse_strict(src) {
+            Ok((hs, len)) if len <= self.max_handshake_size => {
+                src.advance(len);
+                self.phase = Phase::Messages;
+                Ok(Some(hs))
+            }
+            Ok(_) => Err(CodecError::TooLargeHandshake(self.max_handshake_size)),
+            Err(e) if e.is_truncated() => {
+                if src.len() >= self.max_handshake_size {
+                    return Err(CodecError::TooLargeHandshake(self.max_handshake_size));
+                }
+                src.reserve(self.max_handshake_size - src.len());
+                Ok(None)
+            }
+            Err(e) => Err(CodecError::CannotParseHandshake(e)),
+        }
+    }
+
+    fn decode_message(&mut self, src: &mut BytesMut) -> Result<Option<Message>, CodecError> {
+        if src.len() < Message::HEADER_SIZE {
+            return Ok(None);
+        }
+        let magic = MagicBytes(<[u8; MagicBytes::SIZE]>::try_from(&src[..MagicBytes::SIZE]).unwrap_or_default());
+        if magic != self.magic {
+            return Err(CodecError::InvalidMagicBytes(magic, self.magic.clone()));
+        }
+        let code = src[MagicBytes::SIZE];
+        let body_len = {
+            let mut len_bytes = &src[MagicBytes::SIZE + 1..Message::HEADER_SIZE];
+            // reference node encodes length as a signed int
+            let len = len_bytes.get_i32();
+            let len = usize::try_from(len).map_err(|_| CodecError::NegativeLength(len))?;
+            if len > self.max_message_size {
+                return Err(CodecError::TooLargeMessage(len as u64, self.max_message_size));
+            }
+            len
+        };
+        let frame_len = if body_len == 0 {
+            Message::HEADER_SIZE
+        } else {
+            Message::HEADER_SIZE + Message::CHECKSUM_SIZE + body_len
+        };
+        if src.len() < frame_len {
+            src.reserve(frame_len - src.len());
+            return Ok(None);
+        }
+
+        let mut frame = src.split_to(frame_len);
+        frame.advance(Message::HEADER_SIZE);
+        if body_len == 0 {
+            return Ok(Some(Message::new(code, frame.freeze())));
+        }
+        let checksum = frame.split_to(Message::CHECKSUM_SIZE);
+        if checksum[..] != Message::checksum(&frame)[..] {
+            return Err(CodecError::InvalidChecksum(code));
+        }
+        Ok(Some(Message::new(code, frame.freeze())))
+    }
+}
+
+impl Decoder for P2pCodec {
+    type Item = P2pFrame;
+    type Error = CodecError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        match self.phase {
+            Phase::Handshake => self.decode_handshake(src).map(|hs| hs.map(P2pFrame::Handshake)),
+            Phase::Messages => self.decode_message(src).map(|msg| msg.map(P2pFrame::Message)),
+        }
+    }
+}
+
+impl Encoder<Handshake> for P2pCodec {
+    type Error = CodecError;
+
+    fn encode(&mut self, hs: Handshake, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        hs.encode(dst).map_err(CodecError::CannotSerializeHandshake)
+    }
+}
+
+impl Encoder<Message> for P2pCodec {
+    type Error = CodecError;
+
+    fn encode(&mut self, msg: Message, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        let body_len = msg.body.len();
+        if body_len > self.max_message_size || body_len > i32::MAX as usize {
+            return Err(CodecError::TooLargeMessage(body_len as u64, self.max_message_size));
+        }
+        dst.reserve(Message::HEADER_SIZE + Message::CHECKSUM_SIZE + body_len);
+        dst.put_slice(&self.magic.0);
+        dst.put_u8(msg.code);
+        dst.put_i32(body_len as i32);
+        if body_len > 0 {
+            dst.put_slice(&Message::checksum(&msg.body));
+            dst.put_slice(&msg.body);
+        }
+        Ok(())
+    }
+}
+
+impl Encoder<P2pFrame> for P2pCodec {
+    type Error = CodecError;
+
+    fn encode(&mut self, frame: P2pFrame, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        match frame {
+            P2pFrame::Handshake(hs) => self.encode(hs, dst),
+            P2pFrame::Message(msg) => self.encode(msg, dst),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // real app handshake with public address, mode and session id features
+    const HS_HEX: &str = "dee2aca3fb2e076572676f726566040005126572676f2d6d61696e6e65742d342e302e310108d5efc1d0c64602100400010001030e01000204eecc9582ffaaafeeaa01";
+
+    fn hs_bytes() -> Vec<u8> {
+        hex::decode(HS_HEX).expect("internal error: invalid hex str")
+    }
+
+    fn encode_msg(msg: Message) -> BytesMut {
+        let mut buf = BytesMut::new();
+        P2pCodec::new(MagicBytes::MAINNET).encode(msg, &mut buf).expect("internal error: can't encode message");
+        buf
+    }
+
+    fn decode_all(codec: &mut P2pCodec, buf: &mut BytesMut) -> Vec<P2pFrame> {
+        let mut frames = Vec::new();
+        while let Some(frame) = codec.decode(buf).expect("internal error: can't decode frame") {
+            frames.push(frame);
+        }
+        frames
+    }
+
+    #[test]
+    fn test_handshake_and_messages_in_one_buffer() {
+        let hs = Handshake::parse(&hs_bytes()).expect("internal error: can't parse hs");
+        let get_peers = Message::new(1, Vec::new());
+        let peers = Message::new(2, vec![1, 2, 3, 4, 5]);
+
+        let mut buf = BytesMut::from(&hs_bytes()[..]);
+        buf.extend_from_slice(&encode_msg(get_peers.clone()));
+        buf.extend_from_slice(&encode_msg(peers.clone()));
+
+        let mut codec = P2pCodec::new(MagicBytes::MAINNET);
+        let frames = decode_all(&mut codec, &mut buf);
+        assert_eq!(frames, vec![P2pFrame::Handshake(hs), P2pFrame::Message(get_peers), P2pFrame::Message(peers)]);
+        assert!(codec.is_handshaked());
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn test_byte_by_byte_feeding() {
+        let msg = Message::new(55, vec![7; 100]);
+        let mut data = hs_bytes();
+        data.extend_from_slice(&encode_msg(msg.clone()));
+
+        let mut codec = P2pCodec::new(MagicBytes::MAINNET);
+        let mut buf = BytesMut::new();
+        let mut frames = Vec::new();
+        for b in data {
+            buf.put_u8(b);
+            frames.extend(decode_all(&mut codec, &mut buf));
+        }
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[1], P2pFrame::Message(msg));
+    }
+
+    #[test]
+    fn test_empty_body_frame() {
+        let buf = encode_msg(Message::new(1, Vec::new()));
+        assert_eq!(&buf[..], &[1, 0, 2, 4, 1, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_handshake_size_limit() {
+        let mut codec = P2pCodec::new(MagicBytes::MAINNET).with_max_handshake_size(20);
+        let mut buf = BytesMut::from(&hs_bytes()[..19]);
+        assert!(matches!(codec.decode(&mut buf), Ok(None)));
+        buf.extend_from_slice(&hs_bytes()[19..]);
+        assert!(matches!(codec.decode(&mut buf), Err(CodecError::TooLargeHandshake(20))));
+    }
+
+    #[test]
+    fn test_message_errors() {
+        let mut codec = P2pCodec::new(MagicBytes::MAINNET).with_max_message_size(10);
+        let mut buf = BytesMut::from(&hs_bytes()[..]);
+        assert!(matches!(codec.decode(&mut buf), Ok(Some(P2pFrame::Handshake(_)))));
+
+        let mut wrong_magic = encode_msg(Message::new(1, Vec::new()));
+        wrong_magic[0] = 2;
+        assert!(matches!(codec.decode(&mut wrong_magic), Err(CodecError::InvalidMagicBytes(_, _))));
+
+        let mut too_large = encode_msg(Message::new(2, vec![0; 11]));
+        assert!(matches!(codec.decode(&mut too_large), Err(CodecError::TooLargeMessage(11, 10))));
+
+        let mut negative = encode_msg(Message::new(2, Vec::new()));
+        negative[MagicBytes::SIZE + 1..Message::HEADER_SIZE].copy_from_slice(&(-1i32).to_be_bytes());
+        assert!(matches!(codec.decode(&mut negative), Err(CodecError::NegativeLength(-1))));
+
+        let mut corrupted = encode_msg(Message::new(2, vec![0; 10]));
+        let last = corrupted.len() - 1;
+        corrupted[last] = 1;
+        assert!(matches!(codec.decode(&mut corrupted), Err(CodecError::InvalidChecksum(2))));
+    }
+}
@@ -0,0 +1,323 @@
+use std::collections::{HashMap, HashSet};
+use std::net::{Shutdown, SocketAddr, TcpStream};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::messages::Handshake;
+use crate::utils::random_u64;
+use crate::{handshaking_with, HandshakeConfig, HandshakingError};
+
+pub use peer_source::PeerSource;
+
+mod peer_source;
+
+#[derive(Debug, Clone)]
+pub struct ConnectionManagerConfig {
+    /// Count of handshaked outbound connections to keep.
+    pub target_outbound: usize,
+    pub max_concurrent_dials: usize,
+    /// Delay before redialing a failed address, doubled on each next failure.
+    pub base_backoff: Duration,
+    pub max_backoff: Duration,
+    /// How often free slots are filled, if nothing else happens.
+    pub refill_interval: Duration,
+    pub handshake: HandshakeConfig,
+}
+
+impl Default for ConnectionManagerConfig {
+    fn default() -> Self {
+        ConnectionManagerConfig {
+            target_outbound: 8,
+            max_concurrent_dials: 3,
+            base_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(10 * 60),
+            refill_interval: Duration::from_secs(1),
+            handshake: HandshakeConfig::default(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConnectionEvent {
+    /// Connection is owned by the receiver, the manager keeps only a handle to shut it down.
+    Connected { addr: SocketAddr, handshake: Handshake, conn: TcpStream },
+    Disconnected { addr: SocketAddr, handshake: Handshake },
+    DialFailed { addr: SocketAddr, error: HandshakingError, retry_in: Duration },
+}
+
+/// Keeps a target number of handshaked outbound connections to peers from the [`PeerSource`].
+///
+/// Dials are made on separate threads. Failed addresses are redialed with exponential backoff with jitter.
+/// Connections aren't monitored, so the owner reports closed ones with [`ConnectionManager::disconnect`].
+pub struct ConnectionManager {
+    commands: Sender<Command>,
+    connected: Arc<Mutex<HashMap<SocketAddr, Connection>>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+struct Connection {
+    handshake: Handshake,
+    // Clone of the stream, which is sent in the event
+    conn: Option<TcpStream>,
+}
+
+enum Command {
+    Dialed(SocketAddr, Result<(TcpStream, Handshake), HandshakingError>),
+    Disconnect(SocketAddr),
+    Stop,
+}
+
+impl ConnectionManager {
+    pub fn start<S: PeerSource + 'static>(hs: Handshake, source: S, config: ConnectionManagerConfig) -> (Self, Receiver<ConnectionEvent>) {
+        let (commands, commands_rx) = mpsc::channel();
+        let (events, events_rx) = mpsc::channel();
+        let connected = Arc::new(Mutex::new(HashMap::new()));
+
+        let worker = Worker {
+            hs: Arc::new(hs),
+            source,
+            config,
+            commands: commands.clone(),
+            events,
+            connected: Arc::clone(&connected),
+            dialing: HashSet::new(),
+            backoffs: HashMap::new(),
+        };
+        let handle = thread::spawn(move || worker.run(commands_rx));
+
+        let manager = ConnectionManager {
+            commands,
+            connected,
+            handle: Some(handle),
+        };
+        (manager, events_rx)
+    }
+
+    /// Shuts the connection down and frees its slot for another peer.
+    pub fn disconnect(&self, addr: SocketAddr) {
+        let _ = self.commands.send(Command::Disconnect(addr));
+    }
+
+    pub fn connected(&self) -> Vec<(SocketAddr, Handshake)> {
+        lock(&self.connected)
+            .iter()
+            .map(|(addr, c)| (*addr, c.handshake.clone()))
+            .collect()
+    }
+}
+
+// Connections stay open, because they are owned by the events receiver
+impl Drop for ConnectionManager {
+    fn drop(&mut self) {
+        let _ = self.commands.send(Command::Stop);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+struct Worker<S> {
+    hs: Arc<Handshake>,
+    source: S,
+    config: ConnectionManagerConfig,
+    commands: Sender<Command>,
+    events: Sender<ConnectionEvent>,
+    connected: Arc<Mutex<HashMap<SocketAddr, Connection>>>,
+    dialing: HashSet<SocketAddr>,
+    backoffs: HashMap<SocketAddr, Backoff>,
+}
+
+struct Backoff {
+    failures: u32,
+    until: Instant,
+}
+
+impl<S: PeerSource> Worker<S> {
+    fn run(mut self, commands: Receiver<Command>) {
+        loop {
+            self.refill();
+            match commands.recv_timeout(self.config.refill_interval) {
+                Ok(Command::Dialed(addr, res)) => self.on_dialed(addr, res),
+                Ok(Command::Disconnect(addr)) => self.on_disconnect(addr),
+                Ok(Command::Stop) | Err(RecvTimeoutError::Disconnected) => break,
+                Err(RecvTimeoutError::Timeout) => {}
+            }
+        }
+    }
+
+    fn refill(&mut self) {
+        let connected_count = lock(&self.connected).len();
+        let free = self.config.target_outbound.saturating_sub(connected_count + self.dialing.len());
+        let dials = free.min(self.config.max_concurrent_dials.saturating_sub(self.dialing.len()));
+        if dials == 0 {
+            return;
+        }
+
+        let now = Instant::now();
+        let max_dialing = self.dialing.len() + dials;
+        let busy = lock(&self.connected).keys().chain(&self.dialing).copied().collect::<Vec<_>>();
+        for addr in self.source.candidates(&busy) {
+            if self.dialing.len() >= max_dialing {
+                break;
+            }
+            let is_backed_off = self.backoffs.get(&addr).is_some_and(|b| b.until > now);
+            if is_backed_off || self.dialing.contains(&addr) || lock(&self.connected).contains_key(&addr) {
+                continue;
+            }
+            self.dial(addr);
+        }
+    }
+
+    fn dial(&mut self, addr: SocketAddr) {
+        self.dialing.insert(addr);
+        let hs = Handshake::clone(&self.hs);
+        let config = self.config.handshake.clone();
+        let commands = self.commands.clone();
+        thread::spawn(move || {
+            let res = handshaking_with(addr, hs, &config);
+            let _ = commands.send(Command::Dialed(addr, res));
+        });
+    }
+
+    fn on_dialed(&mut self, addr: SocketAddr, res: Result<(TcpStream, Handshake), HandshakingError>) {
+        self.dialing.remove(&addr);
+        match res {
+            Ok((conn, handshake)) => {
+                self.backoffs.remove(&addr);
+                self.source.on_connected(addr, &handshake);
+                let connection = Connection {
+                    handshake: handshake.clone(),
+                    conn: conn.try_clone().ok(),
+                };
+                lock(&self.connected).insert(addr, connection);
+                let _ = self.events.send(ConnectionEvent::Connected { addr, handshake, conn });
+            }
+            Err(error) => {
+                self.source.on_failed(addr, &error);
+                let retry_in = self.back_off(addr);
+                let _ = self.events.send(ConnectionEvent::DialFailed { addr, error, retry_in });
+            }
+        }
+    }
+
+    fn on_disconnect(&mut self, addr: SocketAddr) {
+        let connection = lock(&self.connected).remove(&addr);
+        if let Some(Connection { handshake, conn }) = connection {
+            if let Some(conn) = conn {
+                let _ = conn.shutdown(Shutdown::Both);
+            }
+            // the peer isn't redialed at once, so the slot is given to another one
+            self.back_off(addr);
+            let _ = self.events.send(ConnectionEvent::Disconnected { addr, handshake });
+        }
+    }
+
+    fn back_off(&mut self, addr: SocketAddr) -> Duration {
+        let failures = self.backoffs.get(&addr).map_or(0, |b| b.failures);
+        let delay = backoff_delay(self.config.base_backoff, self.config.max_backoff, failures);
+        let backoff = Backoff {
+            failures: failures.saturating_add(1),
+            until: Instant::now() + delay,
+        };
+        self.backoffs.insert(addr, backoff);
+        delay
+    }
+}
+
+// Full delay is `base * 2^failures` capped by `max`, a random part of its second half is cut off
+fn backoff_delay(base: Duration, max: Duration, failures: u32) -> Duration {
+    let factor = 1u32.checked_shl(failures).unwrap_or(u32::MAX);
+    let delay = base.checked_mul(factor).map_or(max, |d| d.min(max));
+    let jitter = random_u64() as f64 / u64::MAX as f64;
+    delay.mul_f64(1.0 - jitter / 2.0)
+}
+
+fn lock(connected: &Mutex<HashMap<SocketAddr, Connection>>) -> MutexGuard<'_, HashMap<SocketAddr, Connection>> {
+    connected.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{create_hs, MockBehavior, MockPeer};
+
+    use super::*;
+
+    const EVENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+    fn spawn_peers(behaviors: Vec<MockBehavior>) -> Vec<MockPeer> {
+        behaviors
+            .into_iter()
+            .enumerate()
+            .map(|(i, b)| MockPeer::spawn(create_hs("mock-node", i as i64 + 1), b).expect("internal error: can't spawn mock peer"))
+            .collect()
+    }
+
+    fn test_config(target_outbound: usize) -> ConnectionManagerConfig {
+        ConnectionManagerConfig {
+            target_outbound,
+            base_backoff: Duration::from_millis(100),
+            refill_interval: Duration::from_millis(20),
+            ..ConnectionManagerConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_keeps_target_outbound() {
+        let peers = spawn_peers(vec![MockBehavior::Honest; 3]);
+        let addrs = peers.iter().map(|p| p.addr()).collect::<Vec<_>>();
+        let (manager, events) = ConnectionManager::start(create_hs("client", 0), addrs.clone(), test_config(2));
+
+        let mut conns = Vec::new();
+        for _ in 0..2 {
+            match events.recv_timeout(EVENT_TIMEOUT).expect("internal error: no event") {
+                ConnectionEvent::Connected { addr, handshake, conn } => {
+                    assert_eq!(handshake.peer_name.as_str(), "mock-node");
+                    conns.push((addr, conn));
+                }
+                e => panic!("unexpected event {:?}", e),
+            }
+        }
+        assert!(events.recv_timeout(Duration::from_millis(200)).is_err());
+        assert_eq!(manager.connected().len(), 2);
+
+        // freed slot is given to the third peer
+        let (disconnected, _conn) = conns.remove(0);
+        manager.disconnect(disconnected);
+        assert!(matches!(
+            events.recv_timeout(EVENT_TIMEOUT),
+            Ok(ConnectionEvent::Disconnected { addr, .. }) if addr == disconnected
+        ));
+        match events.recv_timeout(EVENT_TIMEOUT).expect("internal error: no event") {
+            ConnectionEvent::Connected { addr, .. } => assert!(addr != disconnected && addr != conns[0].0),
+            e => panic!("unexpected event {:?}", e),
+        }
+    }
+
+    #[test]
+    fn test_failed_dials_back_off() {
+        let peers = spawn_peers(vec![MockBehavior::CloseEarly]);
+        let (_manager, events) = ConnectionManager::start(create_hs("client", 0), vec![peers[0].addr()], test_config(1));
+
+        let mut retries = Vec::new();
+        for _ in 0..3 {
+            match events.recv_timeout(EVENT_TIMEOUT).expect("internal error: no event") {
+                ConnectionEvent::DialFailed { retry_in, .. } => retries.push(retry_in),
+                e => panic!("unexpected event {:?}", e),
+            }
+        }
+        assert!(retries[0] >= Duration::from_millis(50) && retries[0] <= Duration::from_millis(100));
+        assert!(retries[2] >= Duration::from_millis(200) && retries[2] <= Duration::from_millis(400));
+    }
+
+    #[test]
+    fn test_backoff_delay() {
+        let (base, max) = (Duration::from_secs(1), Duration::from_secs(60));
+        for failures in 0..40 {
+            let full = Duration::from_secs(1u64.checked_shl(failures).unwrap_or(u64::MAX).min(60));
+            let delay = backoff_delay(base, max, failures);
+            assert!(delay <= full && delay >= full / 2, "{:?} isn't in the second half of {:?}", delay, full);
+        }
+    }
+}
@@ -0,0 +1,97 @@
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, PoisonError};
+
+use crate::addr_manager::AddrManager;
+use crate::messages::Handshake;
+use crate::models::{NetGroup, PeerAddr};
+use crate::peer_book::PeerBook;
+use crate::HandshakingError;
+
+/// Addresses the [`ConnectionManager`](super::ConnectionManager) picks peers from.
+pub trait PeerSource: Send {
+    /// Addresses in the order of preference. Connected, dialed and backed off ones are skipped by the manager.
+    ///
+    /// `busy` are the addresses the manager is connected to or dialing.
+    fn candidates(&mut self, busy: &[SocketAddr]) -> Vec<SocketAddr>;
+
+    fn on_connected(&mut self, _addr: SocketAddr, _hs: &Handshake) {}
+
+    fn on_failed(&mut self, _addr: SocketAddr, _err: &HandshakingError) {}
+}
+
+/// Fixed list of addresses, i.e. seed nodes.
+impl PeerSource for Vec<SocketAddr> {
+    fn candidates(&mut self, _busy: &[SocketAddr]) -> Vec<SocketAddr> {
+        self.clone()
+    }
+}
+
+/// Prefers peers with fewer failures, then recently seen ones. Handshakes and failures are recorded to the book.
+impl PeerSource for Arc<Mutex<PeerBook>> {
+    fn candidates(&mut self, _busy: &[SocketAddr]) -> Vec<SocketAddr> {
+        let book = self.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut records = book.iter().collect::<Vec<_>>();
+        records.sort_by_key(|r| (r.failures, std::cmp::Reverse(r.last_seen)));
+        records.into_iter().map(|r| r.addr.0).collect()
+    }
+
+    fn on_connected(&mut self, addr: SocketAddr, hs: &Handshake) {
+        let mut book = self.lock().unwrap_or_else(PoisonError::into_inner);
+        book.record_handshake(PeerAddr(addr), hs);
+    }
+
+    fn on_failed(&mut self, addr: SocketAddr, _err: &HandshakingError) {
+        let mut book = self.lock().unwrap_or_else(PoisonError::into_inner);
+        book.record_failure(&PeerAddr(addr));
+    }
+}
+
+/// One address per netgroup, skipping the netgroups of busy peers. Connected peers' addresses are tried,
+/// ones they declare are learned.
+impl PeerSource for Arc<Mutex<AddrManager>> {
+    fn candidates(&mut self, busy: &[SocketAddr]) -> Vec<SocketAddr> {
+        let excluded = busy.iter().map(|addr| NetGroup::of(addr.ip())).collect::<HashSet<_>>();
+        let mut manager = self.lock().unwrap_or_else(PoisonError::into_inner);
+        manager.select_excluding(usize::MAX, &excluded).into_iter().map(|addr| addr.0).collect()
+    }
+
+    fn on_connected(&mut self, addr: SocketAddr, hs: &Handshake) {
+        let mut manager = self.lock().unwrap_or_else(PoisonError::into_inner);
+        manager.mark_good(&PeerAddr(addr));
+        manager.add_handshake(hs, addr.ip());
+    }
+
+    fn on_failed(&mut self, addr: SocketAddr, _err: &HandshakingError) {
+        let mut manager = self.lock().unwrap_or_else(PoisonError::into_inner);
+        manager.mark_failed(&PeerAddr(addr));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use crate::addr_manager::AddrManagerConfig;
+
+    use super::*;
+
+    #[test]
+    fn test_candidates_skip_busy_netgroups() {
+        let mut manager = AddrManager::new(AddrManagerConfig { key: [1, 2], ..AddrManagerConfig::default() });
+        for a in 1..=3 {
+            for c in 0..4 {
+                manager.add(PeerAddr(SocketAddr::from(([a, 0, c, 1], 9030))), IpAddr::V4(Ipv4Addr::new(a + 100, 0, 0, 1)));
+            }
+        }
+        let mut source = Arc::new(Mutex::new(manager));
+
+        let busy = vec![SocketAddr::from(([1, 0, 0, 1], 9030)), SocketAddr::from(([2, 0, 9, 9], 9030))];
+        for _ in 0..10 {
+            let candidates = source.candidates(&busy);
+            assert_eq!(candidates.len(), 1);
+            assert_eq!(NetGroup::of(candidates[0].ip()), NetGroup::Ipv4([3, 0]));
+        }
+        assert_eq!(source.candidates(&[]).len(), 3);
+    }
+}
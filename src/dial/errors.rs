@@ -0,0 +1,23 @@
+use std::io;
+
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum Socks5Error {
+    #[error("Failed IO operation with SOCKS5 proxy: {0}")]
+    FailedIoOp(#[from] io::Error),
+    #[error("Proxy answered with unsupported SOCKS version {0}")]
+    UnsupportedVersion(u8),
+    #[error("Proxy accepts none of the offered auth methods")]
+    NoAcceptableAuthMethod,
+    #[error("Proxy rejected username and password")]
+    AuthFailed,
+    #[error("Can't send {0} bytes long credential, max length is 255")]
+    TooLongCredential(usize),
+    #[error("Can't send {0} bytes long hostname, max length is 255")]
+    TooLongHostname(usize),
+    #[error("Proxy can't connect to the target, reply code {0}")]
+    ConnectFailed(u8),
+    #[error("Proxy answered with unknown address type {0}")]
+    UnknownAddrType(u8),
+}
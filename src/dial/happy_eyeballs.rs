@@ -0,0 +1,180 @@
+use std::mem;
+use std::net::{Shutdown, SocketAddr, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::Duration;
+
+/// Sockets of the racing attempts, which are shut down, when the race is won.
+#[derive(Debug, Default)]
+pub(crate) struct Cancellation {
+    is_cancelled: AtomicBool,
+    // sockets are keyed by attempts, because the same address may be dialed twice
+    conns: Mutex<Vec<(usize, TcpStream)>>,
+}
+
+/// One of the racing attempts.
+#[derive(Debug)]
+pub(crate) struct Attempt {
+    id: usize,
+    cancellation: Arc<Cancellation>,
+}
+
+impl Attempt {
+    /// Registers the connected socket, returns `false` if the race is already over and the socket should be dropped.
+    pub(crate) fn register(&self, conn: &TcpStream) -> bool {
+        let mut conns = self.cancellation.conns.lock().unwrap_or_else(PoisonError::into_inner);
+        if self.cancellation.is_cancelled.load(Ordering::SeqCst) {
+            return false;
+        }
+        if let Ok(conn) = conn.try_clone() {
+            conns.push((self.id, conn));
+        }
+        true
+    }
+}
+
+impl Cancellation {
+    // Registered clones are dropped, including the winner's one, so its connection is closed with the caller's stream
+    fn cancel_except(&self, winner: usize) {
+        let conns = {
+            let mut conns = self.conns.lock().unwrap_or_else(PoisonError::into_inner);
+            self.is_cancelled.store(true, Ordering::SeqCst);
+            mem::take(&mut *conns)
+        };
+        for (_, conn) in conns.iter().filter(|(id, _)| *id != winner) {
+            let _ = conn.shutdown(Shutdown::Both);
+        }
+    }
+}
+
+/// Runs attempts staggered by `attempt_delay` as in RFC 8305, the first successful one wins.
+///
+/// Next attempt starts at once, when the previous one fails. Failures before the win are passed to `on_failed`,
+/// the last one is returned, if all attempts fail. Connected losing attempts are shut down through [`Cancellation`],
+/// connecting ones can't be interrupted and finish in the background, when they connect or time out.
+pub(crate) fn race<T, E, F, C>(addrs: Vec<SocketAddr>, attempt_delay: Duration, attempt: F, mut on_failed: C) -> Result<T, Option<E>>
+where
+    T: Send + 'static,
+    E: Send + 'static,
+    F: Fn(SocketAddr, &Attempt) -> Result<T, E> + Send + Sync + 'static,
+    C: FnMut(SocketAddr, &E),
+{
+    let addrs = interleave_families(addrs);
+    let attempt = Arc::new(attempt);
+    let cancellation = Arc::new(Cancellation::default());
+    let (results_tx, results) = mpsc::channel();
+
+    let mut started = 0;
+    let mut pending = 0;
+    let mut last_err = None;
+    loop {
+        if let Some(addr) = addrs.get(started).copied() {
+            let (attempt, results_tx) = (Arc::clone(&attempt), results_tx.clone());
+            let id = started;
+            let handle = Attempt { id, cancellation: Arc::clone(&cancellation) };
+            thread::spawn(move || {
+                let res = attempt(addr, &handle);
+                // receiver is dropped after the win, so losing results are dropped too
+                let _ = results_tx.send((id, addr, res));
+            });
+            started += 1;
+            pending += 1;
+        }
+        if pending == 0 {
+            return Err(last_err);
+        }
+
+        let res = match started < addrs.len() {
+            true => results.recv_timeout(attempt_delay),
+            false => results.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+        match res {
+            Ok((id, _, Ok(won))) => {
+                cancellation.cancel_except(id);
+                return Ok(won);
+            }
+            Ok((_, addr, Err(e))) => {
+                pending -= 1;
+                on_failed(addr, &e);
+                last_err = Some(e);
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return Err(last_err),
+        }
+    }
+}
+
+// Families alternate starting with IPv6, the order inside of a family is kept
+fn interleave_families(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let mut unique = Vec::with_capacity(addrs.len());
+    for addr in addrs {
+        if !unique.contains(&addr) {
+            unique.push(addr);
+        }
+    }
+    let (v6, v4): (Vec<_>, Vec<_>) = unique.into_iter().partition(|a| a.is_ipv6());
+    let mut interleaved = Vec::with_capacity(v6.len() + v4.len());
+    let (mut v6, mut v4) = (v6.into_iter(), v4.into_iter());
+    loop {
+        match (v6.next(), v4.next()) {
+            (None, None) => break,
+            (a, b) => interleaved.extend(a.into_iter().chain(b)),
+        }
+    }
+    interleaved
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    use super::*;
+
+    #[test]
+    fn test_cancellation_releases_winner() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("internal error: can't bind listener");
+        let addr = listener.local_addr().expect("internal error: no local addr");
+        let cancellation = Arc::new(Cancellation::default());
+        let attempts = (0..2).map(|id| Attempt { id, cancellation: Arc::clone(&cancellation) }).collect::<Vec<_>>();
+        let conns = attempts
+            .iter()
+            .map(|attempt| {
+                let conn = TcpStream::connect(addr).expect("internal error: can't connect");
+                assert!(attempt.register(&conn));
+                let (accepted, _) = listener.accept().expect("internal error: can't accept");
+                accepted.set_read_timeout(Some(Duration::from_secs(5))).expect("internal error: can't set timeout");
+                (conn, accepted)
+            })
+            .collect::<Vec<_>>();
+
+        // the same address is dialed by both attempts, only the loser is shut down
+        cancellation.cancel_except(0);
+        assert!(!attempts[1].register(&conns[1].0));
+        let mut buf = [0; 1];
+        for (i, (conn, mut accepted)) in conns.into_iter().enumerate() {
+            if i == 0 {
+                (&conn).write_all(&[1]).expect("internal error: winner is shut down");
+                assert_eq!(accepted.read(&mut buf).expect("internal error: can't read"), 1);
+            }
+            // cancellation is still alive, but holds no clone of the dropped stream
+            drop(conn);
+            assert_eq!(accepted.read(&mut buf).expect("internal error: can't read"), 0);
+        }
+    }
+
+    #[test]
+    fn test_interleave_families() {
+        let addrs = ["1.1.1.1:1", "2.2.2.2:1", "[::1]:1", "[::2]:1", "3.3.3.3:1", "1.1.1.1:1"]
+            .iter()
+            .map(|a| a.parse::<SocketAddr>().expect("internal error: invalid socket addr"))
+            .collect::<Vec<_>>();
+        let expected = ["[::1]:1", "1.1.1.1:1", "[::2]:1", "2.2.2.2:1", "3.3.3.3:1"]
+            .iter()
+            .map(|a| a.parse::<SocketAddr>().expect("internal error: invalid socket addr"))
+            .collect::<Vec<_>>();
+        assert_eq!(interleave_families(addrs), expected);
+    }
+}
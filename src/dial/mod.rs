@@ -0,0 +1,148 @@
+use std::io::{Error as IoError, ErrorKind};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use crate::features::PeerFeature;
+use crate::messages::Handshake;
+use crate::HandshakingError;
+
+use errors as dial_errors;
+pub use dial_errors::*;
+pub use socks5::{Socks5Auth, Socks5Proxy};
+
+mod errors;
+mod happy_eyeballs;
+mod socks5;
+
+/// How connections to peers are made.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum DialStrategy {
+    #[default]
+    Direct,
+    // Resolved addresses are dialed in parallel, the first handshaked connection is used.
+    // Connecting can't be cancelled, so losing attempts linger till they connect or `connect_timeout` passes
+    HappyEyeballs { attempt_delay: Duration, connect_timeout: Duration },
+    // Peers are reached only through the proxy, declared addresses are removed from our handshake
+    Socks5(Socks5Proxy),
+}
+
+pub(crate) use happy_eyeballs::{race, Attempt};
+
+pub(crate) enum Target<'a> {
+    Addr(SocketAddr),
+    // Hostname, which is resolved by the proxy
+    Host(&'a str, u16),
+}
+
+impl DialStrategy {
+    // Recommended by RFC 8305
+    pub const DEFAULT_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+    // Bounds each racing connect, the whole handshaking is still bounded by `HandshakeConfig::timeout`
+    pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+    pub fn happy_eyeballs() -> Self {
+        DialStrategy::HappyEyeballs {
+            attempt_delay: Self::DEFAULT_ATTEMPT_DELAY,
+            connect_timeout: Self::DEFAULT_CONNECT_TIMEOUT,
+        }
+    }
+
+    pub fn is_proxied(&self) -> bool {
+        matches!(self, DialStrategy::Socks5(_))
+    }
+
+    pub(crate) fn connect(&self, target: &Target<'_>, timeout: Duration) -> Result<TcpStream, HandshakingError> {
+        match (self, target) {
+            (DialStrategy::Socks5(proxy), target) => Ok(proxy.connect(target, timeout)?),
+            (_, Target::Addr(addr)) => Ok(TcpStream::connect_timeout(addr, timeout)?),
+            (_, Target::Host(host, port)) => Ok(connect_host(host, *port, timeout)?),
+        }
+    }
+
+    /// Our handshake without addresses, which would leak our real address through the proxy.
+    pub(crate) fn sanitize(&self, mut hs: Handshake) -> Handshake {
+        if self.is_proxied() {
+            hs.pub_address = None;
+            if let Some(features) = hs.features.as_mut() {
+                features.retain(|f| !matches!(f, PeerFeature::LocalAddr(_)));
+            }
+            if hs.features.as_ref().is_some_and(|f| f.is_empty()) {
+                hs.features = None;
+            }
+        }
+        hs
+    }
+}
+
+// Resolved addresses are tried in order, each one bounded by the timeout, the last error is returned
+fn connect_host(host: &str, port: u16, timeout: Duration) -> Result<TcpStream, IoError> {
+    let mut last_err = IoError::new(ErrorKind::InvalidInput, "could not resolve to any addresses");
+    for addr in (host, port).to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(conn) => return Ok(conn),
+            Err(e) => last_err = e,
+        }
+    }
+    Err(last_err)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::models::PeerAddr;
+    use crate::testing::{create_hs, MockBehavior, MockPeer, MockSocks5};
+    use crate::{handshaking_host, handshaking_with, HandshakeConfig};
+
+    use super::*;
+
+    fn socks5_config(proxy: Socks5Proxy) -> HandshakeConfig {
+        HandshakeConfig::new().with_timeout(Duration::from_secs(2)).with_dial(DialStrategy::Socks5(proxy))
+    }
+
+    #[test]
+    fn test_socks5_hides_own_addresses() {
+        let peer = MockPeer::spawn(create_hs("node", 2), MockBehavior::Honest).expect("internal error: can't spawn mock peer");
+        let proxy = MockSocks5::spawn(None).expect("internal error: can't spawn mock proxy");
+        let real_addr = PeerAddr("203.0.113.5:9030".parse().expect("internal error: invalid socket addr"));
+        let mut hs = create_hs("client", 1);
+        hs.pub_address = Some(real_addr.clone());
+        hs.features.as_mut().expect("internal error: no features").push(PeerFeature::LocalAddr(real_addr));
+
+        let (_conn, peer_hs) = handshaking_with(peer.addr(), hs, &socks5_config(Socks5Proxy::new(proxy.addr())))
+            .expect("internal error: handshaking failed");
+        assert_eq!(peer_hs, create_hs("node", 2));
+        assert_eq!(proxy.requests(), vec![peer.addr().to_string()]);
+        let sent = Handshake::parse(&peer.received()[0]).expect("internal error: can't parse sent hs");
+        assert_eq!(sent, create_hs("client", 1));
+    }
+
+    #[test]
+    fn test_socks5_auth_and_remote_dns() {
+        let peer = MockPeer::spawn(create_hs("node", 2), MockBehavior::Honest).expect("internal error: can't spawn mock peer");
+        let proxy = MockSocks5::spawn(Some(("ergo", "secret"))).expect("internal error: can't spawn mock proxy");
+        let port = peer.addr().port();
+
+        let config = socks5_config(Socks5Proxy::new(proxy.addr()).with_auth("ergo", "secret"));
+        let res = handshaking_host("localhost", port, create_hs("client", 1), &config);
+        assert!(res.is_ok());
+        assert_eq!(proxy.requests(), vec![format!("localhost:{}", port)]);
+
+        let config = socks5_config(Socks5Proxy::new(proxy.addr()).with_auth("ergo", "wrong"));
+        let res = handshaking_host("localhost", port, create_hs("client", 1), &config);
+        assert!(matches!(res, Err(HandshakingError::ProxyFailed(Socks5Error::AuthFailed))));
+
+        let res = handshaking_host("localhost", port, create_hs("client", 1), &socks5_config(Socks5Proxy::new(proxy.addr())));
+        assert!(matches!(res, Err(HandshakingError::ProxyFailed(Socks5Error::NoAcceptableAuthMethod))));
+    }
+
+    #[test]
+    fn test_direct_connect_to_host() {
+        let peer = MockPeer::spawn(create_hs("node", 2), MockBehavior::Honest).expect("internal error: can't spawn mock peer");
+        let conn = DialStrategy::Direct
+            .connect(&Target::Host("localhost", peer.addr().port()), Duration::from_secs(2))
+            .expect("internal error: can't connect to host");
+        assert_eq!(conn.peer_addr().map(|addr| addr.port()).ok(), Some(peer.addr().port()));
+
+        let res = DialStrategy::Direct.connect(&Target::Host("localhost", 0), Duration::from_secs(2));
+        assert!(res.is_err());
+    }
+}
@@ -0,0 +1,150 @@
+use std::convert::TryFrom;
+use std::io::{Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpStream};
+use std::time::Duration;
+
+use super::{Socks5Error, Target};
+
+const VERSION: u8 = 5;
+const AUTH_VERSION: u8 = 1;
+const METHOD_NO_AUTH: u8 = 0;
+const METHOD_USERNAME_PASSWORD: u8 = 2;
+const CMD_CONNECT: u8 = 1;
+const ATYP_IPV4: u8 = 1;
+const ATYP_DOMAIN: u8 = 3;
+const ATYP_IPV6: u8 = 4;
+const REPLY_SUCCEEDED: u8 = 0;
+
+/// SOCKS5 proxy, i.e. Tor client on 127.0.0.1:9050.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Socks5Proxy {
+    pub addr: SocketAddr,
+    pub auth: Option<Socks5Auth>,
+}
+
+/// Username/password auth of RFC 1929. Tor isolates streams with different credentials.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Socks5Auth {
+    pub username: String,
+    pub password: String,
+}
+
+impl Socks5Proxy {
+    pub fn new(addr: SocketAddr) -> Self {
+        Socks5Proxy { addr, auth: None }
+    }
+
+    pub fn with_auth(mut self, username: &str, password: &str) -> Self {
+        self.auth = Some(Socks5Auth {
+            username: username.to_string(),
+            password: password.to_string(),
+        });
+        self
+    }
+
+    /// Connects to the target through the proxy. Hostnames are resolved by the proxy.
+    pub(crate) fn connect(&self, target: &Target<'_>, timeout: Duration) -> Result<TcpStream, Socks5Error> {
+        let mut conn = TcpStream::connect_timeout(&self.addr, timeout)?;
+        conn.set_read_timeout(Some(timeout))?;
+        conn.set_write_timeout(Some(timeout))?;
+
+        self.negotiate_auth(&mut conn)?;
+        send_connect(&mut conn, target)?;
+        read_connect_reply(&mut conn)?;
+
+        conn.set_read_timeout(None)?;
+        conn.set_write_timeout(None)?;
+        Ok(conn)
+    }
+
+    fn negotiate_auth(&self, conn: &mut TcpStream) -> Result<(), Socks5Error> {
+        let greeting = match self.auth {
+            Some(_) => vec![VERSION, 2, METHOD_NO_AUTH, METHOD_USERNAME_PASSWORD],
+            None => vec![VERSION, 1, METHOD_NO_AUTH],
+        };
+        conn.write_all(&greeting)?;
+
+        let mut reply = [0u8; 2];
+        conn.read_exact(&mut reply)?;
+        match reply {
+            [VERSION, METHOD_NO_AUTH] => Ok(()),
+            [VERSION, METHOD_USERNAME_PASSWORD] => match self.auth.as_ref() {
+                Some(auth) => send_credentials(conn, auth),
+                None => Err(Socks5Error::NoAcceptableAuthMethod),
+            },
+            [VERSION, _] => Err(Socks5Error::NoAcceptableAuthMethod),
+            [version, _] => Err(Socks5Error::UnsupportedVersion(version)),
+        }
+    }
+}
+
+fn send_credentials(conn: &mut TcpStream, auth: &Socks5Auth) -> Result<(), Socks5Error> {
+    let mut request = vec![AUTH_VERSION];
+    for credential in [&auth.username, &auth.password].iter() {
+        let len = u8::try_from(credential.len()).map_err(|_| Socks5Error::TooLongCredential(credential.len()))?;
+        request.push(len);
+        request.extend_from_slice(credential.as_bytes());
+    }
+    conn.write_all(&request)?;
+
+    let mut reply = [0u8; 2];
+    conn.read_exact(&mut reply)?;
+    match reply {
+        [AUTH_VERSION, 0] => Ok(()),
+        _ => Err(Socks5Error::AuthFailed),
+    }
+}
+
+fn send_connect(conn: &mut TcpStream, target: &Target<'_>) -> Result<(), Socks5Error> {
+    let mut request = vec![VERSION, CMD_CONNECT, 0];
+    let port = match target {
+        Target::Addr(addr) => {
+            match addr.ip() {
+                IpAddr::V4(ip) => {
+                    request.push(ATYP_IPV4);
+                    request.extend_from_slice(&ip.octets());
+                }
+                IpAddr::V6(ip) => {
+                    request.push(ATYP_IPV6);
+                    request.extend_from_slice(&ip.octets());
+                }
+            }
+            addr.port()
+        }
+        Target::Host(host, port) => {
+            let len = u8::try_from(host.len()).map_err(|_| Socks5Error::TooLongHostname(host.len()))?;
+            request.extend_from_slice(&[ATYP_DOMAIN, len]);
+            request.extend_from_slice(host.as_bytes());
+            *port
+        }
+    };
+    request.extend_from_slice(&port.to_be_bytes());
+    conn.write_all(&request)?;
+    conn.flush().map_err(Socks5Error::FailedIoOp)
+}
+
+// Bound address of the reply is read and dropped, so the stream starts with the target's data
+fn read_connect_reply(conn: &mut TcpStream) -> Result<(), Socks5Error> {
+    let mut header = [0u8; 4];
+    conn.read_exact(&mut header)?;
+    let [version, reply, _, atyp] = header;
+    if version != VERSION {
+        return Err(Socks5Error::UnsupportedVersion(version));
+    }
+    if reply != REPLY_SUCCEEDED {
+        return Err(Socks5Error::ConnectFailed(reply));
+    }
+    let addr_len = match atyp {
+        ATYP_IPV4 => 4,
+        ATYP_IPV6 => 16,
+        ATYP_DOMAIN => {
+            let mut len = [0u8; 1];
+            conn.read_exact(&mut len)?;
+            len[0] as usize
+        }
+        _ => return Err(Socks5Error::UnknownAddrType(atyp)),
+    };
+    let mut bound_addr = vec![0u8; addr_len + 2];
+    conn.read_exact(&mut bound_addr)?;
+    Ok(())
+}
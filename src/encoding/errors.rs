@@ -0,0 +1,24 @@
+use std::io;
+
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum VlqDecodeError {
+    #[error("Vlq encoded value overflows {0}")]
+    Overflow(&'static str),
+    #[error("Vlq encoded value is truncated")]
+    Truncated,
+    #[error("Vlq encoded value has redundant trailing zero byte")]
+    NonCanonical,
+    #[error("Can't read vlq encoded value: {0}")]
+    CannotReadData(#[source] io::Error),
+}
+
+impl From<io::Error> for VlqDecodeError {
+    fn from(err: io::Error) -> Self {
+        match err.kind() {
+            io::ErrorKind::UnexpectedEof => VlqDecodeError::Truncated,
+            _ => VlqDecodeError::CannotReadData(err),
+        }
+    }
+}
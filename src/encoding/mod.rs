@@ -1 +1,7 @@
+pub use encoding_errors::*;
+
+use errors as encoding_errors;
+
+mod errors;
 pub mod vlq;
+pub mod zigzag;
@@ -1,13 +1,34 @@
+use std::convert::TryFrom;
 use std::io;
 
-use sigma_ser::peekable_reader::PeekableReader;
+use super::errors::VlqDecodeError;
+use super::zigzag;
 
 // todo-minor try better: it should somehow define, that vlq is used
 // todo-minor maybe move to vlq lib
 pub trait TryFromVlq: Sized {
     type Error;
 
-    fn try_from_vlq(data: Vec<u8>) -> Result<Self, Self::Error>;
+    fn try_from_vlq(data: &[u8]) -> Result<Self, Self::Error>;
+
+    /// Decodes model from the next `len` bytes of the buffer. Data isn't copied, if it's stored contiguously.
+    #[cfg(feature = "bytes")]
+    fn get_vlq<B: bytes::Buf>(buf: &mut B, len: usize) -> Result<Self, Self::Error>
+    where
+        Self::Error: From<VlqDecodeError>,
+    {
+        // model parsers accept some shorter lengths, so cut off data is rejected before parsing
+        if buf.remaining() < len {
+            return Err(VlqDecodeError::Truncated.into());
+        }
+        if buf.chunk().len() >= len {
+            let res = Self::try_from_vlq(&buf.chunk()[..len]);
+            buf.advance(len);
+            res
+        } else {
+            Self::try_from_vlq(&buf.copy_to_bytes(len))
+        }
+    }
 }
 
 // todo-minor try better: it should somehow define, that vlq is used
@@ -15,17 +36,238 @@ pub trait TryFromVlq: Sized {
 pub trait TryIntoVlq {
     type Error;
 
-    fn try_into_vlq(&self) -> Result<Vec<u8>, Self::Error>;
+    fn write_vlq<W: WriteVlqExt>(&self, writer: &mut W) -> Result<(), Self::Error>;
+
+    fn try_into_vlq(&self) -> Result<Vec<u8>, Self::Error> {
+        let mut buf = Vec::new();
+        self.write_vlq(&mut buf)?;
+        Ok(buf)
+    }
+
+    #[cfg(feature = "bytes")]
+    fn put_vlq<B: bytes::BufMut>(&self, buf: &mut B) -> Result<(), Self::Error> {
+        self.write_vlq(&mut bytes::BufMut::writer(buf))
+    }
+}
+
+/// Reads integers encoded in accordance to the ergo serialization spec: `u8` as is, other unsigned integers
+/// as VLQ and signed integers as ZigZag + VLQ.
+pub trait ReadVlqExt: io::Read {
+    fn get_u8(&mut self) -> Result<u8, VlqDecodeError> {
+        let mut buf = [0; 1];
+        self.read_exact(&mut buf)?;
+        Ok(buf[0])
+    }
+
+    fn get_u16(&mut self) -> Result<u16, VlqDecodeError> {
+        u16::try_from(self.get_u64()?).map_err(|_| VlqDecodeError::Overflow("u16"))
+    }
+
+    fn get_u32(&mut self) -> Result<u32, VlqDecodeError> {
+        u32::try_from(self.get_u64()?).map_err(|_| VlqDecodeError::Overflow("u32"))
+    }
+
+    fn get_u64(&mut self) -> Result<u64, VlqDecodeError> {
+        let mut res = 0;
+        let mut shift = 0;
+        loop {
+            let byte = self.get_u8()?;
+            // 10th byte can hold only the highest bit of u64
+            if shift == 63 && byte > 1 {
+                return Err(VlqDecodeError::Overflow("u64"));
+            }
+            res |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                if byte == 0 && shift > 0 {
+                    return Err(VlqDecodeError::NonCanonical);
+                }
+                return Ok(res);
+            }
+            shift += 7;
+        }
+    }
+
+    fn get_i32(&mut self) -> Result<i32, VlqDecodeError> {
+        self.get_u64().map(zigzag::decode_i32)
+    }
+
+    fn get_i64(&mut self) -> Result<i64, VlqDecodeError> {
+        self.get_u64().map(zigzag::decode_i64)
+    }
 }
 
-pub(crate) type DefaultVlqReader<T> = PeekableReader<io::Cursor<T>>;
+/// Writes integers encoded in accordance to the ergo serialization spec, see [`ReadVlqExt`].
+pub trait WriteVlqExt: io::Write {
+    fn put_u8(&mut self, v: u8) -> io::Result<()> {
+        self.write_all(&[v])
+    }
+
+    fn put_u16(&mut self, v: u16) -> io::Result<()> {
+        self.put_u64(u64::from(v))
+    }
+
+    fn put_u32(&mut self, v: u32) -> io::Result<()> {
+        self.put_u64(u64::from(v))
+    }
+
+    fn put_u64(&mut self, mut v: u64) -> io::Result<()> {
+        let mut buf = [0; MAX_VLQ_U64_SIZE];
+        let mut len = 0;
+        loop {
+            let byte = (v & 0x7f) as u8;
+            v >>= 7;
+            if v == 0 {
+                buf[len] = byte;
+                len += 1;
+                break;
+            }
+            buf[len] = byte | 0x80;
+            len += 1;
+        }
+        self.write_all(&buf[..len])
+    }
+
+    fn put_i32(&mut self, v: i32) -> io::Result<()> {
+        self.put_u64(zigzag::encode_i32(v))
+    }
+
+    fn put_i64(&mut self, v: i64) -> io::Result<()> {
+        self.put_u64(zigzag::encode_i64(v))
+    }
+}
+
+impl<R: io::Read + ?Sized> ReadVlqExt for R {}
+
+impl<W: io::Write + ?Sized> WriteVlqExt for W {}
+
+// u64 has 64 bits, each vlq byte holds 7 of them
+const MAX_VLQ_U64_SIZE: usize = 10;
+
+/// Reader over borrowed bytes, which lends read data without copying it.
+#[derive(Debug, Clone)]
+pub struct SliceReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> SliceReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    pub fn remaining(&self) -> &'a [u8] {
+        &self.data[self.pos..]
+    }
+
+    pub fn read_slice(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        let remaining = self.remaining();
+        if remaining.len() < len {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        self.pos += len;
+        Ok(&remaining[..len])
+    }
+}
+
+impl io::Read for SliceReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut remaining = self.remaining();
+        let n = remaining.read(buf)?;
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+pub(crate) type DefaultVlqReader<'a> = SliceReader<'a>;
 pub(crate) type DefaultVlqWriter<T> = io::Cursor<T>;
 
 // todo-minor: get_vlq_reader(type, data) - shall be discussed
-pub(crate) fn default_vlq_reader<T: AsRef<[u8]>>(data: T) -> DefaultVlqReader<T> {
-    PeekableReader::new(io::Cursor::new(data))
+pub(crate) fn default_vlq_reader(data: &[u8]) -> DefaultVlqReader<'_> {
+    SliceReader::new(data)
 }
 
 pub(crate) fn default_vlq_writer<T: AsRef<[u8]>>(data: T) -> DefaultVlqWriter<T> {
     io::Cursor::new(data)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode<F: FnOnce(&mut Vec<u8>) -> io::Result<()>>(f: F) -> Vec<u8> {
+        let mut buf = Vec::new();
+        f(&mut buf).expect("internal error: can't write to vec");
+        buf
+    }
+
+    fn hex_to_bytes(s: &str) -> Vec<u8> {
+        hex::decode(s).expect("internal error: invalid hex str")
+    }
+
+    #[test]
+    fn test_unsigned_vectors() {
+        let cases: Vec<(u64, &str)> = vec![
+            (0, "00"),
+            (1, "01"),
+            (127, "7f"),
+            (128, "8001"),
+            (300, "ac02"),
+            (9030, "c646"),
+            (u64::from(u32::MAX), "ffffffff0f"),
+            (u64::MAX, "ffffffffffffffffff01"),
+        ];
+        for (v, hex_str) in cases {
+            let bytes = hex_to_bytes(hex_str);
+            assert_eq!(encode(|w| w.put_u64(v)), bytes);
+            assert_eq!(default_vlq_reader(&bytes).get_u64().expect("internal error: can't decode vlq"), v);
+        }
+    }
+
+    #[test]
+    fn test_signed_vectors() {
+        // values and encodings are taken from the real handshakes mode and session id features
+        let i32_cases: Vec<(i32, &str)> = vec![(-1, "01"), (0, "00"), (1, "02"), (i32::MIN, "ffffffffffffffffff01")];
+        for (v, hex_str) in i32_cases {
+            let bytes = hex_to_bytes(hex_str);
+            assert_eq!(encode(|w| w.put_i32(v)), bytes);
+            assert_eq!(default_vlq_reader(&bytes).get_i32().expect("internal error: can't decode vlq"), v);
+        }
+        let i64_cases: Vec<(i64, &str)> = vec![
+            (-7226886467503878579, "e5c6abfafabc87cbc801"),
+            (-2393537216959524988, "f7c1e5d8dadac6b742"),
+            (6155961833357488951, "eecc9582ffaaafeeaa01"),
+            (i64::MIN, "ffffffffffffffffff01"),
+        ];
+        for (v, hex_str) in i64_cases {
+            let bytes = hex_to_bytes(hex_str);
+            assert_eq!(encode(|w| w.put_i64(v)), bytes);
+            assert_eq!(default_vlq_reader(&bytes).get_i64().expect("internal error: can't decode vlq"), v);
+        }
+    }
+
+    #[test]
+    fn test_slice_reader() {
+        let data = [0xac, 0x02, 1, 2, 3];
+        let mut reader = default_vlq_reader(&data);
+        assert_eq!(reader.get_u16().expect("internal error: can't decode vlq"), 300);
+        assert_eq!(reader.position(), 2);
+        assert_eq!(reader.read_slice(2).expect("internal error: can't read slice"), &[1, 2]);
+        assert!(reader.read_slice(2).is_err());
+        assert_eq!(reader.remaining(), &[3]);
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        assert!(matches!(default_vlq_reader(&[]).get_u8(), Err(VlqDecodeError::Truncated)));
+        assert!(matches!(default_vlq_reader(&[0x80]).get_u64(), Err(VlqDecodeError::Truncated)));
+        assert!(matches!(default_vlq_reader(&[0x80, 0x00]).get_u64(), Err(VlqDecodeError::NonCanonical)));
+        assert!(matches!(default_vlq_reader(&[0xff; 10]).get_u64(), Err(VlqDecodeError::Overflow("u64"))));
+        assert!(matches!(default_vlq_reader(&hex_to_bytes("ffffffffffffffffff02")).get_u64(), Err(VlqDecodeError::Overflow("u64"))));
+        assert!(matches!(default_vlq_reader(&hex_to_bytes("808004")).get_u16(), Err(VlqDecodeError::Overflow("u16"))));
+        assert!(matches!(default_vlq_reader(&hex_to_bytes("8080808010")).get_u32(), Err(VlqDecodeError::Overflow("u32"))));
+    }
+}
@@ -0,0 +1,21 @@
+//! ZigZag encoding of signed integers, so that small negative numbers have short vlq encoding.
+
+// Encoded value is sign extended to u64 rather than zero extended, as the reference node does.
+// So negative numbers with big absolute values are vlq-encoded in 10 bytes.
+pub fn encode_i32(v: i32) -> u64 {
+    ((v << 1) ^ (v >> 31)) as i64 as u64
+}
+
+// Only lower 32 bits are used, so both sign and zero extended values are decoded
+pub fn decode_i32(v: u64) -> i32 {
+    let v = v as u32;
+    ((v >> 1) as i32) ^ -((v & 1) as i32)
+}
+
+pub fn encode_i64(v: i64) -> u64 {
+    ((v << 1) ^ (v >> 63)) as u64
+}
+
+pub fn decode_i64(v: u64) -> i64 {
+    ((v >> 1) as i64) ^ -((v & 1) as i64)
+}
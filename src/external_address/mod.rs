@@ -0,0 +1,253 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, Instant};
+
+use crate::models::{AddrScope, NetGroup, PeerAddr};
+use crate::AcceptedPeer;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EstimatorConfig {
+    /// Port, which is put in the suggested address. Observed ports are ignored, because NAT maps them.
+    pub listen_port: u16,
+    /// Votes needed for the suggestion, the suggested ip also needs more than a half of all votes.
+    pub min_votes: usize,
+    /// Unroutable observations are useful only in local networks and tests.
+    pub allow_unroutable: bool,
+    /// Votes of observers, which didn't confirm them, expire after the period.
+    pub vote_ttl: Duration,
+}
+
+impl EstimatorConfig {
+    pub const DEFAULT_VOTE_TTL: Duration = Duration::from_secs(60 * 60);
+
+    pub fn new(listen_port: u16) -> Self {
+        EstimatorConfig {
+            listen_port,
+            min_votes: 3,
+            allow_unroutable: false,
+            vote_ttl: Self::DEFAULT_VOTE_TTL,
+        }
+    }
+}
+
+/// Estimates our external address from how handshaked peers see us.
+///
+/// Each observer votes for the ip, which it sees us at. The Ergo handshake has no field for it, so votes come from:
+/// - inbound peers through [`ExternalAddressEstimator::observe_accepted`]: the peer has dialed the address it knows us by,
+///   which is the destination of the accepted connection. Behind NAT the destination is rewritten to our private
+///   address, so such votes are unroutable and skipped;
+/// - out of band reports through [`ExternalAddressEstimator::observe`], i.e. the remote address of a peer's socket.
+///
+/// Local addresses of outbound connections aren't votes: they are chosen by our host. Observers from one netgroup
+/// have one vote, so a single network can't outvote the others. Suggested address can be declared as
+/// `pub_address` in our handshake.
+#[derive(Debug, Clone)]
+pub struct ExternalAddressEstimator {
+    config: EstimatorConfig,
+    votes: HashMap<Observer, Vote>,
+}
+
+// Unroutable observers don't share a netgroup, i.e. local listeners on different loopback ips
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Observer {
+    Group(NetGroup),
+    Ip(IpAddr),
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Vote {
+    ip: IpAddr,
+    observed_at: Instant,
+}
+
+impl Observer {
+    fn of(ip: IpAddr) -> Self {
+        match NetGroup::of(ip) {
+            NetGroup::Unroutable => Observer::Ip(ip.to_canonical()),
+            group => Observer::Group(group),
+        }
+    }
+}
+
+impl ExternalAddressEstimator {
+    pub fn new(config: EstimatorConfig) -> Self {
+        ExternalAddressEstimator {
+            config,
+            votes: HashMap::new(),
+        }
+    }
+
+    /// Records that `observer` sees our connection from `observed` address, replacing the previous vote of its netgroup.
+    /// Returns whether the observation is counted.
+    pub fn observe(&mut self, observer: IpAddr, observed: IpAddr) -> bool {
+        self.observe_at(observer, observed, Instant::now())
+    }
+
+    /// Records the address, which the handshaked inbound peer has dialed, as its vote. Returns whether it's counted.
+    pub fn observe_accepted(&mut self, peer: &AcceptedPeer) -> bool {
+        match (peer.conn.peer_addr(), peer.conn.local_addr()) {
+            (Ok(observer), Ok(dialed)) => self.observe(observer.ip(), dialed.ip()),
+            _ => false,
+        }
+    }
+
+    /// Removes the vote of the observer's netgroup, i.e. when the observer disconnects.
+    pub fn forget(&mut self, observer: IpAddr) {
+        self.votes.remove(&Observer::of(observer));
+    }
+
+    /// Unexpired votes.
+    pub fn votes(&self) -> usize {
+        self.live_votes(Instant::now()).count()
+    }
+
+    /// The ip with the majority of votes and the listen port, `None` until there are enough votes.
+    pub fn suggest(&self) -> Option<PeerAddr> {
+        self.suggest_at(Instant::now())
+    }
+
+    fn observe_at(&mut self, observer: IpAddr, observed: IpAddr, now: Instant) -> bool {
+        let observed = observed.to_canonical();
+        if !self.config.allow_unroutable && AddrScope::of(observed) != AddrScope::Global {
+            return false;
+        }
+        let ttl = self.config.vote_ttl;
+        self.votes.retain(|_, vote| now.saturating_duration_since(vote.observed_at) < ttl);
+        self.votes.insert(Observer::of(observer), Vote { ip: observed, observed_at: now });
+        true
+    }
+
+    fn live_votes(&self, now: Instant) -> impl Iterator<Item = IpAddr> + '_ {
+        let ttl = self.config.vote_ttl;
+        self.votes
+            .values()
+            .filter(move |vote| now.saturating_duration_since(vote.observed_at) < ttl)
+            .map(|vote| vote.ip)
+    }
+
+    fn suggest_at(&self, now: Instant) -> Option<PeerAddr> {
+        let mut counts = HashMap::new();
+        let mut total = 0;
+        for ip in self.live_votes(now) {
+            *counts.entry(ip).or_insert(0usize) += 1;
+            total += 1;
+        }
+        if total < self.config.min_votes.max(1) {
+            return None;
+        }
+        let (ip, count) = counts.into_iter().max_by_key(|(_, count)| *count)?;
+        if count * 2 <= total {
+            return None;
+        }
+        Some(PeerAddr(SocketAddr::new(ip, self.config.listen_port)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, TcpListener, TcpStream};
+    use std::thread;
+
+    use crate::testing::create_hs;
+    use crate::{accept_handshaking, handshaking, HandshakeConfig};
+
+    use super::*;
+
+    fn ip(a: u8, b: u8, c: u8, d: u8) -> IpAddr {
+        IpAddr::V4(Ipv4Addr::new(a, b, c, d))
+    }
+
+    #[test]
+    fn test_majority_vote() {
+        let mut estimator = ExternalAddressEstimator::new(EstimatorConfig::new(9030));
+        let external = ip(213, 239, 193, 208);
+        assert!(estimator.observe(ip(1, 1, 1, 1), external));
+        assert!(estimator.observe(ip(2, 2, 2, 2), external));
+        assert_eq!(estimator.suggest(), None);
+        assert!(estimator.observe(ip(3, 3, 3, 3), ip(5, 5, 5, 5)));
+        assert_eq!(estimator.suggest(), Some(PeerAddr(SocketAddr::new(external, 9030))));
+
+        // tie has no majority
+        assert!(estimator.observe(ip(4, 4, 4, 4), ip(5, 5, 5, 5)));
+        assert_eq!(estimator.suggest(), None);
+
+        // disconnected observer doesn't vote
+        estimator.forget(ip(4, 4, 4, 4));
+        assert_eq!(estimator.votes(), 3);
+        assert_eq!(estimator.suggest(), Some(PeerAddr(SocketAddr::new(external, 9030))));
+    }
+
+    #[test]
+    fn test_one_vote_per_netgroup() {
+        let mut estimator = ExternalAddressEstimator::new(EstimatorConfig::new(9030));
+        for d in 1..10 {
+            estimator.observe(ip(6, 6, 0, d), ip(7, 7, 7, 7));
+        }
+        assert_eq!(estimator.votes(), 1);
+        assert_eq!(estimator.suggest(), None);
+        assert!(!estimator.observe(ip(1, 1, 1, 1), ip(192, 168, 0, 2)));
+    }
+
+    #[test]
+    fn test_votes_expire() {
+        let mut estimator = ExternalAddressEstimator::new(EstimatorConfig::new(9030));
+        let now = Instant::now();
+        let ttl = EstimatorConfig::DEFAULT_VOTE_TTL;
+        for a in 1..=3 {
+            assert!(estimator.observe_at(ip(a, a, a, a), ip(7, 7, 7, 7), now));
+        }
+        assert!(estimator.suggest_at(now + ttl / 2).is_some());
+        assert_eq!(estimator.suggest_at(now + ttl), None);
+
+        // expired votes are dropped on the next observation
+        assert!(estimator.observe_at(ip(4, 4, 4, 4), ip(7, 7, 7, 7), now + ttl));
+        assert_eq!(estimator.votes.len(), 1);
+    }
+
+    // the whole 127.0.0.0/8 is routed to loopback only on linux
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_observe_local_listeners() {
+        let config = EstimatorConfig {
+            allow_unroutable: true,
+            ..EstimatorConfig::new(9030)
+        };
+        let mut estimator = ExternalAddressEstimator::new(config);
+        let listeners = (1..=3)
+            .map(|d| TcpListener::bind((ip(127, 0, 0, d), 0)).expect("internal error: can't bind listener"))
+            .collect::<Vec<_>>();
+        for listener in listeners.iter() {
+            let addr = listener.local_addr().expect("internal error: no local addr");
+            let _conn = TcpStream::connect(addr).expect("internal error: can't connect");
+            // listener plays the peer, which reports the remote address of the accepted socket
+            let (_accepted, seen_from) = listener.accept().expect("internal error: can't accept");
+            assert!(estimator.observe(addr.ip(), seen_from.ip()));
+        }
+        assert_eq!(estimator.votes(), 3);
+        let suggested = estimator.suggest().expect("internal error: no suggestion");
+        assert!(suggested.0.ip().is_loopback());
+        assert_eq!(suggested.0.port(), 9030);
+    }
+
+    #[test]
+    fn test_observe_accepted_peer() {
+        let listener = TcpListener::bind((ip(127, 0, 0, 1), 0)).expect("internal error: can't bind listener");
+        let addr = listener.local_addr().expect("internal error: no local addr");
+        let client = thread::spawn(move || handshaking(addr, create_hs("client", 1)));
+        let (conn, _) = listener.accept().expect("internal error: can't accept");
+        let peer = accept_handshaking(conn, create_hs("node", 2), &HandshakeConfig::new()).expect("internal error: accepting failed");
+        assert!(client.join().expect("internal error: client panicked").is_ok());
+
+        // loopback isn't our external address, unless it's allowed
+        let mut estimator = ExternalAddressEstimator::new(EstimatorConfig { min_votes: 1, ..EstimatorConfig::new(9030) });
+        assert!(!estimator.observe_accepted(&peer));
+        let config = EstimatorConfig {
+            min_votes: 1,
+            allow_unroutable: true,
+            ..EstimatorConfig::new(9030)
+        };
+        let mut estimator = ExternalAddressEstimator::new(config);
+        assert!(estimator.observe_accepted(&peer));
+        assert_eq!(estimator.suggest(), Some(PeerAddr(SocketAddr::new(addr.ip(), 9030))));
+    }
+}
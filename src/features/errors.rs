@@ -2,8 +2,7 @@ use std::io;
 
 use thiserror::Error;
 
-use sigma_ser::vlq_encode::VlqEncodingError;
-
+use crate::encoding::VlqDecodeError;
 use crate::models::{ModelParseError, ModelSerializeError};
 
 use super::Features;
@@ -23,9 +22,8 @@ pub enum FeaturesError {
 pub enum FeatureParseError {
     #[error("Feature can't be read from bytes: {0}")]
     CannotReadData(#[from] io::Error),
-    #[error("Decoding data failed")]
-    // todo-tmp VlqEncodingError doesn't impl Error. VlqDecodingError::VlqDecodingError tells us nothing
-    CannotVlqDecodeData(VlqEncodingError),
+    #[error("Decoding data failed: {0}")]
+    CannotVlqDecodeData(#[from] VlqDecodeError),
     #[error("{0}")]
     CannotParseLocalAddress(#[source] ModelParseError),
 }
@@ -36,11 +34,7 @@ pub enum FeatureSerializeError {
     CannotSerializeLocalAddress(#[source] ModelSerializeError),
     #[error("Feature can't be written to buffer: {0}")]
     CannotWriteData(#[from] io::Error),
+    #[error("Unrecognized feature can't be serialized")]
+    UnrecognizedFeature,
 }
 
-// tmp, until VlqEncodingError is fixed
-impl From<VlqEncodingError> for FeatureParseError {
-    fn from(err: VlqEncodingError) -> Self {
-        FeatureParseError::CannotVlqDecodeData(err)
-    }
-}
@@ -1,7 +1,7 @@
 use std::convert::TryFrom;
 use std::ops::{Deref, DerefMut};
 
-use crate::encoding::vlq::{TryFromVlq, TryIntoVlq};
+use crate::encoding::vlq::{TryFromVlq, TryIntoVlq, WriteVlqExt};
 use crate::models::PeerAddr;
 
 pub use feature_errors::*;
@@ -14,10 +14,20 @@ mod errors;
 mod mode;
 mod session_id;
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(try_from = "Vec<PeerFeature>", into = "Vec<PeerFeature>")
+)]
 pub struct Features(Vec<PeerFeature>);
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(tag = "type", content = "value", rename_all = "snake_case")
+)]
 pub enum PeerFeature {
     Mode(Mode),
     LocalAddr(PeerAddr),
@@ -36,6 +46,20 @@ impl Features {
     }
 }
 
+impl TryFrom<Vec<PeerFeature>> for Features {
+    type Error = FeaturesError;
+
+    fn try_from(features: Vec<PeerFeature>) -> Result<Self, Self::Error> {
+        Self::try_new(features)
+    }
+}
+
+impl From<Features> for Vec<PeerFeature> {
+    fn from(features: Features) -> Self {
+        features.0
+    }
+}
+
 impl Deref for Features {
     type Target = Vec<PeerFeature>;
 
@@ -55,20 +79,21 @@ impl PeerFeature {
     pub const LOCAL_ADDR_ID: u8 = 2;
     pub const SESSION_ID: u8 = 3;
 
-    pub fn get_id(&self) -> u8 {
+    // Id of unrecognized feature isn't stored, because such features are dropped on parse
+    pub fn get_id(&self) -> Option<u8> {
         match self {
-            PeerFeature::Mode(_) => Self::MODE_ID,
-            PeerFeature::LocalAddr(_) => Self::LOCAL_ADDR_ID,
-            PeerFeature::SessionId(_) => Self::SESSION_ID,
-            PeerFeature::Unrecognized => panic!("unrecognized features was used"),
+            PeerFeature::Mode(_) => Some(Self::MODE_ID),
+            PeerFeature::LocalAddr(_) => Some(Self::LOCAL_ADDR_ID),
+            PeerFeature::SessionId(_) => Some(Self::SESSION_ID),
+            PeerFeature::Unrecognized => None,
         }
     }
 }
 
-impl TryFrom<(u8, Vec<u8>)> for PeerFeature {
+impl TryFrom<(u8, &[u8])> for PeerFeature {
     type Error = FeaturesError;
 
-    fn try_from((id, data): (u8, Vec<u8>)) -> Result<Self, Self::Error> {
+    fn try_from((id, data): (u8, &[u8])) -> Result<Self, Self::Error> {
         let res = match id {
             PeerFeature::MODE_ID => Mode::try_from_vlq(data).map(PeerFeature::Mode),
             PeerFeature::LOCAL_ADDR_ID => PeerAddr::try_from_vlq(data)
@@ -84,12 +109,12 @@ impl TryFrom<(u8, Vec<u8>)> for PeerFeature {
 impl TryIntoVlq for PeerFeature {
     type Error = FeaturesError;
 
-    fn try_into_vlq(&self) -> Result<Vec<u8>, Self::Error> {
+    fn write_vlq<W: WriteVlqExt>(&self, writer: &mut W) -> Result<(), Self::Error> {
         let res = match self {
-            PeerFeature::Mode(mode) => mode.try_into_vlq(),
-            PeerFeature::LocalAddr(peer_addr) => peer_addr.try_into_vlq().map_err(FeatureSerializeError::CannotSerializeLocalAddress),
-            PeerFeature::SessionId(session_id) => session_id.try_into_vlq(),
-            PeerFeature::Unrecognized => panic!("unrecognized features was used"),
+            PeerFeature::Mode(mode) => mode.write_vlq(writer),
+            PeerFeature::LocalAddr(peer_addr) => peer_addr.write_vlq(writer).map_err(FeatureSerializeError::CannotSerializeLocalAddress),
+            PeerFeature::SessionId(session_id) => session_id.write_vlq(writer),
+            PeerFeature::Unrecognized => Err(FeatureSerializeError::UnrecognizedFeature),
         };
         res.map_err(FeaturesError::CannotSerializeFeature)
     }
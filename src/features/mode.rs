@@ -1,10 +1,9 @@
-use sigma_ser::vlq_encode::{ReadSigmaVlqExt, WriteSigmaVlqExt};
-
-use crate::encoding::vlq::{default_vlq_reader, default_vlq_writer, TryFromVlq, TryIntoVlq};
+use crate::encoding::vlq::{default_vlq_reader, ReadVlqExt, TryFromVlq, TryIntoVlq, WriteVlqExt};
 
 use super::{FeatureParseError, FeatureSerializeError};
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Mode {
     pub state_type: u8,
     pub is_verifying: bool,
@@ -15,7 +14,7 @@ pub struct Mode {
 impl TryFromVlq for Mode {
     type Error = FeatureParseError;
 
-    fn try_from_vlq(data: Vec<u8>) -> Result<Self, Self::Error> {
+    fn try_from_vlq(data: &[u8]) -> Result<Self, Self::Error> {
         let mut vlq_reader = default_vlq_reader(data);
 
         let state_type = vlq_reader.get_u8()?;
@@ -42,8 +41,7 @@ impl TryFromVlq for Mode {
 impl TryIntoVlq for Mode {
     type Error = FeatureSerializeError;
 
-    fn try_into_vlq(&self) -> Result<Vec<u8>, Self::Error> {
-        let mut vlq_writer = default_vlq_writer(Vec::new());
+    fn write_vlq<W: WriteVlqExt>(&self, vlq_writer: &mut W) -> Result<(), Self::Error> {
         let &Mode { state_type, is_verifying, nipopow_suffix_len, blocks_to_keep} = self;
 
         vlq_writer.put_u8(state_type)?;
@@ -56,6 +54,6 @@ impl TryIntoVlq for Mode {
         }
         vlq_writer.put_i32(blocks_to_keep)?;
 
-        Ok(vlq_writer.into_inner())
+        Ok(())
     }
 }
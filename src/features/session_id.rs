@@ -1,13 +1,12 @@
-use std::io::{Read, Write};
+use std::io::Read;
 
-use sigma_ser::vlq_encode::{ReadSigmaVlqExt, WriteSigmaVlqExt};
-
-use crate::encoding::vlq::{default_vlq_reader, default_vlq_writer, TryFromVlq, TryIntoVlq};
+use crate::encoding::vlq::{default_vlq_reader, ReadVlqExt, TryFromVlq, TryIntoVlq, WriteVlqExt};
 use crate::models::MagicBytes;
 
 use super::{FeatureParseError, FeatureSerializeError};
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct SessionId {
     pub magic: MagicBytes,
     pub session_id: i64,
@@ -16,7 +15,7 @@ pub struct SessionId {
 impl TryFromVlq for SessionId {
     type Error = FeatureParseError;
 
-    fn try_from_vlq(data: Vec<u8>) -> Result<Self, Self::Error> {
+    fn try_from_vlq(data: &[u8]) -> Result<Self, Self::Error> {
         let mut vlq_reader = default_vlq_reader(data);
 
         let magic = {
@@ -33,13 +32,12 @@ impl TryFromVlq for SessionId {
 impl TryIntoVlq for SessionId {
     type Error = FeatureSerializeError;
 
-    fn try_into_vlq(&self) -> Result<Vec<u8>, Self::Error> {
-        let mut vlq_writer = default_vlq_writer(Vec::new());
+    fn write_vlq<W: WriteVlqExt>(&self, vlq_writer: &mut W) -> Result<(), Self::Error> {
         let SessionId { magic: MagicBytes(magic), session_id } = self;
 
-        vlq_writer.write(magic)?;
+        vlq_writer.write_all(magic)?;
         vlq_writer.put_i64(*session_id)?;
 
-        Ok(vlq_writer.into_inner())
+        Ok(())
     }
 }
@@ -1,12 +1,17 @@
-use std::io::{Error as IoError, Read, Write};
-use std::net::{TcpStream, ToSocketAddrs};
+use std::io::{Error as IoError, ErrorKind, Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::Arc;
 use std::time::Duration;
 
 use thiserror::Error;
 
+use crate::admission::{AdmissionError, InboundAdmission, InboundPermit};
+use crate::dial::{race, Attempt, DialStrategy, Socks5Error, Target};
+use crate::features::{PeerFeature, SessionId};
 use crate::messages::{Handshake, HsSpecWriterError, HsSpecReaderError};
-
-const HS_TIMEOUT: Duration = Duration::from_secs(30);
+use crate::models::{MagicBytes, PeerAddr};
+use crate::observer::{HandshakeObserver, HandshakeStage, Probe};
+use crate::reputation::{Misbehavior, PeerReputation};
 
 #[derive(Error, Debug)]
 pub enum HandshakingError {
@@ -16,16 +21,340 @@ pub enum HandshakingError {
     MessageSerializeError(#[from] HsSpecWriterError),
     #[error("Failed handshake message parse: {0}")]
     MessageParseError(#[from] HsSpecReaderError),
+    #[error("Peer {0} is banned")]
+    Banned(IpAddr),
+    #[error("Peer is from another network with magic bytes {0}")]
+    WrongNetwork(MagicBytes),
+    #[error("Connected to self")]
+    SelfConnection,
+    #[error("Inbound peer rejected: {0}")]
+    Rejected(#[from] AdmissionError),
+    #[error("Peer declared unroutable address {0}")]
+    UnroutableAddr(PeerAddr),
+    #[error("Failed dialing through proxy: {0}")]
+    ProxyFailed(#[from] Socks5Error),
+}
+
+impl HandshakingError {
+    /// Short name of the variant, i.e. a metric label.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            HandshakingError::FailedIoOp(_) => "io",
+            HandshakingError::MessageSerializeError(_) => "serialize",
+            HandshakingError::MessageParseError(_) => "parse",
+            HandshakingError::Banned(_) => "banned",
+            HandshakingError::WrongNetwork(_) => "wrong_network",
+            HandshakingError::SelfConnection => "self_connection",
+            HandshakingError::Rejected(_) => "rejected",
+            HandshakingError::UnroutableAddr(_) => "unroutable_addr",
+            HandshakingError::ProxyFailed(_) => "proxy",
+        }
+    }
+}
+
+/// What to do with unroutable addresses, which the peer declares in its handshake.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DeclaredAddrPolicy {
+    // Handshake is returned as received
+    #[default]
+    Keep,
+    // Unroutable public address and `LocalAddr` features are removed from the handshake
+    Drop,
+    // Unroutable public address fails handshaking. `LocalAddr` features are private on nodes behind NAT, so they're only removed
+    Reject,
+}
+
+/// Options of [`handshaking_with`].
+#[derive(Debug, Clone)]
+pub struct HandshakeConfig {
+    timeout: Duration,
+    reputation: Option<Arc<PeerReputation>>,
+    admission: Option<InboundAdmission>,
+    declared_addr_policy: DeclaredAddrPolicy,
+    dial: DialStrategy,
+    observer: Option<Arc<dyn HandshakeObserver>>,
+}
+
+impl HandshakeConfig {
+    pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+    pub fn new() -> Self {
+        HandshakeConfig {
+            timeout: Self::DEFAULT_TIMEOUT,
+            reputation: None,
+            admission: None,
+            declared_addr_policy: DeclaredAddrPolicy::Keep,
+            dial: DialStrategy::Direct,
+            observer: None,
+        }
+    }
+
+    /// Bounds waiting for the peer's handshake.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Banned peers aren't connected, failed ones are penalized.
+    pub fn with_reputation(mut self, reputation: Arc<PeerReputation>) -> Self {
+        self.reputation = Some(reputation);
+        self
+    }
+
+    /// Inbound peers are admitted before their handshakes are read.
+    pub fn with_admission(mut self, admission: InboundAdmission) -> Self {
+        self.admission = Some(admission);
+        self
+    }
+
+    /// Filters declared addresses before they reach an address book.
+    pub fn with_declared_addr_policy(mut self, policy: DeclaredAddrPolicy) -> Self {
+        self.declared_addr_policy = policy;
+        self
+    }
+
+    /// Our public address and `LocalAddr` features aren't sent, when dialing through a proxy.
+    pub fn with_dial(mut self, dial: DialStrategy) -> Self {
+        self.dial = dial;
+        self
+    }
+
+    /// Steps of every handshaking are reported to the observer.
+    pub fn with_observer(mut self, observer: Arc<dyn HandshakeObserver>) -> Self {
+        self.observer = Some(observer);
+        self
+    }
+}
+
+impl Default for HandshakeConfig {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 pub fn handshaking<A: ToSocketAddrs>(addr: A, hs_msg: Handshake) -> Result<(TcpStream, Handshake), HandshakingError> {
-    let mut conn = TcpStream::connect(addr)?;
-    conn.set_read_timeout(Some(HS_TIMEOUT))
-        .expect("internal error: zero duration passed as read timeout");
+    handshaking_with(addr, hs_msg, &HandshakeConfig::default())
+}
+
+/// Connects to the first resolved address, which isn't banned, and exchanges handshakes.
+///
+/// Peer's handshake is rejected, if its session id has magic bytes other than the local one or the same session id.
+///
+/// Through a proxy hostnames are still resolved locally, use [`handshaking_host`] to resolve them by the proxy.
+pub fn handshaking_with<A: ToSocketAddrs>(addr: A, hs_msg: Handshake, config: &HandshakeConfig) -> Result<(TcpStream, Handshake), HandshakingError> {
+    measured(false, |(_, peer_hs): &(TcpStream, Handshake)| peer_hs, || dial_handshaking(addr, hs_msg, config))
+}
+
+fn dial_handshaking<A: ToSocketAddrs>(addr: A, hs_msg: Handshake, config: &HandshakeConfig) -> Result<(TcpStream, Handshake), HandshakingError> {
+    let hs_msg = config.dial.sanitize(hs_msg);
+    let hs_bytes = hs_msg.serialize()?;
+    let addrs = addr.to_socket_addrs()?.collect::<Vec<_>>();
+    if let DialStrategy::HappyEyeballs { attempt_delay, connect_timeout } = config.dial {
+        return race_handshaking(addrs, hs_msg, hs_bytes, config, attempt_delay, connect_timeout);
+    }
+
+    let mut last_err = None;
+    for addr in addrs {
+        let res = try_handshaking(addr, &hs_msg, &hs_bytes, config);
+        match res {
+            Ok(res) => return Ok(res),
+            Err(e) => {
+                report(config, addr, &e);
+                last_err = Some(e);
+            }
+        }
+    }
+    Err(last_err.unwrap_or_else(unresolved))
+}
+
+// Attempts are made with the handshake and config clones, because losing ones outlive the call
+fn race_handshaking(
+    addrs: Vec<SocketAddr>,
+    hs_msg: Handshake,
+    hs_bytes: Vec<u8>,
+    config: &HandshakeConfig,
+    attempt_delay: Duration,
+    connect_timeout: Duration,
+) -> Result<(TcpStream, Handshake), HandshakingError> {
+    let (allowed, banned): (Vec<_>, Vec<_>) = addrs
+        .into_iter()
+        .partition(|addr| !config.reputation.as_ref().is_some_and(|r| r.is_banned(addr.ip())));
+    if let (true, Some(addr)) = (allowed.is_empty(), banned.first()) {
+        return Err(HandshakingError::Banned(addr.ip()));
+    }
 
+    let attempt_config = config.clone();
+    let connect_timeout = connect_timeout.min(config.timeout);
+    let attempt = move |addr: SocketAddr, race_attempt: &Attempt| {
+        let probe = Probe::new(addr.to_string(), false, attempt_config.observer.as_deref());
+        probe.in_span(|| {
+            let conn = TcpStream::connect_timeout(&addr, connect_timeout).map_err(|e| probe.failed(HandshakeStage::Connect, e.into()))?;
+            // losing attempts aren't failures of the peer, so they aren't observed
+            if !race_attempt.register(&conn) {
+                return Err(IoError::new(ErrorKind::Interrupted, "another attempt won").into());
+            }
+            probe.connected();
+            exchange(conn, &hs_msg, &hs_bytes, &attempt_config, &probe)
+        })
+    };
+    race(allowed, attempt_delay, attempt, |addr, e| report(config, addr, e)).map_err(|e| e.unwrap_or_else(unresolved))
+}
+
+// Metrics count each call once, however many addresses or racing attempts it takes
+#[cfg(feature = "metrics")]
+fn measured<T, F>(is_inbound: bool, peer_hs: fn(&T) -> &Handshake, handshaking: F) -> Result<T, HandshakingError>
+where
+    F: FnOnce() -> Result<T, HandshakingError>,
+{
+    let started = std::time::Instant::now();
+    crate::metrics::record_attempt(is_inbound);
+    let res = handshaking();
+    match res.as_ref() {
+        Ok(res) => crate::metrics::record_success(is_inbound, peer_hs(res), started.elapsed()),
+        Err(e) => crate::metrics::record_failure(is_inbound, e),
+    }
+    res
+}
+
+#[cfg(not(feature = "metrics"))]
+fn measured<T, F>(_is_inbound: bool, _peer_hs: fn(&T) -> &Handshake, handshaking: F) -> Result<T, HandshakingError>
+where
+    F: FnOnce() -> Result<T, HandshakingError>,
+{
+    handshaking()
+}
+
+fn report(config: &HandshakeConfig, addr: SocketAddr, err: &HandshakingError) {
+    if let (Some(reputation), Some(misbehavior)) = (config.reputation.as_ref(), Misbehavior::from_error(err)) {
+        reputation.report(addr.ip(), misbehavior);
+    }
+}
+
+fn unresolved() -> HandshakingError {
+    IoError::new(ErrorKind::InvalidInput, "could not resolve to any addresses").into()
+}
+
+/// Connects to the host and exchanges handshakes. Through SOCKS5 proxy the host is resolved by the proxy, so DNS queries don't leak.
+pub fn handshaking_host(host: &str, port: u16, hs_msg: Handshake, config: &HandshakeConfig) -> Result<(TcpStream, Handshake), HandshakingError> {
+    measured(false, |(_, peer_hs): &(TcpStream, Handshake)| peer_hs, || proxied_handshaking(host, port, hs_msg, config))
+}
+
+fn proxied_handshaking(host: &str, port: u16, hs_msg: Handshake, config: &HandshakeConfig) -> Result<(TcpStream, Handshake), HandshakingError> {
+    if !config.dial.is_proxied() {
+        return dial_handshaking((host, port), hs_msg, config);
+    }
+    let hs_msg = config.dial.sanitize(hs_msg);
     let hs_bytes = hs_msg.serialize()?;
-    send_hs(&mut conn, &hs_bytes)?;
-    read_hs(&mut conn).map(|hs| (conn, hs))
+    let probe = Probe::new(format!("{}:{}", host, port), false, config.observer.as_deref());
+    probe.in_span(|| {
+        let conn = config
+            .dial
+            .connect(&Target::Host(host, port), config.timeout)
+            .map_err(|e| probe.failed(HandshakeStage::Connect, e))?;
+        probe.connected();
+        exchange(conn, &hs_msg, &hs_bytes, config, &probe)
+    })
+}
+
+/// Inbound peer, which passed handshaking.
+#[derive(Debug)]
+pub struct AcceptedPeer {
+    pub conn: TcpStream,
+    pub handshake: Handshake,
+    // Inbound slot, which should live as long as the connection. It's `None` without admission in the config
+    pub permit: Option<InboundPermit>,
+}
+
+/// Exchanges handshakes with the accepted inbound connection.
+///
+/// Banned and not admitted peers are rejected before reading their handshakes.
+pub fn accept_handshaking(conn: TcpStream, hs_msg: Handshake, config: &HandshakeConfig) -> Result<AcceptedPeer, HandshakingError> {
+    measured(true, |peer: &AcceptedPeer| &peer.handshake, || {
+        let addr = conn.peer_addr()?;
+        let probe = Probe::new(addr.to_string(), true, config.observer.as_deref());
+        probe.in_span(|| accept_observed(conn, addr, hs_msg, config, &probe))
+    })
+}
+
+fn accept_observed(mut conn: TcpStream, addr: SocketAddr, hs_msg: Handshake, config: &HandshakeConfig, probe: &Probe<'_>) -> Result<AcceptedPeer, HandshakingError> {
+    if config.reputation.as_ref().is_some_and(|r| r.is_banned(addr.ip())) {
+        return Err(probe.failed(HandshakeStage::Connect, HandshakingError::Banned(addr.ip())));
+    }
+    let permit = match config.admission.as_ref() {
+        Some(admission) => Some(
+            admission
+                .admit(addr, conn.try_clone().ok())
+                .map_err(|e| probe.failed(HandshakeStage::Connect, e.into()))?,
+        ),
+        None => None,
+    };
+    probe.connected();
+
+    let hs_msg = config.dial.sanitize(hs_msg);
+    let res = try_accepting(&mut conn, &hs_msg, config, probe);
+    match res {
+        Ok(handshake) => {
+            if let Some(permit) = permit.as_ref() {
+                permit.set_handshake(&handshake);
+            }
+            Ok(AcceptedPeer { conn, handshake, permit })
+        }
+        Err(e) => {
+            report(config, addr, &e);
+            Err(e)
+        }
+    }
+}
+
+fn try_accepting(conn: &mut TcpStream, hs_msg: &Handshake, config: &HandshakeConfig, probe: &Probe<'_>) -> Result<Handshake, HandshakingError> {
+    conn.set_read_timeout(Some(config.timeout)).map_err(|e| probe.failed(HandshakeStage::Receive, e.into()))?;
+    let peer_hs = receive_hs(conn, probe)?;
+    let peer_hs = validate_hs(hs_msg, peer_hs, config, probe)?;
+    let hs_bytes = hs_msg.serialize()?;
+    send_hs(conn, &hs_bytes).map_err(|e| probe.failed(HandshakeStage::Send, e))?;
+    probe.sent(hs_bytes.len());
+    probe.completed(&peer_hs);
+    Ok(peer_hs)
+}
+
+fn try_handshaking(addr: SocketAddr, hs_msg: &Handshake, hs_bytes: &[u8], config: &HandshakeConfig) -> Result<(TcpStream, Handshake), HandshakingError> {
+    let probe = Probe::new(addr.to_string(), false, config.observer.as_deref());
+    probe.in_span(|| {
+        if config.reputation.as_ref().is_some_and(|r| r.is_banned(addr.ip())) {
+            return Err(probe.failed(HandshakeStage::Connect, HandshakingError::Banned(addr.ip())));
+        }
+        let conn = config
+            .dial
+            .connect(&Target::Addr(addr), config.timeout)
+            .map_err(|e| probe.failed(HandshakeStage::Connect, e))?;
+        probe.connected();
+        exchange(conn, hs_msg, hs_bytes, config, &probe)
+    })
+}
+
+fn exchange(mut conn: TcpStream, hs_msg: &Handshake, hs_bytes: &[u8], config: &HandshakeConfig, probe: &Probe<'_>) -> Result<(TcpStream, Handshake), HandshakingError> {
+    conn.set_read_timeout(Some(config.timeout)).map_err(|e| probe.failed(HandshakeStage::Send, e.into()))?;
+
+    send_hs(&mut conn, hs_bytes).map_err(|e| probe.failed(HandshakeStage::Send, e))?;
+    probe.sent(hs_bytes.len());
+    let peer_hs = receive_hs(&mut conn, probe)?;
+    let peer_hs = validate_hs(hs_msg, peer_hs, config, probe)?;
+    probe.completed(&peer_hs);
+    Ok((conn, peer_hs))
+}
+
+fn receive_hs(conn: &mut TcpStream, probe: &Probe<'_>) -> Result<Handshake, HandshakingError> {
+    let data = read_hs(conn).map_err(|e| probe.failed(HandshakeStage::Receive, e))?;
+    probe.received(data.len());
+    // parsing only received bytes, so truncated handshakes aren't padded with zeroes
+    Handshake::parse(&data).map_err(|e| probe.failed(HandshakeStage::Parse, e.into()))
+}
+
+fn validate_hs(hs_msg: &Handshake, mut peer_hs: Handshake, config: &HandshakeConfig, probe: &Probe<'_>) -> Result<Handshake, HandshakingError> {
+    check_session(hs_msg, &peer_hs)
+        .and_then(|_| apply_addr_policy(config.declared_addr_policy, &mut peer_hs))
+        .map_err(|e| probe.failed(HandshakeStage::Validate, e))?;
+    Ok(peer_hs)
 }
 
 fn send_hs(conn: &mut TcpStream, data: &[u8]) -> Result<(), HandshakingError> {
@@ -33,13 +362,322 @@ fn send_hs(conn: &mut TcpStream, data: &[u8]) -> Result<(), HandshakingError> {
     conn.flush().map_err(HandshakingError::FailedIoOp)
 }
 
-fn read_hs(conn: &mut TcpStream) -> Result<Handshake, HandshakingError> {
-    let mut buf = vec![0; 100];
-    match conn.read(&mut buf) {
-        Ok(_) => {
-            conn.set_read_timeout(None).expect("internal error: zero duration passed as read timeout");
-            Handshake::parse(&buf).map_err(HandshakingError::MessageParseError)
+// Reference node handshakes fit into the first chunk, unless they have many features
+const HS_READ_CHUNK_SIZE: usize = 100;
+const MAX_HS_SIZE: usize = 8 * 1024;
+const LEGACY_HS_GRACE: Duration = Duration::from_millis(200);
+
+// Reads until the handshake with features count is received, the peer stops sending or the limit is reached.
+// Once the received bytes parse as a handshake without features count, the peer is given only a short grace
+// period to send the rest, so legacy handshakes don't wait for the whole read timeout.
+fn read_hs(conn: &mut TcpStream) -> Result<Vec<u8>, HandshakingError> {
+    let timeout = conn.read_timeout()?;
+    let mut data = Vec::new();
+    let mut buf = [0; HS_READ_CHUNK_SIZE];
+    loop {
+        let n = match conn.read(&mut buf) {
+            Ok(n) => n,
+            Err(e) if !data.is_empty() && matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => 0,
+            Err(e) => return Err(e.into()),
+        };
+        data.extend_from_slice(&buf[..n]);
+        let is_incomplete = matches!(Handshake::parse_strict(&data), Err(e) if e.is_truncated());
+        if n == 0 || !is_incomplete || data.len() >= MAX_HS_SIZE {
+            break;
         }
-        Err(e) => Err(HandshakingError::FailedIoOp(e)),
+        let is_legacy_complete = Handshake::parse(&data).is_ok();
+        conn.set_read_timeout(if is_legacy_complete { Some(LEGACY_HS_GRACE) } else { timeout })?;
+    }
+    conn.set_read_timeout(None)?;
+    Ok(data)
+}
+
+// Checks are made only if both handshakes have session id feature
+fn check_session(hs_msg: &Handshake, peer_hs: &Handshake) -> Result<(), HandshakingError> {
+    if let (Some(local), Some(peer)) = (session_id(hs_msg), session_id(peer_hs)) {
+        if local.magic != peer.magic {
+            return Err(HandshakingError::WrongNetwork(peer.magic.clone()));
+        }
+        if local.session_id == peer.session_id {
+            return Err(HandshakingError::SelfConnection);
+        }
+    }
+    Ok(())
+}
+
+fn apply_addr_policy(policy: DeclaredAddrPolicy, peer_hs: &mut Handshake) -> Result<(), HandshakingError> {
+    if policy == DeclaredAddrPolicy::Keep {
+        return Ok(());
+    }
+    if let Some(addr) = peer_hs.pub_address.take() {
+        match addr.is_routable() {
+            true => peer_hs.pub_address = Some(addr),
+            false if policy == DeclaredAddrPolicy::Reject => return Err(HandshakingError::UnroutableAddr(addr)),
+            false => {}
+        }
+    }
+    if let Some(features) = peer_hs.features.as_mut() {
+        features.retain(|f| !matches!(f, PeerFeature::LocalAddr(addr) if !addr.is_routable()));
+    }
+    Ok(())
+}
+
+pub(crate) fn session_id(hs: &Handshake) -> Option<&SessionId> {
+    hs.features.iter().flat_map(|f| f.iter()).find_map(|f| match f {
+        PeerFeature::SessionId(session_id) => Some(session_id),
+        _ => None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+    use std::time::{Duration, Instant};
+
+    use crate::features::PeerFeature;
+    use crate::models::MagicBytes;
+    use crate::testing::{create_hs, MockBehavior, MockPeer};
+
+    use super::*;
+
+    fn handshake_with(behavior: MockBehavior) -> Result<(TcpStream, Handshake), HandshakingError> {
+        let peer = MockPeer::spawn(create_hs("mock-node", 2), behavior).expect("internal error: can't spawn mock peer");
+        handshaking(peer.addr(), create_hs("client", 1))
+    }
+
+    #[test]
+    fn test_handshaking_honest_peer() {
+        let peer = MockPeer::spawn(create_hs("mock-node", 2), MockBehavior::Honest).expect("internal error: can't spawn mock peer");
+        let (_conn, hs) = handshaking(peer.addr(), create_hs("client", 1)).expect("internal error: handshaking failed");
+        assert_eq!(hs, create_hs("mock-node", 2));
+
+        let received = peer.received();
+        assert_eq!(received.len(), 1);
+        let client_hs = Handshake::parse(&received[0]).expect("internal error: can't parse sent hs");
+        assert_eq!(client_hs, create_hs("client", 1));
+    }
+
+    #[test]
+    fn test_handshaking_slow_peer() {
+        let res = handshake_with(MockBehavior::Delay(Duration::from_millis(200)));
+        assert!(res.is_ok());
+    }
+
+    #[test]
+    fn test_handshaking_peer_closes_early() {
+        let res = handshake_with(MockBehavior::CloseEarly);
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn test_handshaking_truncated_response() {
+        let res = handshake_with(MockBehavior::Truncate(20));
+        assert!(matches!(res, Err(HandshakingError::MessageParseError(_))));
+    }
+
+    #[test]
+    fn test_handshaking_trailing_junk() {
+        let (_conn, hs) = handshake_with(MockBehavior::TrailingJunk(8)).expect("internal error: handshaking failed");
+        assert_eq!(hs, create_hs("mock-node", 2));
+    }
+
+    #[test]
+    fn test_handshaking_without_features_count() {
+        let started_at = Instant::now();
+        let (_conn, hs) = handshake_with(MockBehavior::WithoutFeaturesCount).expect("internal error: handshaking failed");
+        assert!(started_at.elapsed() < HandshakeConfig::DEFAULT_TIMEOUT / 2);
+        assert_eq!(hs, Handshake { features: None, ..create_hs("mock-node", 2) });
+    }
+
+    #[test]
+    fn test_handshaking_oversized() {
+        let (_conn, hs) = handshake_with(MockBehavior::Oversized(3 * HS_READ_CHUNK_SIZE)).expect("internal error: handshaking failed");
+        let local_addrs = hs.features.iter().flat_map(|f| f.iter()).filter(|f| matches!(f, PeerFeature::LocalAddr(_))).count();
+        assert!(hs.serialize().expect("internal error: can't serialize hs").len() >= 3 * HS_READ_CHUNK_SIZE);
+        assert!(local_addrs > 0);
+        assert_eq!(hs.peer_name, create_hs("mock-node", 2).peer_name);
+    }
+
+    #[test]
+    fn test_handshaking_wrong_magic() {
+        let wrong_magic = MagicBytes([2, 0, 0, 2]);
+        let res = handshake_with(MockBehavior::WrongMagic(wrong_magic.clone()));
+        assert!(matches!(res, Err(HandshakingError::WrongNetwork(magic)) if magic == wrong_magic));
+    }
+
+    #[test]
+    fn test_handshaking_self_connection() {
+        let peer = MockPeer::spawn(create_hs("client", 1), MockBehavior::Honest).expect("internal error: can't spawn mock peer");
+        let res = handshaking(peer.addr(), create_hs("client", 1));
+        assert!(matches!(res, Err(HandshakingError::SelfConnection)));
+    }
+
+    #[test]
+    fn test_handshaking_bans_misbehaving_peer() {
+        let reputation = Arc::new(PeerReputation::default());
+        let config = HandshakeConfig::new().with_reputation(Arc::clone(&reputation));
+        let peer = MockPeer::spawn(create_hs("mock-node", 2), MockBehavior::Truncate(20)).expect("internal error: can't spawn mock peer");
+
+        // two malformed handshakes reach the ban threshold
+        for _ in 0..2 {
+            let res = handshaking_with(peer.addr(), create_hs("client", 1), &config);
+            assert!(matches!(res, Err(HandshakingError::MessageParseError(_))));
+        }
+        assert!(reputation.is_banned(peer.addr().ip()));
+        let res = handshaking_with(peer.addr(), create_hs("client", 1), &config);
+        assert!(matches!(res, Err(HandshakingError::Banned(_))));
+        // socket isn't spent on the banned peer
+        assert_eq!(peer.received().len(), 2);
+    }
+
+    #[test]
+    fn test_accept_handshaking_with_admission() {
+        use std::net::TcpListener;
+        use std::thread;
+
+        use crate::admission::{AdmissionConfig, AdmissionError};
+
+        let listener = TcpListener::bind("127.0.0.1:0").expect("internal error: can't bind listener");
+        let addr = listener.local_addr().expect("internal error: no local addr");
+        let admission = InboundAdmission::new(AdmissionConfig { max_per_ip: 1, ..AdmissionConfig::default() });
+        let config = HandshakeConfig::new().with_admission(admission.clone());
+        let client = thread::spawn(move || {
+            let first = handshaking(addr, create_hs("client", 1));
+            let second = handshaking(addr, create_hs("client", 1));
+            (first.map(|(_, hs)| hs), second.is_err())
+        });
+
+        let accept = || {
+            let (conn, _) = listener.accept().expect("internal error: can't accept");
+            accept_handshaking(conn, create_hs("server", 3), &config)
+        };
+        let accepted = accept().expect("internal error: accepting failed");
+        assert_eq!(accepted.handshake, create_hs("client", 1));
+        assert_eq!(admission.inbound_count(), 1);
+        let res = accept();
+        assert!(matches!(res, Err(HandshakingError::Rejected(AdmissionError::TooManyFromIp(_)))));
+
+        let (first, is_second_failed) = client.join().expect("internal error: client panicked");
+        assert_eq!(first.expect("internal error: handshaking failed"), create_hs("server", 3));
+        assert!(is_second_failed);
+        drop(accepted);
+        assert_eq!(admission.inbound_count(), 0);
+    }
+
+    #[test]
+    fn test_declared_addr_policy() {
+        let private_addr = PeerAddr("192.168.1.10:9030".parse().expect("internal error: invalid socket addr"));
+        let mut peer_hs = create_hs("mock-node", 2);
+        peer_hs.pub_address = Some(private_addr.clone());
+        peer_hs.features.as_mut().expect("internal error: no features").push(PeerFeature::LocalAddr(private_addr.clone()));
+        let peer = MockPeer::spawn(peer_hs.clone(), MockBehavior::Honest).expect("internal error: can't spawn mock peer");
+        let handshake = |policy| handshaking_with(peer.addr(), create_hs("client", 1), &HandshakeConfig::new().with_declared_addr_policy(policy));
+
+        let (_conn, kept) = handshake(DeclaredAddrPolicy::Keep).expect("internal error: handshaking failed");
+        assert_eq!(kept, peer_hs);
+        let (_conn, dropped) = handshake(DeclaredAddrPolicy::Drop).expect("internal error: handshaking failed");
+        assert_eq!(dropped.pub_address, None);
+        assert_eq!(dropped.features, create_hs("mock-node", 2).features);
+        let res = handshake(DeclaredAddrPolicy::Reject);
+        assert!(matches!(res, Err(HandshakingError::UnroutableAddr(addr)) if addr == private_addr));
+    }
+
+    #[test]
+    fn test_happy_eyeballs_first_handshake_wins() {
+        use std::time::Instant;
+
+        // slow peer accepts the connection first, but answers after the fast one
+        let slow = MockPeer::spawn(create_hs("slow-node", 4), MockBehavior::Delay(Duration::from_secs(2))).expect("internal error: can't spawn mock peer");
+        let fast = MockPeer::spawn(create_hs("fast-node", 5), MockBehavior::Honest).expect("internal error: can't spawn mock peer");
+        let config = HandshakeConfig::new().with_dial(DialStrategy::HappyEyeballs { attempt_delay: Duration::from_millis(50), connect_timeout: DialStrategy::DEFAULT_CONNECT_TIMEOUT });
+
+        let started = Instant::now();
+        let (_conn, hs) = handshaking_with(&[slow.addr(), fast.addr()][..], create_hs("client", 1), &config).expect("internal error: handshaking failed");
+        assert_eq!(hs, create_hs("fast-node", 5));
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert_eq!(slow.received().len(), 1);
+
+        // losing connection is shut down, so the slow peer sees EOF after responding
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while slow.closed() == 0 && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        assert_eq!(slow.closed(), 1);
+    }
+
+    #[test]
+    fn test_happy_eyeballs_failure_starts_next_attempt() {
+        use std::net::TcpListener;
+        use std::time::Instant;
+
+        let closed = TcpListener::bind("127.0.0.1:0").and_then(|l| l.local_addr()).expect("internal error: can't bind listener");
+        let peer = MockPeer::spawn(create_hs("mock-node", 2), MockBehavior::Honest).expect("internal error: can't spawn mock peer");
+        let config = HandshakeConfig::new().with_dial(DialStrategy::HappyEyeballs { attempt_delay: Duration::from_secs(10), connect_timeout: DialStrategy::DEFAULT_CONNECT_TIMEOUT });
+
+        let started = Instant::now();
+        let res = handshaking_with(&[closed, peer.addr()][..], create_hs("client", 1), &config);
+        assert!(res.is_ok());
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        let res = handshaking_with(closed, create_hs("client", 1), &config);
+        assert!(matches!(res, Err(HandshakingError::FailedIoOp(_))));
+    }
+
+    #[test]
+    fn test_scripted_peer() {
+        let script = vec![MockBehavior::CloseEarly, MockBehavior::Honest];
+        let peer = MockPeer::spawn_scripted(create_hs("mock-node", 2), script).expect("internal error: can't spawn mock peer");
+        assert!(handshaking(peer.addr(), create_hs("client", 1)).is_err());
+        assert!(handshaking(peer.addr(), create_hs("client", 1)).is_ok());
+        assert!(handshaking(peer.addr(), create_hs("client", 1)).is_ok());
+    }
+
+    #[derive(Debug, Default)]
+    struct RecordingObserver {
+        events: Mutex<Vec<String>>,
+    }
+
+    impl HandshakeObserver for RecordingObserver {
+        fn on_connected(&self, _peer: &str, _elapsed: Duration) {
+            self.events.lock().expect("internal error: poisoned").push("connected".to_string());
+        }
+
+        fn on_sent(&self, _peer: &str, bytes: usize) {
+            self.events.lock().expect("internal error: poisoned").push(format!("sent {}", bytes));
+        }
+
+        fn on_received(&self, _peer: &str, _bytes: usize, _reply_time: Duration) {
+            self.events.lock().expect("internal error: poisoned").push("received".to_string());
+        }
+
+        fn on_completed(&self, _peer: &str, hs: &Handshake, _elapsed: Duration) {
+            self.events.lock().expect("internal error: poisoned").push(format!("completed {}", hs.peer_name));
+        }
+
+        fn on_failed(&self, _peer: &str, stage: HandshakeStage, err: &HandshakingError) {
+            self.events.lock().expect("internal error: poisoned").push(format!("failed {} {}", stage.as_str(), err.kind()));
+        }
+    }
+
+    #[test]
+    fn test_observer_reports_stages() {
+        let observer = Arc::new(RecordingObserver::default());
+        let config = HandshakeConfig::new().with_observer(Arc::clone(&observer) as Arc<dyn HandshakeObserver>);
+        let sent_len = create_hs("client", 1).serialize().expect("internal error: can't serialize hs").len();
+
+        let peer = MockPeer::spawn(create_hs("mock-node", 2), MockBehavior::Honest).expect("internal error: can't spawn mock peer");
+        handshaking_with(peer.addr(), create_hs("client", 1), &config).expect("internal error: handshaking failed");
+        let events = std::mem::take(&mut *observer.events.lock().expect("internal error: poisoned"));
+        assert_eq!(events, vec!["connected".to_string(), format!("sent {}", sent_len), "received".to_string(), "completed mock-node".to_string()]);
+
+        let peer = MockPeer::spawn(create_hs("mock-node", 2), MockBehavior::Truncate(20)).expect("internal error: can't spawn mock peer");
+        assert!(handshaking_with(peer.addr(), create_hs("client", 1), &config).is_err());
+        let events = std::mem::take(&mut *observer.events.lock().expect("internal error: poisoned"));
+        assert_eq!(events.last().map(String::as_str), Some("failed parse parse"));
+
+        let peer = MockPeer::spawn(create_hs("client", 1), MockBehavior::Honest).expect("internal error: can't spawn mock peer");
+        assert!(handshaking_with(peer.addr(), create_hs("client", 1), &config).is_err());
+        let events = std::mem::take(&mut *observer.events.lock().expect("internal error: poisoned"));
+        assert_eq!(events.last().map(String::as_str), Some("failed validate self_connection"));
     }
 }
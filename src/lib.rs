@@ -1,8 +1,29 @@
-pub use hs::{handshaking, HandshakingError};
+#![cfg_attr(not(test), deny(clippy::panic, clippy::expect_used, clippy::unwrap_used))]
+
+pub use hs::{accept_handshaking, handshaking, handshaking_host, handshaking_with, AcceptedPeer, DeclaredAddrPolicy, HandshakeConfig, HandshakingError};
 
 pub mod messages;
 pub mod models;
 pub mod features;
 pub mod encoding;
+pub mod peer_book;
+pub mod addr_manager;
+pub mod address_verifier;
+pub mod external_address;
+pub mod dial;
+pub mod reputation;
+pub mod connection_manager;
+pub mod admission;
+pub mod observer;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+#[cfg(feature = "codec")]
+pub mod codec;
+#[cfg(feature = "capture")]
+pub mod capture;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+#[cfg(any(test, feature = "arbitrary"))]
+mod arbitrary;
 mod hs;
 mod utils;
@@ -2,20 +2,24 @@ use std::convert::TryFrom;
 use std::io;
 use std::ops::{Deref, DerefMut};
 
-use sigma_ser::vlq_encode::{ReadSigmaVlqExt, VlqEncodingError, WriteSigmaVlqExt};
 use thiserror::Error;
 
-use crate::encoding::vlq::{default_vlq_reader, default_vlq_writer, TryFromVlq, TryIntoVlq};
-use crate::features::{Features, FeaturesError, PeerFeature};
+use crate::encoding::vlq::{default_vlq_reader, default_vlq_writer, ReadVlqExt, SliceReader, TryFromVlq, TryIntoVlq, WriteVlqExt};
+use crate::encoding::VlqDecodeError;
+use crate::features::{FeatureSerializeError, Features, FeaturesError, PeerFeature};
 use crate::models::{ModelParseError, ModelSerializeError, PeerAddr, ShortString, Version};
 use crate::utils::make_timestamp;
 
+pub use annotation::{HsAnnotation, HsField, HsFieldKind};
+pub use builder::{HandshakeBuildError, HandshakeBuilder};
+pub use peers::Peers;
 use spec_reader::HSSpecReader;
 pub use spec_reader::HsSpecReaderError;
 use spec_writer::HSSpecWriter;
 pub use spec_writer::HsSpecWriterError;
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Handshake {
     pub agent_name: ShortString,
     pub version: Version,
@@ -25,11 +29,42 @@ pub struct Handshake {
 }
 
 impl Handshake {
+    pub fn builder() -> HandshakeBuilder {
+        HandshakeBuilder::new()
+    }
+
     // todo-crucial max size?
     pub fn parse(data: &[u8]) -> Result<Self, HsSpecReaderError> {
+        Self::read(&mut HSSpecReader::new(default_vlq_reader(data)), false)
+    }
+
+    // Parses handshake from the beginning of the data, returning it with its length.
+    // Features count is required, because otherwise the end of a handshake received from a stream is ambiguous.
+    pub(crate) fn parse_strict(data: &[u8]) -> Result<(Self, usize), HsSpecReaderError> {
         let mut hs_reader = HSSpecReader::new(default_vlq_reader(data));
+        let hs = Self::read(&mut hs_reader, true)?;
+        Ok((hs, hs_reader.position()))
+    }
 
+    /// Splits raw handshake bytes into labelled byte spans using the same reader as [`Handshake::parse`].
+    pub fn annotate(data: &[u8]) -> Result<HsAnnotation, HsSpecReaderError> {
+        annotation::annotate(data)
+    }
+
+    pub fn serialize(&self) -> Result<Vec<u8>, HsSpecWriterError> {
+        let mut hs_writer = HSSpecWriter::new(default_vlq_writer(Vec::new()));
+        self.write(&mut hs_writer)?;
+        Ok(hs_writer.into_inner().into_inner())
+    }
+
+    // Reference node always sends features count, but it's optional for the `parse` backward compatibility
+    fn read(hs_reader: &mut HSSpecReader, is_features_count_required: bool) -> Result<Self, HsSpecReaderError> {
         let _timestamp = hs_reader.get_u64()?;
+        Self::read_spec(hs_reader, is_features_count_required)
+    }
+
+    // Peer spec is a handshake without timestamp, it's also sent in `Peers` message
+    fn read_spec(hs_reader: &mut HSSpecReader, is_features_count_required: bool) -> Result<Self, HsSpecReaderError> {
         let agent_name = hs_reader.read_short_string()?;
         let version = hs_reader.read_version()?;
         let peer_name = hs_reader.read_short_string()?;
@@ -45,16 +80,15 @@ impl Handshake {
         let features = hs_reader
             // moving out unrecognized features
             // todo-minor move to spec reader?
-            .read_features()?
-            .map(|mut f| {
+            .read_features(is_features_count_required)?
+            .and_then(|mut f| {
                 f.retain(|pf| pf != &PeerFeature::Unrecognized);
-                if f.len() > 0 {
+                if !f.is_empty() {
                     Some(f)
                 } else {
                     None
                 }
-            })
-            .flatten();
+            });
 
         Ok(Handshake {
             agent_name,
@@ -65,10 +99,12 @@ impl Handshake {
         })
     }
 
-    pub fn serialize(&self) -> Result<Vec<u8>, HsSpecWriterError> {
-        let mut hs_writer = HSSpecWriter::new(default_vlq_writer(Vec::new()));
-
+    fn write<W: WriteVlqExt>(&self, hs_writer: &mut HSSpecWriter<W>) -> Result<(), HsSpecWriterError> {
         hs_writer.put_u64(make_timestamp())?;
+        self.write_spec(hs_writer)
+    }
+
+    fn write_spec<W: WriteVlqExt>(&self, hs_writer: &mut HSSpecWriter<W>) -> Result<(), HsSpecWriterError> {
         hs_writer.write_short_string(&self.agent_name)?;
         hs_writer.write_version(&self.version)?;
         hs_writer.write_short_string(&self.peer_name)?;
@@ -79,14 +115,24 @@ impl Handshake {
         } else {
             hs_writer.put_u8(0)?;
         }
+        // reference node requires features count even if there are no features, so `None` is written as 0 count.
+        // Handshakes without features used to end after the public address flag, `parse` still reads them
         if let Some(features) = self.features.as_ref() {
             hs_writer.write_features(features)?;
+        } else {
+            hs_writer.put_u8(0)?;
         }
 
-        Ok(hs_writer.into_inner().into_inner())
+        Ok(())
     }
 }
 
+mod annotation;
+#[cfg(feature = "bytes")]
+mod buf;
+mod builder;
+mod peers;
+
 mod spec_reader {
     use super::*;
 
@@ -101,21 +147,26 @@ mod spec_reader {
         TooShortPeerAddrDataLength(u8, u8),
         #[error("Can't read feature: {0}")]
         CannotReadPeerFeatureFromBytes(#[from] FeaturesError),
-        #[error("Decoding data failed")]
-        // todo-tmp VlqEncodingError doesn't impl Error. VlqDecodingError::VlqDecodingError tells us nothing
-        CannotVlqDecodeData(VlqEncodingError),
+        #[error("Decoding data failed: {0}")]
+        CannotVlqDecodeData(#[from] VlqDecodeError),
+        #[error("Received {0} peer specs, maximum allowed {}", Peers::MAX_LEN)]
+        TooMuchPeerSpecs(u32),
     }
 
-    pub(super) struct HSSpecReader<R: ReadSigmaVlqExt>(R);
-
-    // tmp, until VlqEncodingError is fixed
-    impl From<VlqEncodingError> for HsSpecReaderError {
-        fn from(err: VlqEncodingError) -> Self {
-            HsSpecReaderError::CannotVlqDecodeData(err)
+    impl HsSpecReaderError {
+        // Whether the error is caused by the end of data, so it may be parsed, when more data is received
+        pub(crate) fn is_truncated(&self) -> bool {
+            match self {
+                HsSpecReaderError::CannotReadBytes(e) => e.kind() == io::ErrorKind::UnexpectedEof,
+                HsSpecReaderError::CannotVlqDecodeData(VlqDecodeError::Truncated) => true,
+                _ => false,
+            }
         }
     }
 
-    impl<R: ReadSigmaVlqExt> HSSpecReader<R> {
+    pub(super) struct HSSpecReader<'a>(SliceReader<'a>);
+
+    impl<'a> HSSpecReader<'a> {
         // Used due to public address (de)serialization bug in the reference ergo-node:
         // port length is encoded as 4 bytes rather than 2: https://github.com/hyperledger-labs/Scorex/blob/30f3bea5ddb660f479964b7879912cebc4ee467e/src/main/scala/scorex/core/network/PeerSpec.scala#L49
         const PORT_EXCESS_BYTES: u8 = 2;
@@ -133,7 +184,7 @@ mod spec_reader {
         //     assert_eq!(10, a);
         //     assert_eq!(10, b);
         // }
-        pub(super) fn new(reader: R) -> Self {
+        pub(super) fn new(reader: SliceReader<'a>) -> Self {
             Self(reader)
         }
 
@@ -145,7 +196,7 @@ mod spec_reader {
 
         pub(super) fn read_version(&mut self) -> Result<Version, HsSpecReaderError> {
             let mut v = Version::default();
-            self.read_exact(&mut v.0)?;
+            v.0.copy_from_slice(self.read_model_data(Version::SIZE)?);
             Ok(v)
         }
 
@@ -158,43 +209,51 @@ mod spec_reader {
             Err(HsSpecReaderError::TooShortPeerAddrDataLength(len, PeerAddr::SIZE_IPv4_SOCKET as u8 + Self::PORT_EXCESS_BYTES))
         }
 
-        pub(super) fn read_features(&mut self) -> Result<Option<Features>, HsSpecReaderError> {
-            let features_num = self.get_u8().ok();
+        pub(super) fn read_features(&mut self, is_count_required: bool) -> Result<Option<Features>, HsSpecReaderError> {
+            let features_num = if is_count_required {
+                Some(self.get_u8()?)
+            } else {
+                self.get_u8().ok()
+            };
             if let Some(mut num) = features_num {
                 let mut features = Vec::with_capacity(num as usize);
                 while num != 0 {
-                    let feature_id = self.get_u8()?;
-                    let feature_data = {
-                        let len = self.get_u16()?;
-                        self.read_model_data(len as usize)?
-                    };
+                    let (feature_id, feature_data) = self.read_raw_feature()?;
                     let feature_res = PeerFeature::try_from((feature_id, feature_data))?;
                     features.push(feature_res);
                     num -= 1;
                 }
                 return Features::try_new(features)
-                    .map(|f| Some(f))
+                    .map(Some)
                     .map_err(HsSpecReaderError::CannotReadPeerFeatureFromBytes);
             }
             Ok(None)
         }
 
-        fn read_model_data(&mut self, len: usize) -> Result<Vec<u8>, HsSpecReaderError> {
-            let mut buf = vec![0; len];
-            self.read_exact(&mut buf)?;
-            Ok(buf)
+        // Reads feature id and its length-prefixed data without decoding it
+        pub(super) fn read_raw_feature(&mut self) -> Result<(u8, &'a [u8]), HsSpecReaderError> {
+            let feature_id = self.get_u8()?;
+            let feature_data = {
+                let len = self.get_u16()?;
+                self.read_model_data(len as usize)?
+            };
+            Ok((feature_id, feature_data))
+        }
+
+        fn read_model_data(&mut self, len: usize) -> Result<&'a [u8], HsSpecReaderError> {
+            self.0.read_slice(len).map_err(HsSpecReaderError::CannotReadBytes)
         }
     }
 
-    impl<R: ReadSigmaVlqExt> Deref for HSSpecReader<R> {
-        type Target = R;
+    impl<'a> Deref for HSSpecReader<'a> {
+        type Target = SliceReader<'a>;
 
         fn deref(&self) -> &Self::Target {
             &self.0
         }
     }
 
-    impl<R: ReadSigmaVlqExt> DerefMut for HSSpecReader<R> {
+    impl DerefMut for HSSpecReader<'_> {
         fn deref_mut(&mut self) -> &mut Self::Target {
             &mut self.0
         }
@@ -204,6 +263,7 @@ mod spec_reader {
 mod spec_writer {
     use super::*;
 
+    #[allow(clippy::enum_variant_names)]
     #[derive(Error, Debug)]
     pub enum HsSpecWriterError {
         #[error("Can't write model to buffer: {0}")]
@@ -212,11 +272,15 @@ mod spec_writer {
         CannotWriteBytes(#[from] io::Error),
         #[error("Can't write feature: {0}")]
         CannotWritePeerFeature(#[from] FeaturesError),
+        #[error("Can't write short string with length {0}, maximum allowed {}", ShortString::MAX_SIZE)]
+        TooLongShortString(usize),
+        #[error("Can't write {0} peer specs, maximum allowed {}", Peers::MAX_LEN)]
+        TooMuchPeerSpecs(usize),
     }
 
-    pub(super) struct HSSpecWriter<W: WriteSigmaVlqExt>(W);
+    pub(super) struct HSSpecWriter<W: WriteVlqExt>(W);
 
-    impl<W: WriteSigmaVlqExt> HSSpecWriter<W> {
+    impl<W: WriteVlqExt> HSSpecWriter<W> {
         // Used due to public address (de)serialization bug in the reference ergo-node:
         // port length is encoded as 4 bytes rather than 2: https://github.com/hyperledger-labs/Scorex/blob/30f3bea5ddb660f479964b7879912cebc4ee467e/src/main/scala/scorex/core/network/PeerSpec.scala#L49
         const PORT_EXCESS_BYTES: u8 = 2;
@@ -247,8 +311,9 @@ mod spec_writer {
 
         pub(super) fn write_short_string(&mut self, short_string: &ShortString) -> Result<(), HsSpecWriterError> {
             let data = short_string.as_bytes();
-            self.put_u8(data.len() as u8)?;
-            self.write_all(&data).map_err(HsSpecWriterError::CannotWriteBytes)
+            let len = u8::try_from(data.len()).map_err(|_| HsSpecWriterError::TooLongShortString(data.len()))?;
+            self.put_u8(len)?;
+            self.write_all(data).map_err(HsSpecWriterError::CannotWriteBytes)
         }
 
         pub(super) fn write_version(&mut self, version: &Version) -> Result<(), HsSpecWriterError> {
@@ -263,22 +328,28 @@ mod spec_writer {
         }
 
         pub(super) fn write_features(&mut self, features: &Features) -> Result<(), HsSpecWriterError> {
-            self.put_u8(features.len() as u8)?;
+            // features may be added after creation through `DerefMut`
+            let len = u8::try_from(features.len()).map_err(|_| FeaturesError::TooMuchPeerFeatures(features.len()))?;
+            self.put_u8(len)?;
             for feature in features.iter() {
                 self.write_feature(feature)?;
             }
             Ok(())
         }
 
-        fn write_feature(&mut self, feature: &PeerFeature) -> Result<(), HsSpecWriterError> {
-            self.put_u8(feature.get_id())?;
+        pub(super) fn write_feature(&mut self, feature: &PeerFeature) -> Result<(), HsSpecWriterError> {
+            // fails for unrecognized features, so id is always present
             let data = feature.try_into_vlq()?;
+            let id = feature
+                .get_id()
+                .ok_or(FeaturesError::CannotSerializeFeature(FeatureSerializeError::UnrecognizedFeature))?;
+            self.put_u8(id)?;
             self.put_u16(data.len() as u16)?;
             self.write_all(&data).map_err(HsSpecWriterError::CannotWriteBytes)
         }
     }
 
-    impl<W: WriteSigmaVlqExt> Deref for HSSpecWriter<W> {
+    impl<W: WriteVlqExt> Deref for HSSpecWriter<W> {
         type Target = W;
 
         fn deref(&self) -> &Self::Target {
@@ -286,7 +357,7 @@ mod spec_writer {
         }
     }
 
-    impl<W: WriteSigmaVlqExt> DerefMut for HSSpecWriter<W> {
+    impl<W: WriteVlqExt> DerefMut for HSSpecWriter<W> {
         fn deref_mut(&mut self) -> &mut Self::Target {
             &mut self.0
         }
@@ -409,6 +480,12 @@ mod tests {
         vec![case1, case2, case3, case4, case5]
     }
 
+    fn timestamp_len(hs_bytes: &[u8]) -> usize {
+        let mut reader = default_vlq_reader(hs_bytes);
+        reader.get_u64().expect("internal error: can't read timestamp");
+        reader.position()
+    }
+
     fn run_test(hs: Handshake, hs_bytes: Vec<u8>) {
         let hs_actual = Handshake::parse(&hs_bytes);
         assert!(hs_actual.is_ok());
@@ -419,7 +496,7 @@ mod tests {
         assert!(hs_bytes_actual.is_ok());
         let hs_bytes_actual = hs_bytes_actual.expect("internal error: can't serialize hs msg");
         // avoiding timestamp bytes
-        assert_eq!(&hs_bytes_actual[5..], &hs_bytes[5..]);
+        assert_eq!(&hs_bytes_actual[timestamp_len(&hs_bytes_actual)..], &hs_bytes[timestamp_len(&hs_bytes)..]);
     }
 
     #[test]
@@ -446,4 +523,124 @@ mod tests {
             run_test(hs_expected, hs_bytes)
         }
     }
+
+    #[test]
+    fn test_parse_strict() {
+        for (hs_expected, hs_bytes) in real_app_test_cases() {
+            let (hs, len) = Handshake::parse_strict(&hs_bytes).expect("internal error: can't parse hs bytes");
+            assert_eq!(hs, hs_expected);
+            assert_eq!(len, hs_bytes.len());
+            for truncated_len in 0..hs_bytes.len() {
+                let err = Handshake::parse_strict(&hs_bytes[..truncated_len]).expect_err("internal error: truncated hs was parsed");
+                assert!(err.is_truncated());
+            }
+        }
+    }
+
+    #[test]
+    fn test_serialize_without_features() {
+        let hs = create_hs("ergoref", Version([4, 0, 5]), "ergo-node", None, None);
+        let hs_bytes = hs.serialize().expect("internal error: can't serialize hs msg");
+        // features count is always written
+        assert_eq!(hs_bytes.last(), Some(&0));
+        let (hs_actual, len) = Handshake::parse_strict(&hs_bytes).expect("internal error: can't parse hs bytes");
+        assert_eq!(hs_actual, hs);
+        assert_eq!(len, hs_bytes.len());
+    }
+
+    #[test]
+    fn test_features_count_wire_format() {
+        let hs = create_hs("ergoref", Version([4, 0, 5]), "ergo-node", None, None);
+        let hs_bytes = hs.serialize().expect("internal error: can't serialize hs msg");
+        let mut expected_spec = vec![7];
+        expected_spec.extend_from_slice(b"ergoref");
+        expected_spec.extend_from_slice(&[4, 0, 5, 9]);
+        expected_spec.extend_from_slice(b"ergo-node");
+        // no public address, zero features count
+        expected_spec.extend_from_slice(&[0, 0]);
+        assert!(hs_bytes.ends_with(&expected_spec));
+
+        // handshake serialized before the count was always written
+        let without_count = &hs_bytes[..hs_bytes.len() - 1];
+        assert_eq!(Handshake::parse(without_count).expect("internal error: can't parse hs bytes"), hs);
+        let res = Handshake::parse_strict(without_count);
+        assert!(matches!(res, Err(e) if e.is_truncated()));
+    }
+
+    #[test]
+    fn test_serialize_too_much_features() {
+        let mut features = Features::try_new(Vec::new()).expect("internal error: invalid features");
+        let mode = PeerFeature::Mode(Mode { state_type: 0, is_verifying: true, nipopow_suffix_len: None, blocks_to_keep: -1 });
+        features.extend(std::iter::repeat_n(mode, Features::MAX_LEN + 1));
+        let hs = create_hs("ergoref", Version([4, 0, 5]), "ergo-node", None, Some(features));
+        assert!(matches!(
+            hs.serialize(),
+            Err(HsSpecWriterError::CannotWritePeerFeature(FeaturesError::TooMuchPeerFeatures(256)))
+        ));
+    }
+
+    #[test]
+    fn test_serialize_unrecognized_feature() {
+        let features = Features::try_new(vec![PeerFeature::Unrecognized]).expect("internal error: invalid features");
+        let hs = create_hs("ergoref", Version([4, 0, 5]), "ergo-node", None, Some(features));
+        assert!(matches!(
+            hs.serialize(),
+            Err(HsSpecWriterError::CannotWritePeerFeature(FeaturesError::CannotSerializeFeature(
+                FeatureSerializeError::UnrecognizedFeature
+            )))
+        ));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_round_trip() {
+        for (hs_expected, _) in real_app_test_cases() {
+            let json = serde_json::to_string(&hs_expected).expect("internal error: can't serialize hs to json");
+            let hs_actual: Handshake = serde_json::from_str(&json).expect("internal error: can't deserialize hs from json");
+            assert_eq!(hs_actual, hs_expected);
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_human_friendly_forms() {
+        let hs = create_hs(
+            "ergoref",
+            Version([4, 0, 5]),
+            "ergo-mainnet-4.0.1",
+            Some(create_peer_addr("213.239.193.208:9030")),
+            Some(create_features(vec![
+                create_mode_pf(0, true, None, -1),
+                create_local_addr_pf("127.0.0.1:9006"),
+                create_session_id_pf(MagicBytes([1, 0, 2, 4]), 42)
+            ]))
+        );
+        let expected = serde_json::json!({
+            "agent_name": "ergoref",
+            "version": "4.0.5",
+            "peer_name": "ergo-mainnet-4.0.1",
+            "pub_address": "213.239.193.208:9030",
+            "features": [
+                {"type": "mode", "value": {"state_type": 0, "is_verifying": true, "nipopow_suffix_len": null, "blocks_to_keep": -1}},
+                {"type": "local_addr", "value": "127.0.0.1:9006"},
+                {"type": "session_id", "value": {"magic": "01000204", "session_id": 42}}
+            ]
+        });
+        let actual = serde_json::to_value(&hs).expect("internal error: can't serialize hs to json");
+        assert_eq!(actual, expected);
+        let hs_actual: Handshake = serde_json::from_value(expected).expect("internal error: can't deserialize hs from json");
+        assert_eq!(hs_actual, hs);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_rejects_invalid_models() {
+        assert!(serde_json::from_str::<Version>("\"4.0\"").is_err());
+        assert!(serde_json::from_str::<MagicBytes>("\"010002\"").is_err());
+        assert!(serde_json::from_str::<PeerAddr>("\"localhost\"").is_err());
+        let long_name = format!("\"{}\"", "a".repeat(ShortString::MAX_SIZE + 1));
+        assert!(serde_json::from_str::<ShortString>(&long_name).is_err());
+        let too_many_features = format!("[{}]", vec!["{\"type\": \"unrecognized\"}"; Features::MAX_LEN + 1].join(","));
+        assert!(serde_json::from_str::<Features>(&too_many_features).is_err());
+    }
 }
@@ -0,0 +1,272 @@
+use std::fmt;
+use std::ops::Range;
+
+use super::*;
+
+/// Labelled byte spans of a raw handshake message.
+#[derive(Debug, PartialEq, Eq)]
+pub struct HsAnnotation {
+    data: Vec<u8>,
+    fields: Vec<HsField>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct HsField {
+    pub kind: HsFieldKind,
+    pub range: Range<usize>,
+    pub value: String,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum HsFieldKind {
+    Timestamp,
+    AgentNameLength,
+    AgentName,
+    Version,
+    PeerNameLength,
+    PeerName,
+    PubAddressFlag,
+    PubAddressLength,
+    PubAddress,
+    FeaturesCount,
+    FeatureId(usize),
+    FeatureLength(usize),
+    FeaturePayload(usize),
+    // Bytes left after the last feature, which are ignored by the parser
+    Trailing,
+}
+
+impl HsAnnotation {
+    pub fn fields(&self) -> &[HsField] {
+        &self.fields
+    }
+
+    pub fn bytes(&self, field: &HsField) -> &[u8] {
+        &self.data[field.range.clone()]
+    }
+}
+
+// Annotation steps mirror `Handshake::parse`, reader position after each step is the end of the read span.
+struct Annotator<'a> {
+    reader: HSSpecReader<'a>,
+    fields: Vec<HsField>,
+}
+
+impl<'a> Annotator<'a> {
+    fn read<T, F>(&mut self, f: F) -> Result<(T, Range<usize>), HsSpecReaderError>
+    where
+        F: FnOnce(&mut HSSpecReader<'a>) -> Result<T, HsSpecReaderError>,
+    {
+        let start = self.position();
+        let res = f(&mut self.reader)?;
+        Ok((res, start..self.position()))
+    }
+
+    fn position(&self) -> usize {
+        self.reader.position()
+    }
+
+    fn push(&mut self, kind: HsFieldKind, range: Range<usize>, value: impl ToString) {
+        self.fields.push(HsField {
+            kind,
+            range,
+            value: value.to_string(),
+        })
+    }
+
+    // Splits span of a value, which is prefixed with its length, into length and data spans
+    fn push_prefixed(&mut self, kinds: (HsFieldKind, HsFieldKind), range: Range<usize>, data_len: usize, value: impl ToString) {
+        let (len_kind, data_kind) = kinds;
+        let data_start = range.end - data_len;
+        self.push(len_kind, range.start..data_start, data_len);
+        self.push(data_kind, data_start..range.end, value);
+    }
+}
+
+pub(super) fn annotate(data: &[u8]) -> Result<HsAnnotation, HsSpecReaderError> {
+    let mut annotator = Annotator {
+        reader: HSSpecReader::new(default_vlq_reader(data)),
+        fields: Vec::new(),
+    };
+
+    let (timestamp, range) = annotator.read(|r| Ok(r.get_u64()?))?;
+    annotator.push(HsFieldKind::Timestamp, range, timestamp);
+
+    let (agent_name, range) = annotator.read(|r| r.read_short_string())?;
+    let kinds = (HsFieldKind::AgentNameLength, HsFieldKind::AgentName);
+    annotator.push_prefixed(kinds, range, agent_name.len(), format!("{:?}", agent_name.as_str()));
+
+    let (version, range) = annotator.read(|r| r.read_version())?;
+    annotator.push(HsFieldKind::Version, range, version);
+
+    let (peer_name, range) = annotator.read(|r| r.read_short_string())?;
+    let kinds = (HsFieldKind::PeerNameLength, HsFieldKind::PeerName);
+    annotator.push_prefixed(kinds, range, peer_name.len(), format!("{:?}", peer_name.as_str()));
+
+    let (is_pub_node, range) = annotator.read(|r| Ok(r.get_u8()? == 1))?;
+    annotator.push(HsFieldKind::PubAddressFlag, range, is_pub_node);
+    if is_pub_node {
+        let (PeerAddr(addr), range) = annotator.read(|r| r.read_peer_addr())?;
+        // address length is always stored in the first byte
+        annotator.push(HsFieldKind::PubAddressLength, range.start..range.start + 1, range.len() - 1);
+        annotator.push(HsFieldKind::PubAddress, range.start + 1..range.end, addr);
+    }
+
+    let (features_num, range) = annotator.read(|r| Ok(r.get_u8().ok()))?;
+    if let Some(num) = features_num {
+        annotator.push(HsFieldKind::FeaturesCount, range, num);
+        for index in 0..num as usize {
+            let ((feature_id, feature_data), range) = annotator.read(|r| r.read_raw_feature())?;
+            let data_len = feature_data.len();
+            let feature = PeerFeature::try_from((feature_id, feature_data))?;
+            // feature id is always stored in the first byte
+            annotator.push(HsFieldKind::FeatureId(index), range.start..range.start + 1, feature_id);
+            let kinds = (HsFieldKind::FeatureLength(index), HsFieldKind::FeaturePayload(index));
+            annotator.push_prefixed(kinds, range.start + 1..range.end, data_len, format!("{:?}", feature));
+        }
+    }
+
+    if annotator.position() < data.len() {
+        let range = annotator.position()..data.len();
+        annotator.push(HsFieldKind::Trailing, range, "ignored");
+    }
+
+    Ok(HsAnnotation {
+        data: data.to_vec(),
+        fields: annotator.fields,
+    })
+}
+
+impl fmt::Display for HsFieldKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HsFieldKind::Timestamp => write!(f, "timestamp"),
+            HsFieldKind::AgentNameLength => write!(f, "agent name length"),
+            HsFieldKind::AgentName => write!(f, "agent name"),
+            HsFieldKind::Version => write!(f, "version"),
+            HsFieldKind::PeerNameLength => write!(f, "peer name length"),
+            HsFieldKind::PeerName => write!(f, "peer name"),
+            HsFieldKind::PubAddressFlag => write!(f, "public address flag"),
+            HsFieldKind::PubAddressLength => write!(f, "public address length"),
+            HsFieldKind::PubAddress => write!(f, "public address"),
+            HsFieldKind::FeaturesCount => write!(f, "features count"),
+            HsFieldKind::FeatureId(i) => write!(f, "feature[{}] id", i),
+            HsFieldKind::FeatureLength(i) => write!(f, "feature[{}] length", i),
+            HsFieldKind::FeaturePayload(i) => write!(f, "feature[{}] payload", i),
+            HsFieldKind::Trailing => write!(f, "trailing bytes"),
+        }
+    }
+}
+
+impl fmt::Display for HsAnnotation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const BYTES_PER_LINE: usize = 16;
+        const HEX_WIDTH: usize = BYTES_PER_LINE * 3;
+
+        for field in self.fields.iter() {
+            let bytes = self.bytes(field);
+            let hex_line = |chunk: &[u8]| chunk.iter().map(|b| format!("{:02x} ", b)).collect::<String>();
+
+            let mut chunks = bytes.chunks(BYTES_PER_LINE);
+            let first = chunks.next().unwrap_or(&[]);
+            writeln!(f, "{:04x}  {:<width$} {}: {}", field.range.start, hex_line(first), field.kind, field.value, width = HEX_WIDTH)?;
+            for (i, chunk) in chunks.enumerate() {
+                let offset = field.range.start + (i + 1) * BYTES_PER_LINE;
+                writeln!(f, "{:04x}  {}", offset, hex_line(chunk).trim_end())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // base ergo case from https://github.com/ergoplatform/ergo/blob/8ad8818bb0a2bc8df3be88259e379bad7221dc68/src/test/scala/org/ergoplatform/network/HandshakeSpecification.scala
+    const HS_HEX: &str = "bcd2919cee2e076572676f726566030306126572676f2d6d61696e6e65742d332e332e36000210040001000102067f000001ae46";
+    // real app handshake with public address
+    const HS_PUB_ADDR_HEX: &str = "93bdaca3fb2e076572676f726566030306146d61696e6e65742d736565642d6e6f64652d73660108a5e31aafc64602100400010001030d01000204f7c1e5d8dadac6b742";
+
+    fn hex_to_bytes(s: &str) -> Vec<u8> {
+        hex::decode(s).expect("internal error: invalid hex str")
+    }
+
+    fn assert_contiguous(annotation: &HsAnnotation, len: usize) {
+        let mut pos = 0;
+        for field in annotation.fields() {
+            assert_eq!(field.range.start, pos, "gap before {}", field.kind);
+            pos = field.range.end;
+        }
+        assert_eq!(pos, len);
+    }
+
+    #[test]
+    fn test_annotate_base_case() {
+        let data = hex_to_bytes(HS_HEX);
+        let annotation = Handshake::annotate(&data).expect("internal error: can't annotate hs bytes");
+        assert_contiguous(&annotation, data.len());
+
+        let kinds = annotation.fields().iter().map(|f| f.kind).collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            vec![
+                HsFieldKind::Timestamp,
+                HsFieldKind::AgentNameLength,
+                HsFieldKind::AgentName,
+                HsFieldKind::Version,
+                HsFieldKind::PeerNameLength,
+                HsFieldKind::PeerName,
+                HsFieldKind::PubAddressFlag,
+                HsFieldKind::FeaturesCount,
+                HsFieldKind::FeatureId(0),
+                HsFieldKind::FeatureLength(0),
+                HsFieldKind::FeaturePayload(0),
+                HsFieldKind::FeatureId(1),
+                HsFieldKind::FeatureLength(1),
+                HsFieldKind::FeaturePayload(1),
+            ]
+        );
+        let agent_name = &annotation.fields()[2];
+        assert_eq!(annotation.bytes(agent_name), b"ergoref");
+        assert_eq!(annotation.fields()[3].value, "3.3.6");
+    }
+
+    #[test]
+    fn test_annotate_pub_address() {
+        let data = hex_to_bytes(HS_PUB_ADDR_HEX);
+        let annotation = Handshake::annotate(&data).expect("internal error: can't annotate hs bytes");
+        assert_contiguous(&annotation, data.len());
+
+        let pub_addr = annotation
+            .fields()
+            .iter()
+            .find(|f| f.kind == HsFieldKind::PubAddress)
+            .expect("internal error: no public address span");
+        assert_eq!(pub_addr.value, "165.227.26.175:9030");
+    }
+
+    #[test]
+    fn test_annotate_trailing_and_truncated() {
+        let mut data = hex_to_bytes(HS_HEX);
+        data.extend_from_slice(&[0xde, 0xad]);
+        let annotation = Handshake::annotate(&data).expect("internal error: can't annotate hs bytes");
+        assert_contiguous(&annotation, data.len());
+        assert_eq!(annotation.fields().last().map(|f| f.kind), Some(HsFieldKind::Trailing));
+
+        let truncated = &data[..20];
+        assert_eq!(Handshake::annotate(truncated).is_err(), Handshake::parse(truncated).is_err());
+    }
+
+    #[test]
+    fn test_display_dump() {
+        let data = hex_to_bytes(HS_HEX);
+        let annotation = Handshake::annotate(&data).expect("internal error: can't annotate hs bytes");
+        let dump = annotation.to_string();
+        assert!(dump.starts_with("0000  bc d2 91 9c ee 2e"));
+        assert!(dump.contains("agent name: \"ergoref\""));
+        // fields longer than 16 bytes are continued on the next lines
+        let lines_count = annotation.fields().iter().map(|f| f.range.len().div_ceil(16).max(1)).sum::<usize>();
+        assert_eq!(dump.lines().count(), lines_count);
+    }
+}
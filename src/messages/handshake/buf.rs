@@ -0,0 +1,184 @@
+use std::io::{self, IoSlice};
+
+use bytes::{Buf, BufMut};
+
+use super::*;
+
+// Non-contiguous buffers, i.e. chained ones, are rarely built from many chunks, the slices are grown otherwise
+const INITIAL_GATHERED_CHUNKS: usize = 64;
+
+impl Handshake {
+    /// Writes handshake straight into the buffer.
+    pub fn encode<B: BufMut>(&self, buf: &mut B) -> Result<(), HsSpecWriterError> {
+        self.write(&mut HSSpecWriter::new(BufMut::writer(buf)))
+    }
+
+    /// Reads handshake from the buffer, advancing it by the handshake length.
+    ///
+    /// Data isn't copied, if it's stored contiguously, as in `BytesMut`.
+    pub fn decode<B: Buf>(buf: &mut B) -> Result<Self, HsSpecReaderError> {
+        decode_with(buf, |hs_reader| Self::read(hs_reader, false))
+    }
+}
+
+impl PeerFeature {
+    /// Writes feature as it's sent in handshake: id, length and data.
+    pub fn encode<B: BufMut>(&self, buf: &mut B) -> Result<(), HsSpecWriterError> {
+        HSSpecWriter::new(BufMut::writer(buf)).write_feature(self)
+    }
+
+    /// Reads feature written by [`PeerFeature::encode`], advancing the buffer.
+    pub fn decode<B: Buf>(buf: &mut B) -> Result<Self, HsSpecReaderError> {
+        decode_with(buf, |hs_reader| {
+            let (id, data) = hs_reader.read_raw_feature()?;
+            PeerFeature::try_from((id, data)).map_err(HsSpecReaderError::CannotReadPeerFeatureFromBytes)
+        })
+    }
+}
+
+fn decode_with<B, T, F>(buf: &mut B, read: F) -> Result<T, HsSpecReaderError>
+where
+    B: Buf,
+    F: FnOnce(&mut HSSpecReader) -> Result<T, HsSpecReaderError>,
+{
+    let (res, consumed) = if buf.chunk().len() == buf.remaining() {
+        let mut hs_reader = HSSpecReader::new(default_vlq_reader(buf.chunk()));
+        (read(&mut hs_reader)?, hs_reader.position())
+    } else {
+        let data = gather(buf)?;
+        let mut hs_reader = HSSpecReader::new(default_vlq_reader(&data));
+        (read(&mut hs_reader)?, hs_reader.position())
+    };
+    buf.advance(consumed);
+    Ok(res)
+}
+
+// Buffer isn't advanced, so all its chunks are gathered at once
+fn gather<B: Buf>(buf: &B) -> Result<Vec<u8>, HsSpecReaderError> {
+    let mut chunks = vec![IoSlice::new(&[]); INITIAL_GATHERED_CHUNKS];
+    loop {
+        let n = buf.chunks_vectored(&mut chunks);
+        let gathered = chunks[..n].iter().map(|chunk| chunk.len()).sum::<usize>();
+        if gathered == buf.remaining() {
+            return Ok(chunks[..n].iter().flat_map(|chunk| chunk.iter().copied()).collect());
+        }
+        // buffers, which don't expose all their chunks, can't be read without advancing
+        if n < chunks.len() {
+            let e = io::Error::new(io::ErrorKind::InvalidInput, format!("only {} of {} buffered bytes can be gathered", gathered, buf.remaining()));
+            return Err(HsSpecReaderError::CannotReadBytes(e));
+        }
+        chunks.resize(chunks.len() * 2, IoSlice::new(&[]));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use bytes::{Bytes, BytesMut};
+
+    use crate::encoding::vlq::TryFromVlq;
+    use crate::encoding::VlqDecodeError;
+    use crate::features::{Mode, SessionId};
+    use crate::models::{MagicBytes, ModelParseError};
+
+    use super::*;
+
+    // real app handshake with public address, mode and session id features
+    const HS_HEX: &str = "dee2aca3fb2e076572676f726566040005126572676f2d6d61696e6e65742d342e302e310108d5efc1d0c64602100400010001030e01000204eecc9582ffaaafeeaa01";
+
+    fn hs_bytes() -> Vec<u8> {
+        hex::decode(HS_HEX).expect("internal error: invalid hex str")
+    }
+
+    #[test]
+    fn test_decode_leaves_following_bytes() {
+        let mut buf = BytesMut::from(&hs_bytes()[..]);
+        buf.extend_from_slice(&[0xde, 0xad]);
+
+        let hs = Handshake::decode(&mut buf).expect("internal error: can't decode hs");
+        assert_eq!(hs, Handshake::parse(&hs_bytes()).expect("internal error: can't parse hs"));
+        assert_eq!(&buf[..], &[0xde, 0xad]);
+    }
+
+    #[test]
+    fn test_decode_non_contiguous() {
+        let data = hs_bytes();
+        let (head, tail) = data.split_at(20);
+        let mut buf = Bytes::copy_from_slice(head).chain(Bytes::copy_from_slice(tail));
+
+        let hs = Handshake::decode(&mut buf).expect("internal error: can't decode hs");
+        assert_eq!(hs, Handshake::parse(&data).expect("internal error: can't parse hs"));
+        assert_eq!(buf.remaining(), 0);
+    }
+
+    #[test]
+    fn test_decode_many_chunks() {
+        let data = hs_bytes();
+        let mut chunks = data.iter().map(|b| Bytes::copy_from_slice(&[*b]));
+        let first = chunks.next().expect("internal error: empty hs");
+        let mut buf: Box<dyn Buf> = Box::new(first);
+        for chunk in chunks {
+            buf = Box::new(buf.chain(chunk));
+        }
+        assert!(data.len() > INITIAL_GATHERED_CHUNKS);
+
+        let hs = Handshake::decode(&mut buf).expect("internal error: can't decode hs");
+        assert_eq!(hs, Handshake::parse(&data).expect("internal error: can't parse hs"));
+        assert_eq!(buf.remaining(), 0);
+    }
+
+    #[test]
+    fn test_encode_matches_serialize() {
+        let hs = Handshake::parse(&hs_bytes()).expect("internal error: can't parse hs");
+        let mut buf = BytesMut::new();
+        hs.encode(&mut buf).expect("internal error: can't encode hs");
+        assert_eq!(Handshake::decode(&mut buf).expect("internal error: can't decode hs"), hs);
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn test_feature_round_trip() {
+        let features = [
+            PeerFeature::Mode(Mode { state_type: 0, is_verifying: true, nipopow_suffix_len: Some(10), blocks_to_keep: -1 }),
+            PeerFeature::LocalAddr(PeerAddr(SocketAddr::from(([127, 0, 0, 1], 9006)))),
+            PeerFeature::SessionId(SessionId { magic: MagicBytes::MAINNET, session_id: -42 }),
+        ];
+        let mut buf = BytesMut::new();
+        for feature in features.iter() {
+            feature.encode(&mut buf).expect("internal error: can't encode feature");
+        }
+        for feature in features.iter() {
+            assert_eq!(&PeerFeature::decode(&mut buf).expect("internal error: can't decode feature"), feature);
+        }
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn test_model_buf_round_trip() {
+        let addr = PeerAddr(SocketAddr::from(([213, 239, 193, 208], 9030)));
+        let mut buf = BytesMut::new();
+        addr.put_vlq(&mut buf).expect("internal error: can't encode peer addr");
+        let len = buf.len();
+        buf.put_u8(0xff);
+
+        assert_eq!(PeerAddr::get_vlq(&mut buf, len).expect("internal error: can't decode peer addr"), addr);
+        assert_eq!(&buf[..], &[0xff]);
+        assert!(PeerAddr::get_vlq(&mut buf, len).is_err());
+        assert_eq!(&buf[..], &[0xff]);
+    }
+
+    #[test]
+    fn test_model_buf_truncated() {
+        // address is cut off in the middle of its 2 bytes long port
+        let addr = PeerAddr(SocketAddr::from(([213, 239, 193, 208], 9030)));
+        let mut buf = BytesMut::new();
+        addr.put_vlq(&mut buf).expect("internal error: can't encode peer addr");
+        let len = buf.len();
+        buf.truncate(len - 1);
+
+        let res = PeerAddr::get_vlq(&mut buf, len);
+        assert!(matches!(res, Err(ModelParseError::CannotDecodePort(VlqDecodeError::Truncated))));
+        assert_eq!(buf.len(), len - 1);
+    }
+}
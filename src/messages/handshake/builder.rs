@@ -0,0 +1,209 @@
+use std::net::SocketAddr;
+
+use crate::features::{Mode, SessionId};
+
+use super::*;
+
+#[derive(Error, Debug)]
+pub enum HandshakeBuildError {
+    #[error("Handshake field `{0}` isn't set")]
+    MissingField(&'static str),
+    #[error("Invalid handshake field `{0}`: {1}")]
+    InvalidShortString(&'static str, #[source] ModelParseError),
+    #[error("Unrecognized feature can't be sent")]
+    UnrecognizedFeature,
+}
+
+/// Builds outgoing [`Handshake`], validating its fields.
+///
+/// Agent name, version and node name are required. Mode, local address and session id features are
+/// sent at most once and in this order, the last set value of each one is used.
+#[derive(Debug, Default)]
+pub struct HandshakeBuilder {
+    agent_name: Option<String>,
+    version: Option<Version>,
+    node_name: Option<String>,
+    declared_address: Option<SocketAddr>,
+    mode: Option<Mode>,
+    session_id: Option<SessionId>,
+    local_address: Option<SocketAddr>,
+    has_unrecognized: bool,
+}
+
+impl HandshakeBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn agent_name(mut self, agent_name: &str) -> Self {
+        self.agent_name = Some(agent_name.to_string());
+        self
+    }
+
+    pub fn version(mut self, version: Version) -> Self {
+        self.version = Some(version);
+        self
+    }
+
+    pub fn node_name(mut self, node_name: &str) -> Self {
+        self.node_name = Some(node_name.to_string());
+        self
+    }
+
+    pub fn declared_address(mut self, addr: SocketAddr) -> Self {
+        self.declared_address = Some(addr);
+        self
+    }
+
+    pub fn mode(mut self, mode: Mode) -> Self {
+        self.mode = Some(mode);
+        self
+    }
+
+    pub fn session_id(mut self, session_id: SessionId) -> Self {
+        self.session_id = Some(session_id);
+        self
+    }
+
+    pub fn local_address(mut self, addr: SocketAddr) -> Self {
+        self.local_address = Some(addr);
+        self
+    }
+
+    /// Sets the feature as its own setter does, replacing the value set before.
+    pub fn feature(mut self, feature: PeerFeature) -> Self {
+        match feature {
+            PeerFeature::Mode(mode) => self.mode(mode),
+            PeerFeature::SessionId(session_id) => self.session_id(session_id),
+            PeerFeature::LocalAddr(addr) => self.local_address(addr.0),
+            // it fails the build
+            PeerFeature::Unrecognized => {
+                self.has_unrecognized = true;
+                self
+            }
+        }
+    }
+
+    pub fn build(self) -> Result<Handshake, HandshakeBuildError> {
+        let short_string = |field: &'static str, s: Option<String>| {
+            let s = s.ok_or(HandshakeBuildError::MissingField(field))?;
+            ShortString::try_from(s.into_bytes()).map_err(|e| HandshakeBuildError::InvalidShortString(field, e))
+        };
+        let agent_name = short_string("agent_name", self.agent_name)?;
+        let version = self.version.ok_or(HandshakeBuildError::MissingField("version"))?;
+        let peer_name = short_string("node_name", self.node_name)?;
+
+        if self.has_unrecognized {
+            return Err(HandshakeBuildError::UnrecognizedFeature);
+        }
+        let features = {
+            let mut features = Vec::with_capacity(3);
+            features.extend(self.mode.map(PeerFeature::Mode));
+            features.extend(self.local_address.map(|addr| PeerFeature::LocalAddr(PeerAddr(addr))));
+            features.extend(self.session_id.map(PeerFeature::SessionId));
+            // at most 3 features, so it's always within the limit
+            Some(features).filter(|f| !f.is_empty()).and_then(|f| Features::try_new(f).ok())
+        };
+
+        Ok(Handshake {
+            agent_name,
+            version,
+            peer_name,
+            pub_address: self.declared_address.map(PeerAddr),
+            features,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::models::MagicBytes;
+
+    use super::*;
+
+    fn default_mode() -> Mode {
+        Mode { state_type: 0, is_verifying: true, nipopow_suffix_len: None, blocks_to_keep: -1 }
+    }
+
+    #[test]
+    fn test_build_full_handshake() {
+        let declared: SocketAddr = "213.239.193.208:9030".parse().expect("internal error: invalid sock addr str");
+        let local: SocketAddr = "127.0.0.1:9006".parse().expect("internal error: invalid sock addr str");
+        let session_id = SessionId { magic: MagicBytes::MAINNET, session_id: 42 };
+        let hs = Handshake::builder()
+            .agent_name("ergoref")
+            .version(Version([4, 0, 5]))
+            .node_name("ergo-mainnet-4.0.1")
+            .declared_address(declared)
+            .mode(default_mode())
+            .session_id(session_id.clone())
+            .local_address(local)
+            .build()
+            .expect("internal error: can't build hs");
+
+        assert_eq!(hs.agent_name.as_str(), "ergoref");
+        assert_eq!(hs.peer_name.as_str(), "ergo-mainnet-4.0.1");
+        assert_eq!(hs.pub_address, Some(PeerAddr(declared)));
+        let features = hs.features.expect("internal error: no features were built");
+        assert_eq!(
+            features.to_vec(),
+            vec![PeerFeature::Mode(default_mode()), PeerFeature::LocalAddr(PeerAddr(local)), PeerFeature::SessionId(session_id)]
+        );
+    }
+
+    #[test]
+    fn test_build_without_features() {
+        let hs = HandshakeBuilder::new()
+            .agent_name("ergoref")
+            .version(Version([4, 0, 5]))
+            .node_name("ergo-node")
+            .build()
+            .expect("internal error: can't build hs");
+        assert_eq!(hs.features, None);
+        assert_eq!(hs.pub_address, None);
+    }
+
+    #[test]
+    fn test_build_missing_fields() {
+        let res = HandshakeBuilder::new().agent_name("ergoref").node_name("ergo-node").build();
+        assert!(matches!(res, Err(HandshakeBuildError::MissingField("version"))));
+        let res = HandshakeBuilder::new().agent_name("ergoref").version(Version([4, 0, 5])).build();
+        assert!(matches!(res, Err(HandshakeBuildError::MissingField("node_name"))));
+    }
+
+    #[test]
+    fn test_build_invalid_lengths() {
+        let long_name = "a".repeat(ShortString::MAX_SIZE + 1);
+        let res = HandshakeBuilder::new().agent_name(&long_name).version(Version([4, 0, 5])).node_name("ergo-node").build();
+        assert!(matches!(res, Err(HandshakeBuildError::InvalidShortString("agent_name", _))));
+    }
+
+    #[test]
+    fn test_build_repeated_features() {
+        let local: SocketAddr = "127.0.0.1:9006".parse().expect("internal error: invalid sock addr str");
+        let mode = Mode { blocks_to_keep: 1440, ..default_mode() };
+        let mut builder = HandshakeBuilder::new().agent_name("ergoref").version(Version([4, 0, 5])).node_name("ergo-node");
+        for _ in 0..Features::MAX_LEN {
+            builder = builder.feature(PeerFeature::Mode(default_mode()));
+        }
+        let hs = builder
+            .feature(PeerFeature::LocalAddr(PeerAddr(local)))
+            .local_address(local)
+            .mode(mode.clone())
+            .build()
+            .expect("internal error: can't build hs");
+        let features = hs.features.expect("internal error: no features were built");
+        assert_eq!(features.to_vec(), vec![PeerFeature::Mode(mode), PeerFeature::LocalAddr(PeerAddr(local))]);
+    }
+
+    #[test]
+    fn test_build_unrecognized_feature() {
+        let res = HandshakeBuilder::new()
+            .agent_name("ergoref")
+            .version(Version([4, 0, 5]))
+            .node_name("ergo-node")
+            .feature(PeerFeature::Unrecognized)
+            .build();
+        assert!(matches!(res, Err(HandshakeBuildError::UnrecognizedFeature)));
+    }
+}
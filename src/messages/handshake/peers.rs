@@ -0,0 +1,83 @@
+use super::*;
+
+/// `Peers` message body: specs of peers known to the sender.
+///
+/// Peer spec has the same fields as a handshake, but isn't prefixed with a timestamp.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Peers {
+    pub peers: Vec<Handshake>,
+}
+
+impl Peers {
+    pub const CODE: u8 = 2;
+    // Reference node default `maxPeerSpecObjects`
+    pub const MAX_LEN: usize = 64;
+
+    pub fn parse(data: &[u8]) -> Result<Self, HsSpecReaderError> {
+        let mut hs_reader = HSSpecReader::new(default_vlq_reader(data));
+        let len = hs_reader.get_u32()?;
+        if len as usize > Self::MAX_LEN {
+            return Err(HsSpecReaderError::TooMuchPeerSpecs(len));
+        }
+        let peers = (0..len)
+            .map(|_| Handshake::read_spec(&mut hs_reader, true))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Peers { peers })
+    }
+
+    pub fn serialize(&self) -> Result<Vec<u8>, HsSpecWriterError> {
+        let len = u32::try_from(self.peers.len())
+            .ok()
+            .filter(|&len| len as usize <= Self::MAX_LEN)
+            .ok_or(HsSpecWriterError::TooMuchPeerSpecs(self.peers.len()))?;
+        let mut hs_writer = HSSpecWriter::new(default_vlq_writer(Vec::new()));
+        hs_writer.put_u32(len)?;
+        for peer in self.peers.iter() {
+            peer.write_spec(&mut hs_writer)?;
+        }
+        Ok(hs_writer.into_inner().into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use super::*;
+
+    // real app handshake with public address, mode and session id features
+    const HS_HEX: &str = "dee2aca3fb2e076572676f726566040005126572676f2d6d61696e6e65742d342e302e310108d5efc1d0c64602100400010001030e01000204eecc9582ffaaafeeaa01";
+
+    fn hs() -> Handshake {
+        Handshake::parse(&hex::decode(HS_HEX).expect("internal error: invalid hex str")).expect("internal error: can't parse hs")
+    }
+
+    #[test]
+    fn test_peers_round_trip() {
+        let mut other = hs();
+        other.pub_address = Some(PeerAddr(SocketAddr::from(([10, 0, 0, 1], 9030))));
+        other.features = None;
+        let peers = Peers { peers: vec![hs(), other] };
+
+        let data = peers.serialize().expect("internal error: can't serialize peers");
+        assert_eq!(Peers::parse(&data).expect("internal error: can't parse peers"), peers);
+    }
+
+    #[test]
+    fn test_spec_is_hs_without_timestamp() {
+        let data = Peers { peers: vec![hs()] }.serialize().expect("internal error: can't serialize peers");
+        let hs_data = hs().serialize().expect("internal error: can't serialize hs");
+        let mut reader = default_vlq_reader(&hs_data);
+        reader.get_u64().expect("internal error: can't read timestamp");
+        assert_eq!(&data[1..], &hs_data[reader.position()..]);
+    }
+
+    #[test]
+    fn test_peers_limit() {
+        let peers = Peers { peers: vec![hs(); Peers::MAX_LEN + 1] };
+        assert!(matches!(peers.serialize(), Err(HsSpecWriterError::TooMuchPeerSpecs(65))));
+        // only the count is read
+        assert!(matches!(Peers::parse(&[65]), Err(HsSpecReaderError::TooMuchPeerSpecs(65))));
+        assert_eq!(Peers::parse(&[0]).expect("internal error: can't parse peers"), Peers::default());
+    }
+}
@@ -1,4 +1,4 @@
-pub use handshake::Handshake;
-pub(crate) use handshake::{HsSpecReaderError, HsSpecWriterError};
+pub use handshake::{Handshake, HandshakeBuildError, HandshakeBuilder, HsAnnotation, HsField, HsFieldKind, Peers};
+pub use handshake::{HsSpecReaderError, HsSpecWriterError};
 
 mod handshake;
@@ -0,0 +1,175 @@
+//! Handshake metrics recorded through the `metrics` facade. They're exported by the recorder installed by the application,
+//! i.e. `metrics-exporter-prometheus`.
+
+use std::collections::BTreeSet;
+use std::sync::{Mutex, PoisonError};
+use std::time::Duration;
+
+use ::metrics::{counter, histogram};
+
+use crate::features::PeerFeature;
+use crate::messages::Handshake;
+use crate::HandshakingError;
+
+// Handshaking calls labeled by `direction`. Each call counts once, however many addresses it dials,
+// so attempted handshakes are the sum of succeeded and failed ones
+pub const HANDSHAKES_ATTEMPTED: &str = "ergo_handshakes_attempted_total";
+pub const HANDSHAKES_SUCCEEDED: &str = "ergo_handshakes_succeeded_total";
+// Labeled by `direction` and `error`, which is `HandshakingError::kind`
+pub const HANDSHAKES_FAILED: &str = "ergo_handshakes_failed_total";
+// Seconds from the call till the validated handshake, labeled by `direction`
+pub const HANDSHAKE_DURATION: &str = "ergo_handshake_duration_seconds";
+// Handshaked peers labeled by `agent` and `version`. Values are chosen by peers, so only the first
+// `MAX_LABEL_VALUES` distinct ones are kept, the following ones are "other"
+pub const PEER_AGENTS: &str = "ergo_peer_agents_total";
+// Handshaked peers with the `Mode` feature labeled by `state_type` and `verifying`
+pub const PEER_MODES: &str = "ergo_peer_modes_total";
+// Handshaked peers labeled by `present`, which is "true" for the `SessionId` feature
+pub const PEER_SESSION_IDS: &str = "ergo_peer_session_ids_total";
+
+pub const MAX_LABEL_VALUES: usize = 64;
+pub const OTHER_LABEL_VALUE: &str = "other";
+
+static AGENT_LABELS: Mutex<BTreeSet<String>> = Mutex::new(BTreeSet::new());
+static VERSION_LABELS: Mutex<BTreeSet<String>> = Mutex::new(BTreeSet::new());
+
+// Label values are only added, so they are valid after a panic in another thread
+fn bounded_label(seen: &Mutex<BTreeSet<String>>, value: String) -> String {
+    let mut seen = seen.lock().unwrap_or_else(PoisonError::into_inner);
+    if seen.contains(&value) {
+        return value;
+    }
+    if seen.len() >= MAX_LABEL_VALUES {
+        return OTHER_LABEL_VALUE.to_string();
+    }
+    seen.insert(value.clone());
+    value
+}
+
+fn direction(is_inbound: bool) -> &'static str {
+    match is_inbound {
+        true => "inbound",
+        false => "outbound",
+    }
+}
+
+pub(crate) fn record_attempt(is_inbound: bool) {
+    counter!(HANDSHAKES_ATTEMPTED, "direction" => direction(is_inbound)).increment(1);
+}
+
+pub(crate) fn record_failure(is_inbound: bool, err: &HandshakingError) {
+    counter!(HANDSHAKES_FAILED, "direction" => direction(is_inbound), "error" => err.kind()).increment(1);
+}
+
+pub(crate) fn record_success(is_inbound: bool, hs: &Handshake, elapsed: Duration) {
+    counter!(HANDSHAKES_SUCCEEDED, "direction" => direction(is_inbound)).increment(1);
+    histogram!(HANDSHAKE_DURATION, "direction" => direction(is_inbound)).record(elapsed.as_secs_f64());
+    let agent = bounded_label(&AGENT_LABELS, hs.agent_name.to_string());
+    let version = bounded_label(&VERSION_LABELS, hs.version.to_string());
+    counter!(PEER_AGENTS, "agent" => agent, "version" => version).increment(1);
+
+    let features = hs.features.iter().flat_map(|f| f.iter());
+    let mut has_session_id = false;
+    for feature in features {
+        match feature {
+            PeerFeature::Mode(mode) => {
+                counter!(PEER_MODES, "state_type" => mode.state_type.to_string(), "verifying" => mode.is_verifying.to_string()).increment(1);
+            }
+            PeerFeature::SessionId(_) => has_session_id = true,
+            PeerFeature::LocalAddr(_) | PeerFeature::Unrecognized => {}
+        }
+    }
+    counter!(PEER_SESSION_IDS, "present" => has_session_id.to_string()).increment(1);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use metrics_util::debugging::{DebugValue, DebuggingRecorder};
+    use metrics_util::MetricKind;
+
+    use crate::models::MagicBytes;
+    use crate::testing::{create_hs, MockBehavior, MockPeer};
+    use crate::dial::DialStrategy;
+    use crate::{handshaking, handshaking_with, HandshakeConfig, HandshakingError};
+
+    use super::*;
+
+    // Metric name with sorted labels mapped to the counter value or the histogram samples count
+    fn snapshot(recorder: &DebuggingRecorder) -> HashMap<String, u64> {
+        recorder
+            .snapshotter()
+            .snapshot()
+            .into_vec()
+            .into_iter()
+            .map(|(key, _, _, value)| {
+                let (kind, key) = key.into_parts();
+                let mut labels = key.labels().map(|l| format!("{}={}", l.key(), l.value())).collect::<Vec<_>>();
+                labels.sort();
+                let value = match (kind, value) {
+                    (MetricKind::Counter, DebugValue::Counter(n)) => n,
+                    (MetricKind::Histogram, DebugValue::Histogram(samples)) => samples.len() as u64,
+                    _ => 0,
+                };
+                (format!("{}{{{}}}", key.name(), labels.join(",")), value)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_handshake_metrics() {
+        let recorder = DebuggingRecorder::new();
+        let honest = MockPeer::spawn(create_hs("node", 2), MockBehavior::Honest).expect("internal error: can't spawn mock peer");
+        let wrong = MockPeer::spawn(create_hs("node", 2), MockBehavior::WrongMagic(MagicBytes([2, 0, 0, 2])))
+            .expect("internal error: can't spawn mock peer");
+
+        ::metrics::with_local_recorder(&recorder, || {
+            assert!(handshaking(honest.addr(), create_hs("client", 1)).is_ok());
+            assert!(matches!(handshaking(wrong.addr(), create_hs("client", 1)), Err(HandshakingError::WrongNetwork(_))));
+        });
+
+        let metrics = snapshot(&recorder);
+        let expected = [
+            ("ergo_handshakes_attempted_total{direction=outbound}", 2),
+            ("ergo_handshakes_succeeded_total{direction=outbound}", 1),
+            ("ergo_handshakes_failed_total{direction=outbound,error=wrong_network}", 1),
+            ("ergo_handshake_duration_seconds{direction=outbound}", 1),
+            ("ergo_peer_agents_total{agent=ergoref,version=4.0.5}", 1),
+            ("ergo_peer_modes_total{state_type=0,verifying=true}", 1),
+            ("ergo_peer_session_ids_total{present=true}", 1),
+        ];
+        for (name, value) in expected.iter() {
+            assert_eq!(metrics.get(*name), Some(value), "{} in {:?}", name, metrics);
+        }
+        assert_eq!(metrics.len(), expected.len());
+    }
+
+    #[test]
+    fn test_racing_attempts_count_once() {
+        let recorder = DebuggingRecorder::new();
+        let slow = MockPeer::spawn(create_hs("slow", 2), MockBehavior::Delay(Duration::from_millis(500))).expect("internal error: can't spawn mock peer");
+        let fast = MockPeer::spawn(create_hs("fast", 3), MockBehavior::Honest).expect("internal error: can't spawn mock peer");
+        let config = HandshakeConfig::new().with_dial(DialStrategy::HappyEyeballs { attempt_delay: Duration::from_millis(50), connect_timeout: DialStrategy::DEFAULT_CONNECT_TIMEOUT });
+
+        ::metrics::with_local_recorder(&recorder, || {
+            let addrs = [slow.addr(), fast.addr()];
+            assert!(handshaking_with(&addrs[..], create_hs("client", 1), &config).is_ok());
+        });
+
+        let metrics = snapshot(&recorder);
+        assert_eq!(metrics.get("ergo_handshakes_attempted_total{direction=outbound}"), Some(&1));
+        assert_eq!(metrics.get("ergo_handshakes_succeeded_total{direction=outbound}"), Some(&1));
+        assert!(metrics.keys().all(|name| !name.starts_with(HANDSHAKES_FAILED)));
+    }
+
+    #[test]
+    fn test_bounded_label() {
+        let seen = Mutex::new(BTreeSet::new());
+        for i in 0..MAX_LABEL_VALUES {
+            assert_eq!(bounded_label(&seen, i.to_string()), i.to_string());
+        }
+        assert_eq!(bounded_label(&seen, "new".to_string()), OTHER_LABEL_VALUE);
+        assert_eq!(bounded_label(&seen, "0".to_string()), "0");
+    }
+}
@@ -0,0 +1,220 @@
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use super::PeerAddr;
+
+/// Where the address is reachable from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AddrScope {
+    // 0.0.0.0 or ::
+    Unspecified,
+    Loopback,
+    // RFC 1918 and unique local IPv6 addresses, reachable only inside of the local network
+    Private,
+    LinkLocal,
+    // Example ranges of RFC 5737 and RFC 3849
+    Documentation,
+    Multicast,
+    // Broadcast, shared NAT, benchmarking and other special purpose ranges
+    Reserved,
+    Global,
+}
+
+impl AddrScope {
+    pub fn of(ip: IpAddr) -> Self {
+        match ip.to_canonical() {
+            IpAddr::V4(ip) => Self::of_v4(ip),
+            IpAddr::V6(ip) => Self::of_v6(ip),
+        }
+    }
+
+    fn of_v4(ip: Ipv4Addr) -> Self {
+        let [a, b, c, _] = ip.octets();
+        match (a, b, c) {
+            _ if ip.is_unspecified() => AddrScope::Unspecified,
+            (127, ..) => AddrScope::Loopback,
+            (10, ..) | (172, 16..=31, _) | (192, 168, _) => AddrScope::Private,
+            (169, 254, _) => AddrScope::LinkLocal,
+            (192, 0, 2) | (198, 51, 100) | (203, 0, 113) => AddrScope::Documentation,
+            (224..=239, ..) => AddrScope::Multicast,
+            // "this network", shared NAT, IETF protocol assignments, deprecated 6to4 relay anycast, benchmarking
+            // and future use with broadcast
+            (0, ..) | (100, 64..=127, _) | (192, 0, 0) | (192, 88, 99) | (198, 18..=19, _) | (240..=255, ..) => AddrScope::Reserved,
+            _ => AddrScope::Global,
+        }
+    }
+
+    fn of_v6(ip: Ipv6Addr) -> Self {
+        if let Some(ip) = embedded_ipv4(ip) {
+            return Self::of_v4(ip);
+        }
+        let segments = ip.segments();
+        match segments {
+            _ if ip.is_unspecified() => AddrScope::Unspecified,
+            _ if ip.is_loopback() => AddrScope::Loopback,
+            // unique local and deprecated site local addresses
+            [s, ..] if s & 0xfe00 == 0xfc00 || s & 0xffc0 == 0xfec0 => AddrScope::Private,
+            [s, ..] if s & 0xffc0 == 0xfe80 => AddrScope::LinkLocal,
+            [0x2001, 0x0db8, ..] => AddrScope::Documentation,
+            [s, ..] if s & 0xff00 == 0xff00 => AddrScope::Multicast,
+            // discard prefix, Teredo, benchmarking and ORCHID
+            [0x0100, 0, 0, 0, ..] | [0x2001, 0, ..] | [0x2001, 0x0002, 0, ..] => AddrScope::Reserved,
+            [0x2001, s, ..] if s & 0xfff0 == 0x0010 => AddrScope::Reserved,
+            _ => AddrScope::Global,
+        }
+    }
+}
+
+// IPv4 address, which is reached through the 6to4, NAT64 or IPv4-compatible address.
+// IPv4-mapped ones are canonicalized before
+fn embedded_ipv4(ip: Ipv6Addr) -> Option<Ipv4Addr> {
+    let from_segments = |high: u16, low: u16| Ipv4Addr::from((u32::from(high) << 16) | u32::from(low));
+    match ip.segments() {
+        [0x2002, high, low, ..] => Some(from_segments(high, low)),
+        [0x0064, 0xff9b, 0, 0, 0, 0, high, low] => Some(from_segments(high, low)),
+        [0, 0, 0, 0, 0, 0, high, low] if !ip.is_unspecified() && !ip.is_loopback() => Some(from_segments(high, low)),
+        _ => None,
+    }
+}
+
+/// Network of addresses, which are likely controlled by one party: /16 for IPv4 and /32 for IPv6.
+///
+/// All unroutable addresses are in one group.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NetGroup {
+    Unroutable,
+    Ipv4([u8; 2]),
+    Ipv6([u8; 4]),
+}
+
+impl NetGroup {
+    pub fn of(ip: IpAddr) -> Self {
+        let ip = match ip.to_canonical() {
+            IpAddr::V6(v6) => embedded_ipv4(v6).map_or(IpAddr::V6(v6), IpAddr::V4),
+            ip => ip,
+        };
+        if AddrScope::of(ip) != AddrScope::Global {
+            return NetGroup::Unroutable;
+        }
+        match ip {
+            IpAddr::V4(ip) => {
+                let [a, b, ..] = ip.octets();
+                NetGroup::Ipv4([a, b])
+            }
+            IpAddr::V6(ip) => {
+                let [a, b, c, d, ..] = ip.octets();
+                NetGroup::Ipv6([a, b, c, d])
+            }
+        }
+    }
+
+    /// Tagged bytes of the group, which are distinct for different groups.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            NetGroup::Unroutable => vec![0],
+            NetGroup::Ipv4(prefix) => [&[4], &prefix[..]].concat(),
+            NetGroup::Ipv6(prefix) => [&[6], &prefix[..]].concat(),
+        }
+    }
+}
+
+impl fmt::Display for NetGroup {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NetGroup::Unroutable => write!(f, "unroutable"),
+            NetGroup::Ipv4([a, b]) => write!(f, "{}.{}.0.0/16", a, b),
+            NetGroup::Ipv6([a, b, c, d]) => write!(f, "{:x}:{:x}::/32", u16::from_be_bytes([*a, *b]), u16::from_be_bytes([*c, *d])),
+        }
+    }
+}
+
+impl PeerAddr {
+    pub fn scope(&self) -> AddrScope {
+        AddrScope::of(self.0.ip())
+    }
+
+    /// Whether peers from the internet can connect to the address.
+    pub fn is_routable(&self) -> bool {
+        self.scope() == AddrScope::Global && self.0.port() != 0
+    }
+
+    pub fn is_private(&self) -> bool {
+        self.scope() == AddrScope::Private
+    }
+
+    pub fn netgroup(&self) -> NetGroup {
+        NetGroup::of(self.0.ip())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use super::*;
+
+    fn peer_addr(s: &str) -> PeerAddr {
+        PeerAddr(s.parse::<SocketAddr>().expect("internal error: invalid socket addr"))
+    }
+
+    #[test]
+    fn test_scopes() {
+        let cases = [
+            ("0.0.0.0:9030", AddrScope::Unspecified),
+            ("127.0.0.1:9030", AddrScope::Loopback),
+            ("10.1.2.3:9030", AddrScope::Private),
+            ("172.31.0.1:9030", AddrScope::Private),
+            ("192.168.1.1:9030", AddrScope::Private),
+            ("169.254.0.1:9030", AddrScope::LinkLocal),
+            ("203.0.113.7:9030", AddrScope::Documentation),
+            ("224.0.0.1:9030", AddrScope::Multicast),
+            ("100.64.0.1:9030", AddrScope::Reserved),
+            ("255.255.255.255:9030", AddrScope::Reserved),
+            ("192.88.99.1:9030", AddrScope::Reserved),
+            ("213.239.193.208:9030", AddrScope::Global),
+            ("172.32.0.1:9030", AddrScope::Global),
+            ("[::]:9030", AddrScope::Unspecified),
+            ("[::1]:9030", AddrScope::Loopback),
+            ("[fd00::1]:9030", AddrScope::Private),
+            ("[fe80::1]:9030", AddrScope::LinkLocal),
+            ("[2001:db8::1]:9030", AddrScope::Documentation),
+            ("[ff02::1]:9030", AddrScope::Multicast),
+            ("[2001:0:4136:e378:8000:63bf:3fff:fdd2]:9030", AddrScope::Reserved),
+            ("[2001:10::1]:9030", AddrScope::Reserved),
+            ("[2001:1f::1]:9030", AddrScope::Reserved),
+            ("[2001:20::1]:9030", AddrScope::Global),
+            ("[::ffff:10.0.0.1]:9030", AddrScope::Private),
+            ("[2a01:4f8::1]:9030", AddrScope::Global),
+            // 6to4, NAT64 and IPv4-compatible addresses are classified by the embedded IPv4 address
+            ("[2002:c0a8:101::1]:9030", AddrScope::Private),
+            ("[2002:d5ef:c1d0::1]:9030", AddrScope::Global),
+            ("[64:ff9b::7f00:1]:9030", AddrScope::Loopback),
+            ("[64:ff9b::d5ef:c1d0]:9030", AddrScope::Global),
+            ("[::10.0.0.1]:9030", AddrScope::Private),
+            ("[::213.239.193.208]:9030", AddrScope::Global),
+        ];
+        for (addr, scope) in cases.iter() {
+            assert_eq!(peer_addr(addr).scope(), *scope, "{}", addr);
+        }
+    }
+
+    #[test]
+    fn test_is_routable() {
+        assert!(peer_addr("213.239.193.208:9030").is_routable());
+        assert!(!peer_addr("213.239.193.208:0").is_routable());
+        assert!(!peer_addr("192.168.1.1:9030").is_routable());
+        assert!(peer_addr("192.168.1.1:9030").is_private());
+        assert!(!peer_addr("127.0.0.1:9030").is_private());
+    }
+
+    #[test]
+    fn test_netgroup() {
+        assert_eq!(peer_addr("213.239.193.208:9030").netgroup(), peer_addr("213.239.1.1:1").netgroup());
+        assert_ne!(peer_addr("213.239.193.208:9030").netgroup(), peer_addr("213.240.1.1:1").netgroup());
+        assert_eq!(peer_addr("[::ffff:213.239.1.1]:1").netgroup(), NetGroup::Ipv4([213, 239]));
+        assert_eq!(peer_addr("10.0.0.1:1").netgroup(), peer_addr("[fe80::1]:1").netgroup());
+        assert_eq!(peer_addr("[2a01:4f8::1]:1").netgroup().to_string(), "2a01:4f8::/32");
+        assert_eq!(peer_addr("[2002:d5ef:c1d0::1]:1").netgroup(), NetGroup::Ipv4([213, 239]));
+        assert_eq!(peer_addr("[64:ff9b::d5ef:c1d0]:1").netgroup(), NetGroup::Ipv4([213, 239]));
+    }
+}
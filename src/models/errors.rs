@@ -1,8 +1,12 @@
 use std::io;
+use std::str::Utf8Error;
 use std::string::FromUtf8Error;
 
 use thiserror::Error;
 
+use crate::encoding::VlqDecodeError;
+
+use super::MagicBytes;
 use super::PeerAddr;
 use super::ShortString;
 
@@ -12,12 +16,22 @@ pub enum ModelParseError {
     InvalidShortStringLength(usize),
     #[error("Received invalid data: {0}")]
     InvalidUtf8Buffer(#[from] FromUtf8Error),
+    #[error("Received invalid data: {0}")]
+    InvalidUtf8Slice(#[from] Utf8Error),
     #[error(
-        "Can't create HSPeerAddr from buffer with length {0}. Should be {} or {}",
-        PeerAddr::SIZE_IPv4_SOCKET,
-        PeerAddr::SIZE_IPv6_SOCKET
+        "Can't create HSPeerAddr from buffer with length {0}. Should be {} or {} with 1 to 3 bytes long port",
+        PeerAddr::SIZE_IPv4,
+        PeerAddr::SIZE_IPv6
     )]
     InvalidPeerAddrLength(usize),
+    #[error("Can't decode peer address port: {0}")]
+    CannotDecodePort(#[from] VlqDecodeError),
+    #[error("Can't parse peer address from {0:?}. Should be in \"ip:port\" form")]
+    InvalidPeerAddr(String),
+    #[error("Can't parse version from {0:?}. Should be in \"major.minor.patch\" form")]
+    InvalidVersion(String),
+    #[error("Can't parse magic bytes from {0:?}. Should be {} hex encoded bytes", MagicBytes::SIZE)]
+    InvalidMagicBytes(String),
 }
 
 #[derive(Error, Debug)]
@@ -1,6 +1,34 @@
-#[derive(Debug, PartialEq, Eq, Default)]
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+use super::errors::ModelParseError;
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
 pub struct MagicBytes(pub [u8; MagicBytes::SIZE]);
 
 impl MagicBytes {
     pub const SIZE: usize = 4;
+    pub const MAINNET: MagicBytes = MagicBytes([1, 0, 2, 4]);
+}
+
+impl fmt::Display for MagicBytes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", hex::encode(self.0))
+    }
 }
+
+// Parses magic bytes from hex, i.e. "01000204"
+impl FromStr for MagicBytes {
+    type Err = ModelParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes = hex::decode(s).map_err(|_| ModelParseError::InvalidMagicBytes(s.to_string()))?;
+        <[u8; Self::SIZE]>::try_from(bytes.as_slice())
+            .map(MagicBytes)
+            .map_err(|_| ModelParseError::InvalidMagicBytes(s.to_string()))
+    }
+}
+
+#[cfg(feature = "serde")]
+crate::utils::impl_serde_via_str!(MagicBytes);
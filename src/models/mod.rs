@@ -1,3 +1,4 @@
+pub use addr_scope::*;
 pub use magic::*;
 pub use model_errors::*;
 pub use peer_addr::*;
@@ -6,6 +7,7 @@ pub use version::*;
 
 use errors as model_errors;
 
+mod addr_scope;
 mod errors;
 mod magic;
 mod peer_addr;
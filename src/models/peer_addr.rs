@@ -1,14 +1,13 @@
 use std::convert::TryFrom;
-use std::io::Write;
+use std::fmt;
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::str::FromStr;
 
-use sigma_ser::vlq_encode::{ReadSigmaVlqExt, WriteSigmaVlqExt};
-
-use crate::encoding::vlq::{default_vlq_reader, default_vlq_writer, TryFromVlq, TryIntoVlq};
+use crate::encoding::vlq::{default_vlq_reader, ReadVlqExt, TryFromVlq, TryIntoVlq, WriteVlqExt};
 
 use super::errors::{ModelParseError, ModelSerializeError};
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct PeerAddr(pub SocketAddr);
 
 impl PeerAddr {
@@ -17,30 +16,51 @@ impl PeerAddr {
     #[allow(non_upper_case_globals)]
     pub(crate) const SIZE_IPv4_SOCKET: usize = Self::SIZE_IPv4 + Self::SIZE_PORT;
     #[allow(non_upper_case_globals)]
-    const SIZE_IPv4: usize = 4;
+    pub(crate) const SIZE_IPv4: usize = 4;
     #[allow(non_upper_case_globals)]
-    const SIZE_IPv6: usize = 16;
+    pub(crate) const SIZE_IPv6: usize = 16;
     pub(crate) const SIZE_PORT: usize = 2;
-    // Port u16 value is vlq-encoded in 2 or 3 bytes
+    // Port u16 value is vlq-encoded in 1 to 3 bytes
     const PORT_EXCESS_VLQ_SIZE: usize = 1;
+    const PORT_MIN_VLQ_SIZE: usize = 1;
+}
+
+impl fmt::Display for PeerAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+// Parses addresses in "ip:port" form, i.e. "213.239.193.208:9030"
+impl FromStr for PeerAddr {
+    type Err = ModelParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse::<SocketAddr>()
+            .map(PeerAddr)
+            .map_err(|_| ModelParseError::InvalidPeerAddr(s.to_string()))
+    }
 }
 
+#[cfg(feature = "serde")]
+crate::utils::impl_serde_via_str!(PeerAddr);
+
 impl TryFromVlq for PeerAddr {
     type Error = ModelParseError;
 
-    fn try_from_vlq(data: Vec<u8>) -> Result<Self, Self::Error> {
+    fn try_from_vlq(data: &[u8]) -> Result<Self, Self::Error> {
         let (ip_addr, port_bytes) = {
             match data.len() {
-                size_ip4_socket if size_ip4_socket == Self::SIZE_IPv4_SOCKET ||
-                    size_ip4_socket == Self::SIZE_IPv4_SOCKET + Self::PORT_EXCESS_VLQ_SIZE => {
+                size_ip4_socket if (Self::SIZE_IPv4 + Self::PORT_MIN_VLQ_SIZE..=Self::SIZE_IPv4_SOCKET + Self::PORT_EXCESS_VLQ_SIZE)
+                    .contains(&size_ip4_socket) => {
                     let (ip_bytes, port_bytes) = data.split_at(Self::SIZE_IPv4);
-                    let ip_octets = <[u8; Self::SIZE_IPv4]>::try_from(ip_bytes).expect("internal error: slice len != 4");
+                    let ip_octets = <[u8; Self::SIZE_IPv4]>::try_from(ip_bytes).map_err(|_| ModelParseError::InvalidPeerAddrLength(data.len()))?;
                     (IpAddr::V4(Ipv4Addr::from(ip_octets)), port_bytes)
                 }
-                size_ip6_socket if size_ip6_socket == Self::SIZE_IPv6_SOCKET ||
-                    size_ip6_socket == Self::SIZE_IPv6_SOCKET + Self::PORT_EXCESS_VLQ_SIZE => {
+                size_ip6_socket if (Self::SIZE_IPv6 + Self::PORT_MIN_VLQ_SIZE..=Self::SIZE_IPv6_SOCKET + Self::PORT_EXCESS_VLQ_SIZE)
+                    .contains(&size_ip6_socket) => {
                     let (ip_bytes, port_bytes) = data.split_at(Self::SIZE_IPv6);
-                    let ip_octets = <[u8; Self::SIZE_IPv6]>::try_from(ip_bytes).expect("internal error: slice len != 16");
+                    let ip_octets = <[u8; Self::SIZE_IPv6]>::try_from(ip_bytes).map_err(|_| ModelParseError::InvalidPeerAddrLength(data.len()))?;
                     (IpAddr::V6(Ipv6Addr::from(ip_octets)), port_bytes)
                 }
                 _ => return Err(ModelParseError::InvalidPeerAddrLength(data.len())),
@@ -48,7 +68,7 @@ impl TryFromVlq for PeerAddr {
         };
         let port = {
             let mut vlq_reader = default_vlq_reader(port_bytes);
-            vlq_reader.get_u16().expect("internal error: port bytes slice len isn't equal to 2 or 3")
+            vlq_reader.get_u16()?
         };
 
         Ok(Self(SocketAddr::new(ip_addr, port)))
@@ -58,12 +78,8 @@ impl TryFromVlq for PeerAddr {
 impl TryIntoVlq for PeerAddr {
     type Error = ModelSerializeError;
 
-    fn try_into_vlq(&self) -> Result<Vec<u8>, Self::Error> {
+    fn write_vlq<W: WriteVlqExt>(&self, vlq_writer: &mut W) -> Result<(), Self::Error> {
         let PeerAddr(socket_addr) = self;
-        let mut vlq_writer = {
-            let buf_size = if socket_addr.is_ipv4() { Self::SIZE_IPv4_SOCKET } else { Self::SIZE_IPv6_SOCKET };
-            default_vlq_writer(Vec::with_capacity(buf_size))
-        };
         // todo-minor clean up copy-paste
         match socket_addr {
             SocketAddr::V4(sock4) => {
@@ -75,14 +91,18 @@ impl TryIntoVlq for PeerAddr {
         };
         vlq_writer.put_u16(socket_addr.port())?;
 
-        Ok(vlq_writer.into_inner())
+        Ok(())
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::io::Write;
+
     use rand::{thread_rng, Rng};
 
+    use crate::encoding::vlq::default_vlq_writer;
+
     use super::*;
 
     enum AddrType {
@@ -124,16 +144,16 @@ mod tests {
 
     fn gen_ip4_octets() -> [u8; 4] {
         let mut ret = [0u8; 4];
-        for index in 0..4 {
-            ret[index] = thread_rng().gen::<u8>();
+        for b in ret.iter_mut() {
+            *b = thread_rng().gen::<u8>();
         }
         ret
     }
 
     fn gen_ip6_octets() -> [u8; 16] {
         let mut ret = [0u8; 16];
-        for index in 0..16 {
-            ret[index] = thread_rng().gen::<u8>();
+        for b in ret.iter_mut() {
+            *b = thread_rng().gen::<u8>();
         }
         ret
     }
@@ -158,7 +178,7 @@ mod tests {
     fn test_parse_valid_ip4_peer_addr() {
         for _ in 0..10 {
             let data = generate_random_peer_addr_bytes(AddrType::Ip4);
-            let peer_addr = PeerAddr::try_from_vlq(data);
+            let peer_addr = PeerAddr::try_from_vlq(&data);
             assert!(peer_addr.is_ok());
             assert!(peer_addr.expect("internal error: can't vlq decode peer addr").0.is_ipv4())
         }
@@ -168,7 +188,7 @@ mod tests {
     fn test_parse_valid_ip6_peer_addr() {
         for _ in 0..10 {
             let data = generate_random_peer_addr_bytes(AddrType::Ip6);
-            let peer_addr = PeerAddr::try_from_vlq(data);
+            let peer_addr = PeerAddr::try_from_vlq(&data);
             assert!(peer_addr.is_ok());
             assert!(peer_addr.expect("internal error: can't vlq decode peer addr").0.is_ipv6())
         }
@@ -178,15 +198,27 @@ mod tests {
     fn test_parse_invalid_addr_len() {
         for len in 0..100 {
             // skip proper lengths
-            if len == PeerAddr::SIZE_IPv4_SOCKET ||
-                len == PeerAddr::SIZE_IPv6_SOCKET ||
-                len == PeerAddr::SIZE_IPv4_SOCKET + PeerAddr::PORT_EXCESS_VLQ_SIZE ||
-                len == PeerAddr::SIZE_IPv6_SOCKET + PeerAddr::PORT_EXCESS_VLQ_SIZE
-            {
+            if (5..=7).contains(&len) || (17..=19).contains(&len) {
                 continue;
             }
             let bytes = vec![0; len];
-            assert!(PeerAddr::try_from_vlq(bytes).is_err());
+            assert!(PeerAddr::try_from_vlq(&bytes).is_err());
         }
     }
+
+    #[test]
+    fn test_one_byte_port_round_trip() {
+        for addr in ["127.0.0.1:80", "[::1]:0"].iter() {
+            let addr = PeerAddr::from_str(addr).expect("internal error: invalid peer addr");
+            let data = addr.try_into_vlq().expect("internal error: can't vlq encode peer addr");
+            assert_eq!(PeerAddr::try_from_vlq(&data).expect("internal error: can't vlq decode peer addr"), addr);
+        }
+    }
+
+    #[test]
+    fn test_parse_invalid_port() {
+        // vlq continuation bit is set in the last byte
+        let data = [127, 0, 0, 1, 0x80, 0x80];
+        assert!(matches!(PeerAddr::try_from_vlq(&data), Err(ModelParseError::CannotDecodePort(_))));
+    }
 }
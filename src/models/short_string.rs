@@ -1,9 +1,11 @@
 use std::convert::TryFrom;
+use std::fmt;
 use std::ops::Deref;
+use std::str::FromStr;
 
 use super::errors::ModelParseError;
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ShortString(String);
 
 impl ShortString {
@@ -22,6 +24,36 @@ impl TryFrom<Vec<u8>> for ShortString {
     }
 }
 
+// Allocates only the owned string, borrowed data is validated first
+impl TryFrom<&[u8]> for ShortString {
+    type Error = ModelParseError;
+
+    fn try_from(data: &[u8]) -> Result<Self, Self::Error> {
+        if data.len() > Self::MAX_SIZE {
+            return Err(ModelParseError::InvalidShortStringLength(data.len()));
+        }
+        let s = std::str::from_utf8(data)?;
+        Ok(Self(s.to_string()))
+    }
+}
+
+impl FromStr for ShortString {
+    type Err = ModelParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::try_from(s.as_bytes())
+    }
+}
+
+impl fmt::Display for ShortString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[cfg(feature = "serde")]
+crate::utils::impl_serde_via_str!(ShortString);
+
 impl Deref for ShortString {
     type Target = String;
 
@@ -1,6 +1,41 @@
-#[derive(Debug, PartialEq, Eq, Default)]
+use std::fmt;
+use std::str::FromStr;
+
+use super::errors::ModelParseError;
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
 pub struct Version(pub [u8; Version::SIZE]);
 
 impl Version {
     pub const SIZE: usize = 3;
 }
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Version([major, minor, patch]) = self;
+        write!(f, "{}.{}.{}", major, minor, patch)
+    }
+}
+
+// Parses versions in "major.minor.patch" form, i.e. "4.0.5"
+impl FromStr for Version {
+    type Err = ModelParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut version = Version::default();
+        let mut parts = s.split('.');
+        for v in version.0.iter_mut() {
+            *v = parts
+                .next()
+                .and_then(|p| p.parse::<u8>().ok())
+                .ok_or_else(|| ModelParseError::InvalidVersion(s.to_string()))?;
+        }
+        if parts.next().is_some() {
+            return Err(ModelParseError::InvalidVersion(s.to_string()));
+        }
+        Ok(version)
+    }
+}
+
+#[cfg(feature = "serde")]
+crate::utils::impl_serde_via_str!(Version);
@@ -0,0 +1,129 @@
+use std::cell::Cell;
+use std::fmt::Debug;
+use std::time::{Duration, Instant};
+
+use crate::messages::Handshake;
+use crate::HandshakingError;
+
+/// Step of handshaking, which failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HandshakeStage {
+    Connect,
+    Send,
+    Receive,
+    Parse,
+    // Session and declared addresses checks of the parsed handshake
+    Validate,
+}
+
+impl HandshakeStage {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HandshakeStage::Connect => "connect",
+            HandshakeStage::Send => "send",
+            HandshakeStage::Receive => "receive",
+            HandshakeStage::Parse => "parse",
+            HandshakeStage::Validate => "validate",
+        }
+    }
+}
+
+/// Callbacks of handshaking steps, i.e. for custom metrics. Peer is "ip:port" or "host:port" of the dialed target.
+///
+/// Callbacks are called on the handshaking thread, so they should be fast.
+pub trait HandshakeObserver: Debug + Send + Sync {
+    fn on_connected(&self, _peer: &str, _elapsed: Duration) {}
+
+    fn on_sent(&self, _peer: &str, _bytes: usize) {}
+
+    /// `reply_time` is counted from sending our handshake, if it was sent first.
+    fn on_received(&self, _peer: &str, _bytes: usize, _reply_time: Duration) {}
+
+    fn on_completed(&self, _peer: &str, _hs: &Handshake, _elapsed: Duration) {}
+
+    fn on_failed(&self, _peer: &str, _stage: HandshakeStage, _err: &HandshakingError) {}
+}
+
+// Reports handshaking steps of one peer to the observer and tracing
+pub(crate) struct Probe<'a> {
+    peer: String,
+    observer: Option<&'a dyn HandshakeObserver>,
+    started: Instant,
+    sent_at: Cell<Option<Instant>>,
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
+}
+
+impl<'a> Probe<'a> {
+    pub(crate) fn new(peer: String, is_inbound: bool, observer: Option<&'a dyn HandshakeObserver>) -> Self {
+        #[cfg(feature = "tracing")]
+        let span = tracing::debug_span!("handshake", peer = %peer, inbound = is_inbound);
+        #[cfg(not(feature = "tracing"))]
+        let _ = is_inbound;
+        Probe {
+            peer,
+            observer,
+            started: Instant::now(),
+            sent_at: Cell::new(None),
+            #[cfg(feature = "tracing")]
+            span,
+        }
+    }
+
+    pub(crate) fn in_span<T>(&self, f: impl FnOnce() -> T) -> T {
+        #[cfg(feature = "tracing")]
+        let _entered = self.span.enter();
+        f()
+    }
+
+    pub(crate) fn connected(&self) {
+        let elapsed = self.started.elapsed();
+        #[cfg(feature = "tracing")]
+        tracing::debug!(elapsed_ms = elapsed.as_millis() as u64, "connected");
+        if let Some(observer) = self.observer {
+            observer.on_connected(&self.peer, elapsed);
+        }
+    }
+
+    pub(crate) fn sent(&self, bytes: usize) {
+        self.sent_at.set(Some(Instant::now()));
+        #[cfg(feature = "tracing")]
+        tracing::debug!(bytes, "sent handshake");
+        if let Some(observer) = self.observer {
+            observer.on_sent(&self.peer, bytes);
+        }
+    }
+
+    pub(crate) fn received(&self, bytes: usize) {
+        let reply_time = self.sent_at.get().unwrap_or(self.started).elapsed();
+        #[cfg(feature = "tracing")]
+        tracing::debug!(bytes, reply_ms = reply_time.as_millis() as u64, "received handshake");
+        if let Some(observer) = self.observer {
+            observer.on_received(&self.peer, bytes, reply_time);
+        }
+    }
+
+    pub(crate) fn completed(&self, hs: &Handshake) {
+        let elapsed = self.started.elapsed();
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            elapsed_ms = elapsed.as_millis() as u64,
+            agent = hs.agent_name.as_str(),
+            version = %hs.version,
+            "handshake completed"
+        );
+        if let Some(observer) = self.observer {
+            observer.on_completed(&self.peer, hs, elapsed);
+        }
+    }
+
+    /// Reports the error and passes it through.
+    pub(crate) fn failed(&self, stage: HandshakeStage, err: HandshakingError) -> HandshakingError {
+        #[cfg(feature = "tracing")]
+        tracing::debug!(stage = stage.as_str(), kind = err.kind(), error = %err, "handshaking failed");
+        if let Some(observer) = self.observer {
+            observer.on_failed(&self.peer, stage, &err);
+        }
+        err
+    }
+}
@@ -0,0 +1,29 @@
+use std::io;
+
+use thiserror::Error;
+
+use crate::encoding::VlqDecodeError;
+use crate::messages::{HsSpecReaderError, HsSpecWriterError};
+use crate::models::{ModelParseError, ModelSerializeError};
+
+#[derive(Error, Debug)]
+pub enum PeerBookError {
+    #[error("Can't access peer book file: {0}")]
+    FailedIoOp(#[from] io::Error),
+    #[error("Peer book file has invalid header")]
+    InvalidHeader,
+    #[error("Peer book file format version {0} isn't supported")]
+    UnsupportedVersion(u8),
+    #[error("Decoding peer book data failed: {0}")]
+    CannotVlqDecodeData(#[from] VlqDecodeError),
+    #[error("Can't read peer address: {0}")]
+    CannotReadPeerAddr(#[from] ModelParseError),
+    #[error("Can't write peer address: {0}")]
+    CannotWritePeerAddr(#[from] ModelSerializeError),
+    #[error("Can't read peer handshake: {0}")]
+    CannotReadHandshake(#[from] HsSpecReaderError),
+    #[error("Can't write peer handshake: {0}")]
+    CannotWriteHandshake(#[from] HsSpecWriterError),
+    #[error("Unknown peer address source {0}")]
+    UnknownAddrSource(u8),
+}
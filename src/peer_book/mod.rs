@@ -0,0 +1,350 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::features::{Mode, PeerFeature};
+use crate::messages::{Handshake, Peers};
+use crate::models::{PeerAddr, ShortString, Version};
+use crate::utils::make_timestamp;
+
+pub use peer_book_errors::*;
+
+use errors as peer_book_errors;
+
+mod errors;
+mod storage;
+
+/// Known peers addresses with the data received from them.
+///
+/// Records are keyed by the address, which is used to connect to the peer.
+/// When the book is full, addresses of less trusted sources are evicted first, see [`PeerBook::with_capacity`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerBook {
+    peers: HashMap<PeerAddr, PeerRecord>,
+    capacity: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerRecord {
+    pub addr: PeerAddr,
+    pub source: AddrSource,
+    // The last handshake received from the peer
+    pub handshake: Option<Handshake>,
+    // Unix time in millis of the last successful handshake
+    pub last_seen: Option<u64>,
+    // Failed connection attempts since the last successful handshake
+    pub failures: u32,
+}
+
+/// How the address was learned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddrSource {
+    /// Handshake was made with the peer by this address.
+    Connected,
+    /// Declared public address of the peer.
+    Declared,
+    /// `LocalAddr` feature of the peer.
+    LocalAddr,
+    /// Received in `Peers` message.
+    Gossip,
+    /// Added by the user, never evicted.
+    Manual,
+}
+
+impl AddrSource {
+    // Records are evicted in the order of sources retention
+    fn retention(self) -> u8 {
+        match self {
+            AddrSource::Gossip => 0,
+            AddrSource::LocalAddr => 1,
+            AddrSource::Declared => 2,
+            AddrSource::Connected => 3,
+            AddrSource::Manual => 4,
+        }
+    }
+}
+
+impl Default for PeerBook {
+    fn default() -> Self {
+        PeerBook {
+            peers: HashMap::new(),
+            capacity: Self::DEFAULT_CAPACITY,
+        }
+    }
+}
+
+impl PeerBook {
+    pub const DEFAULT_CAPACITY: usize = 4096;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Maximum count of records. New address evicts the record of the same or less trusted source:
+    /// gossiped addresses go first, then local, declared and connected ones, manual addresses are kept.
+    /// Among records of one source the one with the most failures is evicted, then the least recently seen one.
+    /// Records above the capacity, i.e. loaded ones, are evicted at once.
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self.evict_excess();
+        self
+    }
+
+    /// Loads peer book saved by [`PeerBook::save`]. Missing file gives an empty book.
+    ///
+    /// Book has the default capacity, the excess records are evicted as on addition.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, PeerBookError> {
+        storage::load(path.as_ref())
+    }
+
+    /// Writes peer book to the file. The file is replaced atomically, so a failed save keeps the previous one.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), PeerBookError> {
+        storage::save(self, path.as_ref())
+    }
+
+    pub fn get(&self, addr: &PeerAddr) -> Option<&PeerRecord> {
+        self.peers.get(addr)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &PeerRecord> {
+        self.peers.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.peers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.peers.is_empty()
+    }
+
+    pub fn remove(&mut self, addr: &PeerAddr) -> Option<PeerRecord> {
+        self.peers.remove(addr)
+    }
+
+    /// Adds unknown address, returns whether it was added. Records of known addresses are kept as is.
+    /// Address isn't added to the full book, which has no records to evict for it.
+    pub fn add_addr(&mut self, addr: PeerAddr, source: AddrSource) -> bool {
+        if self.peers.contains_key(&addr) || !self.make_room(source) {
+            return false;
+        }
+        self.peers.insert(addr.clone(), PeerRecord::new(addr, source));
+        true
+    }
+
+    /// Records handshake received from the peer connected by `addr`, also adding addresses the peer announced.
+    ///
+    /// Features are stored as they're parsed, so the record is the same after [`PeerBook::save`] and [`PeerBook::load`]:
+    /// unrecognized features are dropped, empty features are `None`.
+    pub fn record_handshake(&mut self, addr: PeerAddr, hs: &Handshake) {
+        self.merge_spec(hs, AddrSource::Declared);
+        if !self.peers.contains_key(&addr) && !self.make_room(AddrSource::Connected) {
+            return;
+        }
+        let record = self
+            .peers
+            .entry(addr.clone())
+            .or_insert_with(|| PeerRecord::new(addr, AddrSource::Connected));
+        // manual addresses stay manual, so they aren't evicted
+        if record.source.retention() < AddrSource::Connected.retention() {
+            record.source = AddrSource::Connected;
+        }
+        record.handshake = Some(normalized(hs));
+        record.last_seen = Some(make_timestamp());
+        record.failures = 0;
+    }
+
+    /// Records failed connection or handshake with the known peer, returns whether the peer is known.
+    pub fn record_failure(&mut self, addr: &PeerAddr) -> bool {
+        match self.peers.get_mut(addr) {
+            Some(record) => {
+                record.failures = record.failures.saturating_add(1);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Adds addresses from peer specs received in `Peers` message, returns count of the new ones.
+    pub fn add_peers(&mut self, peers: &Peers) -> usize {
+        peers.peers.iter().map(|spec| self.merge_spec(spec, AddrSource::Gossip)).sum()
+    }
+
+    // Returns count of the added addresses
+    fn merge_spec(&mut self, spec: &Handshake, declared_source: AddrSource) -> usize {
+        let mut added = 0;
+        if let Some(addr) = spec.pub_address.as_ref() {
+            added += usize::from(self.add_addr(addr.clone(), declared_source));
+        }
+        let local_addrs = spec.features.iter().flat_map(|f| f.iter()).filter_map(|f| match f {
+            PeerFeature::LocalAddr(addr) => Some(addr.clone()),
+            _ => None,
+        });
+        for addr in local_addrs {
+            added += usize::from(self.add_addr(addr, AddrSource::LocalAddr));
+        }
+        added
+    }
+
+    // Evicts records till there is a room for the new one, returns whether there is
+    fn make_room(&mut self, source: AddrSource) -> bool {
+        while self.peers.len() >= self.capacity {
+            match self.eviction_candidate(source) {
+                Some(addr) => self.peers.remove(&addr),
+                None => return false,
+            };
+        }
+        true
+    }
+
+    pub(super) fn evict_excess(&mut self) {
+        while self.peers.len() > self.capacity {
+            match self.eviction_candidate(AddrSource::Connected) {
+                Some(addr) => self.peers.remove(&addr),
+                None => return,
+            };
+        }
+    }
+
+    // Record of the least trusted source, which isn't more trusted than `source`
+    fn eviction_candidate(&self, source: AddrSource) -> Option<PeerAddr> {
+        self.peers
+            .values()
+            .filter(|r| r.source != AddrSource::Manual && r.source.retention() <= source.retention())
+            .min_by_key(|r| (r.source.retention(), Reverse(r.failures), r.last_seen))
+            .map(|r| r.addr.clone())
+    }
+}
+
+fn normalized(hs: &Handshake) -> Handshake {
+    let mut hs = hs.clone();
+    if let Some(features) = hs.features.as_mut() {
+        features.retain(|f| f != &PeerFeature::Unrecognized);
+    }
+    hs.features = hs.features.filter(|f| !f.is_empty());
+    hs
+}
+
+impl PeerRecord {
+    fn new(addr: PeerAddr, source: AddrSource) -> Self {
+        PeerRecord {
+            addr,
+            source,
+            handshake: None,
+            last_seen: None,
+            failures: 0,
+        }
+    }
+
+    pub fn agent_name(&self) -> Option<&ShortString> {
+        self.handshake.as_ref().map(|hs| &hs.agent_name)
+    }
+
+    pub fn version(&self) -> Option<&Version> {
+        self.handshake.as_ref().map(|hs| &hs.version)
+    }
+
+    pub fn declared_addr(&self) -> Option<&PeerAddr> {
+        self.handshake.as_ref().and_then(|hs| hs.pub_address.as_ref())
+    }
+
+    pub fn mode(&self) -> Option<&Mode> {
+        let features = self.handshake.as_ref().and_then(|hs| hs.features.as_ref())?;
+        features.iter().find_map(|f| match f {
+            PeerFeature::Mode(mode) => Some(mode),
+            _ => None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use super::*;
+
+    // real app handshake with public address 213.239.193.208:9030, mode and session id features
+    const HS_HEX: &str = "dee2aca3fb2e076572676f726566040005126572676f2d6d61696e6e65742d342e302e310108d5efc1d0c64602100400010001030e01000204eecc9582ffaaafeeaa01";
+
+    fn hs() -> Handshake {
+        Handshake::parse(&hex::decode(HS_HEX).expect("internal error: invalid hex str")).expect("internal error: can't parse hs")
+    }
+
+    fn addr(ip: [u8; 4], port: u16) -> PeerAddr {
+        PeerAddr(SocketAddr::from((ip, port)))
+    }
+
+    #[test]
+    fn test_record_handshake() {
+        let mut book = PeerBook::new();
+        let connected = addr([127, 0, 0, 1], 9030);
+        assert!(book.add_addr(connected.clone(), AddrSource::Manual));
+        assert!(book.record_failure(&connected));
+
+        book.record_handshake(connected.clone(), &hs());
+        let record = book.get(&connected).expect("internal error: no record");
+        assert_eq!(record.source, AddrSource::Manual);
+        assert_eq!(record.failures, 0);
+        assert!(record.last_seen.is_some());
+        assert_eq!(record.agent_name().map(|s| s.as_str()), Some("ergoref"));
+        assert_eq!(record.version(), Some(&Version([4, 0, 5])));
+        assert!(record.mode().is_some());
+
+        // declared address is learned, but isn't connected yet
+        let declared = record.declared_addr().cloned().expect("internal error: no declared address");
+        let declared_record = book.get(&declared).expect("internal error: no declared address record");
+        assert_eq!(declared_record.source, AddrSource::Declared);
+        assert_eq!(declared_record.handshake, None);
+        assert_eq!(book.len(), 2);
+
+        book.record_handshake(declared.clone(), &Handshake { pub_address: None, ..hs() });
+        assert_eq!(book.get(&declared).map(|r| r.source), Some(AddrSource::Connected));
+    }
+
+    #[test]
+    fn test_add_peers() {
+        let mut book = PeerBook::new();
+        let mut spec = hs();
+        spec.features.as_mut().expect("internal error: no features").push(PeerFeature::LocalAddr(addr([10, 0, 0, 2], 9030)));
+        let peers = Peers { peers: vec![spec.clone(), spec] };
+
+        assert_eq!(book.add_peers(&peers), 2);
+        assert_eq!(book.add_peers(&peers), 0);
+        let local = book.get(&addr([10, 0, 0, 2], 9030)).expect("internal error: no local address record");
+        assert_eq!(local.source, AddrSource::LocalAddr);
+        assert!(!book.record_failure(&addr([1, 1, 1, 1], 1)));
+    }
+
+    #[test]
+    fn test_capacity_eviction() {
+        let mut book = PeerBook::new().with_capacity(3);
+        assert!(book.add_addr(addr([10, 0, 0, 1], 9030), AddrSource::Manual));
+        assert!(book.add_addr(addr([10, 0, 0, 2], 9030), AddrSource::Gossip));
+        assert!(book.add_addr(addr([10, 0, 0, 3], 9030), AddrSource::Gossip));
+        book.record_failure(&addr([10, 0, 0, 2], 9030));
+
+        // gossiped address with the most failures is evicted
+        assert!(book.add_addr(addr([10, 0, 0, 4], 9030), AddrSource::Declared));
+        assert_eq!(book.len(), 3);
+        assert!(book.get(&addr([10, 0, 0, 2], 9030)).is_none());
+
+        // gossiped addresses replace each other, but not the declared one
+        assert!(book.add_addr(addr([10, 0, 0, 5], 9030), AddrSource::Gossip));
+        assert!(book.add_addr(addr([10, 0, 0, 6], 9030), AddrSource::Gossip));
+        assert!(book.get(&addr([10, 0, 0, 5], 9030)).is_none());
+        assert!(book.get(&addr([10, 0, 0, 4], 9030)).is_some());
+
+        // excess records are evicted, when the capacity is lowered
+        let shrunk = book.clone().with_capacity(1);
+        assert_eq!(shrunk.iter().map(|r| r.addr.clone()).collect::<Vec<_>>(), vec![addr([10, 0, 0, 1], 9030)]);
+
+        let mut manual_book = PeerBook::new().with_capacity(1);
+        assert!(manual_book.add_addr(addr([10, 0, 0, 1], 9030), AddrSource::Manual));
+        assert!(!manual_book.add_addr(addr([10, 0, 0, 2], 9030), AddrSource::Connected));
+
+        book.record_handshake(addr([10, 0, 0, 7], 9030), &Handshake { pub_address: None, ..hs() });
+        assert_eq!(book.len(), 3);
+        assert!(book.get(&addr([10, 0, 0, 1], 9030)).is_some());
+        assert_eq!(book.get(&addr([10, 0, 0, 7], 9030)).map(|r| r.source), Some(AddrSource::Connected));
+    }
+}
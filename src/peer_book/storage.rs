@@ -0,0 +1,218 @@
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+
+use crate::encoding::vlq::{default_vlq_reader, default_vlq_writer, ReadVlqExt, SliceReader, TryFromVlq, TryIntoVlq, WriteVlqExt};
+use crate::encoding::VlqDecodeError;
+use crate::messages::Handshake;
+use crate::models::PeerAddr;
+
+use super::{AddrSource, PeerBook, PeerBookError, PeerRecord};
+
+// File starts with magic and format version, then records count and records follow. Record layout:
+// address length, address, source, last seen flag and time, failures, handshake flag, length and handshake bytes.
+const FILE_MAGIC: [u8; 4] = *b"ERPB";
+const FORMAT_VERSION: u8 = 1;
+
+pub(super) fn load(path: &Path) -> Result<PeerBook, PeerBookError> {
+    let data = match fs::read(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(PeerBook::new()),
+        Err(e) => return Err(PeerBookError::FailedIoOp(e)),
+    };
+    let mut reader = default_vlq_reader(&data);
+    if read_slice(&mut reader, FILE_MAGIC.len())? != FILE_MAGIC {
+        return Err(PeerBookError::InvalidHeader);
+    }
+    let version = reader.get_u8()?;
+    if version != FORMAT_VERSION {
+        return Err(PeerBookError::UnsupportedVersion(version));
+    }
+
+    let mut book = PeerBook::new();
+    let count = reader.get_u32()?;
+    for _ in 0..count {
+        let record = read_record(&mut reader)?;
+        book.peers.insert(record.addr.clone(), record);
+    }
+    book.evict_excess();
+    Ok(book)
+}
+
+pub(super) fn save(book: &PeerBook, path: &Path) -> Result<(), PeerBookError> {
+    let mut writer = default_vlq_writer(Vec::new());
+    writer.write_all(&FILE_MAGIC)?;
+    writer.put_u8(FORMAT_VERSION)?;
+    writer.put_u32(book.peers.len() as u32)?;
+    for record in book.peers.values() {
+        write_record(&mut writer, record)?;
+    }
+
+    let tmp_path = {
+        let mut p = path.as_os_str().to_owned();
+        p.push(".tmp");
+        p
+    };
+    let mut file = File::create(&tmp_path)?;
+    file.write_all(writer.get_ref())?;
+    file.sync_all()?;
+    fs::rename(&tmp_path, path).map_err(PeerBookError::FailedIoOp)
+}
+
+fn read_record(reader: &mut SliceReader) -> Result<PeerRecord, PeerBookError> {
+    let addr = {
+        let len = reader.get_u8()?;
+        PeerAddr::try_from_vlq(read_slice(reader, len as usize)?)?
+    };
+    let source = source_from_u8(reader.get_u8()?)?;
+    let last_seen = if reader.get_u8()? == 1 { Some(reader.get_u64()?) } else { None };
+    let failures = reader.get_u32()?;
+    let handshake = if reader.get_u8()? == 1 {
+        let len = reader.get_u32()?;
+        Some(Handshake::parse(read_slice(reader, len as usize)?)?)
+    } else {
+        None
+    };
+    Ok(PeerRecord {
+        addr,
+        source,
+        handshake,
+        last_seen,
+        failures,
+    })
+}
+
+fn write_record<W: WriteVlqExt>(writer: &mut W, record: &PeerRecord) -> Result<(), PeerBookError> {
+    let addr = record.addr.try_into_vlq()?;
+    writer.put_u8(addr.len() as u8)?;
+    writer.write_all(&addr)?;
+    writer.put_u8(source_to_u8(record.source))?;
+    match record.last_seen {
+        Some(time) => {
+            writer.put_u8(1)?;
+            writer.put_u64(time)?;
+        }
+        None => writer.put_u8(0)?,
+    }
+    writer.put_u32(record.failures)?;
+    match record.handshake.as_ref() {
+        Some(hs) => {
+            let hs = hs.serialize()?;
+            writer.put_u8(1)?;
+            writer.put_u32(hs.len() as u32)?;
+            writer.write_all(&hs)?;
+        }
+        None => writer.put_u8(0)?,
+    }
+    Ok(())
+}
+
+fn read_slice<'a>(reader: &mut SliceReader<'a>, len: usize) -> Result<&'a [u8], PeerBookError> {
+    reader.read_slice(len).map_err(|e| PeerBookError::CannotVlqDecodeData(VlqDecodeError::from(e)))
+}
+
+fn source_to_u8(source: AddrSource) -> u8 {
+    match source {
+        AddrSource::Connected => 0,
+        AddrSource::Declared => 1,
+        AddrSource::LocalAddr => 2,
+        AddrSource::Gossip => 3,
+        AddrSource::Manual => 4,
+    }
+}
+
+fn source_from_u8(source: u8) -> Result<AddrSource, PeerBookError> {
+    match source {
+        0 => Ok(AddrSource::Connected),
+        1 => Ok(AddrSource::Declared),
+        2 => Ok(AddrSource::LocalAddr),
+        3 => Ok(AddrSource::Gossip),
+        4 => Ok(AddrSource::Manual),
+        _ => Err(PeerBookError::UnknownAddrSource(source)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+    use std::path::PathBuf;
+
+    use crate::features::{Features, PeerFeature};
+
+    use super::*;
+
+    // real app handshake with public address, mode and session id features
+    const HS_HEX: &str = "dee2aca3fb2e076572676f726566040005126572676f2d6d61696e6e65742d342e302e310108d5efc1d0c64602100400010001030e01000204eecc9582ffaaafeeaa01";
+
+    fn tmp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("ergo-hs-{}-{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn test_save_load_round_trip() {
+        let hs = Handshake::parse(&hex::decode(HS_HEX).expect("internal error: invalid hex str")).expect("internal error: can't parse hs");
+        let mut book = PeerBook::new();
+        let connected = PeerAddr(SocketAddr::from(([127, 0, 0, 1], 9030)));
+        book.record_handshake(connected.clone(), &hs);
+        book.add_addr(PeerAddr(SocketAddr::from(([0xfe, 0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1], 9030))), AddrSource::Gossip);
+        book.add_addr(PeerAddr(SocketAddr::from(([10, 0, 0, 1], 1))), AddrSource::Manual);
+        book.record_failure(&connected);
+
+        let path = tmp_path("round-trip");
+        book.save(&path).expect("internal error: can't save peer book");
+        let loaded = PeerBook::load(&path).expect("internal error: can't load peer book");
+        fs::remove_file(&path).expect("internal error: can't remove peer book file");
+        assert_eq!(loaded, book);
+    }
+
+    #[test]
+    fn test_load_applies_capacity() {
+        let mut book = PeerBook::new().with_capacity(PeerBook::DEFAULT_CAPACITY + 1);
+        for i in 0..=PeerBook::DEFAULT_CAPACITY {
+            book.add_addr(PeerAddr(SocketAddr::from(([10, 0, (i >> 8) as u8, i as u8], 9030))), AddrSource::Gossip);
+        }
+        book.add_addr(PeerAddr(SocketAddr::from(([10, 1, 0, 0], 9030))), AddrSource::Manual);
+
+        let path = tmp_path("capacity");
+        book.save(&path).expect("internal error: can't save peer book");
+        let loaded = PeerBook::load(&path).expect("internal error: can't load peer book");
+        fs::remove_file(&path).expect("internal error: can't remove peer book file");
+        assert_eq!(loaded.len(), PeerBook::DEFAULT_CAPACITY);
+        assert!(loaded.get(&PeerAddr(SocketAddr::from(([10, 1, 0, 0], 9030)))).is_some());
+    }
+
+    #[test]
+    fn test_empty_features_round_trip() {
+        let mut hs = Handshake::parse(&hex::decode(HS_HEX).expect("internal error: invalid hex str")).expect("internal error: can't parse hs");
+        hs.features = Some(Features::try_new(vec![PeerFeature::Unrecognized]).expect("internal error: invalid features"));
+        let mut book = PeerBook::new();
+        book.record_handshake(PeerAddr(SocketAddr::from(([127, 0, 0, 1], 9030))), &hs);
+        hs.features = Some(Features::try_new(Vec::new()).expect("internal error: invalid features"));
+        book.record_handshake(PeerAddr(SocketAddr::from(([127, 0, 0, 2], 9030))), &hs);
+
+        let path = tmp_path("empty-features");
+        book.save(&path).expect("internal error: can't save peer book");
+        let loaded = PeerBook::load(&path).expect("internal error: can't load peer book");
+        fs::remove_file(&path).expect("internal error: can't remove peer book file");
+        assert_eq!(loaded, book);
+        assert_eq!(loaded.iter().filter(|r| r.handshake.is_some()).count(), 2);
+        assert!(loaded.iter().filter_map(|r| r.handshake.as_ref()).all(|hs| hs.features.is_none()));
+    }
+
+    #[test]
+    fn test_load_missing_and_invalid() {
+        let path = tmp_path("invalid");
+        assert!(PeerBook::load(&path).expect("internal error: can't load missing peer book").is_empty());
+
+        PeerBook::new().save(&path).expect("internal error: can't save peer book");
+        let mut data = fs::read(&path).expect("internal error: can't read peer book file");
+        data[FILE_MAGIC.len()] = FORMAT_VERSION + 1;
+        fs::write(&path, &data).expect("internal error: can't write peer book file");
+        assert!(matches!(PeerBook::load(&path), Err(PeerBookError::UnsupportedVersion(2))));
+
+        // records count without records
+        fs::write(&path, [&FILE_MAGIC[..], &[FORMAT_VERSION, 1]].concat()).expect("internal error: can't write peer book file");
+        assert!(matches!(PeerBook::load(&path), Err(PeerBookError::CannotVlqDecodeData(VlqDecodeError::Truncated))));
+        fs::remove_file(&path).expect("internal error: can't remove peer book file");
+    }
+}
@@ -0,0 +1,434 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+use crate::HandshakingError;
+
+pub use subnet::Subnet;
+
+mod subnet;
+
+/// Peer behavior, which is penalized.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Misbehavior {
+    /// Connection or handshake read timed out.
+    Timeout,
+    /// Connection was refused or reset.
+    ConnectionFailed,
+    MalformedHandshake,
+    /// Peer's session id feature has magic bytes of another network.
+    WrongNetwork,
+    /// Violation of the protocol after handshake, i.e. invalid message checksum.
+    ProtocolViolation,
+}
+
+impl Misbehavior {
+    pub fn penalty(&self) -> u32 {
+        match self {
+            Misbehavior::Timeout => 20,
+            Misbehavior::ConnectionFailed => 10,
+            Misbehavior::MalformedHandshake => 50,
+            Misbehavior::ProtocolViolation => 50,
+            Misbehavior::WrongNetwork => ReputationConfig::DEFAULT_BAN_THRESHOLD,
+        }
+    }
+
+    /// Misbehavior, which caused handshaking failure. Local errors, like serialization ones, aren't peer's fault.
+    /// Neither is connection to self, i.e. through a declared or reflected own address.
+    pub fn from_error(err: &HandshakingError) -> Option<Self> {
+        use std::io::ErrorKind;
+
+        match err {
+            HandshakingError::FailedIoOp(e) => match e.kind() {
+                ErrorKind::TimedOut | ErrorKind::WouldBlock => Some(Misbehavior::Timeout),
+                ErrorKind::ConnectionRefused
+                | ErrorKind::ConnectionReset
+                | ErrorKind::ConnectionAborted
+                | ErrorKind::BrokenPipe
+                | ErrorKind::UnexpectedEof => Some(Misbehavior::ConnectionFailed),
+                // unreachable network, exhausted ports and such are failures of the local host
+                _ => None,
+            },
+            HandshakingError::MessageParseError(_) => Some(Misbehavior::MalformedHandshake),
+            HandshakingError::WrongNetwork(_) => Some(Misbehavior::WrongNetwork),
+            HandshakingError::MessageSerializeError(_)
+            | HandshakingError::SelfConnection
+            | HandshakingError::Banned(_)
+            | HandshakingError::Rejected(_)
+            | HandshakingError::UnroutableAddr(_)
+            | HandshakingError::ProxyFailed(_) => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReputationConfig {
+    /// Accumulated penalty, which bans the peer.
+    pub ban_threshold: u32,
+    /// Duration of the first ban, each next ban of the same ip is twice longer.
+    pub base_ban_duration: Duration,
+    pub max_ban_duration: Duration,
+    /// Count of simultaneously banned ips in one subnet, which bans the whole subnet.
+    pub subnet_ban_threshold: usize,
+    /// Accumulated penalty is forgotten, when the ip isn't reported for this time.
+    pub penalty_ttl: Duration,
+    /// Count of bans, which makes the next ban longer, is forgotten this time after the last ban ends.
+    pub bans_count_ttl: Duration,
+    /// Count of tracked ips, the least recently reported unbanned ones are forgotten first.
+    pub max_tracked_ips: usize,
+}
+
+impl ReputationConfig {
+    pub const DEFAULT_BAN_THRESHOLD: u32 = 100;
+}
+
+impl Default for ReputationConfig {
+    fn default() -> Self {
+        ReputationConfig {
+            ban_threshold: Self::DEFAULT_BAN_THRESHOLD,
+            base_ban_duration: Duration::from_secs(10 * 60),
+            max_ban_duration: Duration::from_secs(24 * 60 * 60),
+            subnet_ban_threshold: 4,
+            penalty_ttl: Duration::from_secs(60 * 60),
+            bans_count_ttl: Duration::from_secs(7 * 24 * 60 * 60),
+            max_tracked_ips: 16 * 1024,
+        }
+    }
+}
+
+/// Penalty scores and bans of peers ips and subnets.
+///
+/// It's shared between connections, so state is guarded by a mutex.
+#[derive(Debug, Default)]
+pub struct PeerReputation {
+    config: ReputationConfig,
+    state: Mutex<ReputationState>,
+}
+
+// Expired scores are dropped at most once per interval, so reports don't scan all of them
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Default)]
+struct ReputationState {
+    scores: HashMap<IpAddr, IpScore>,
+    subnet_bans: HashMap<Subnet, Instant>,
+    pruned_at: Option<Instant>,
+}
+
+impl ReputationState {
+    fn prune(&mut self, config: &ReputationConfig, now: Instant) {
+        self.scores.retain(|_, score| {
+            score.expire(config, now);
+            !score.is_empty()
+        });
+        self.subnet_bans.retain(|_, until| *until > now);
+        self.pruned_at = Some(now);
+    }
+
+    fn prune_if_due(&mut self, config: &ReputationConfig, now: Instant) {
+        if self.pruned_at.is_none_or(|at| now.saturating_duration_since(at) >= PRUNE_INTERVAL) {
+            self.prune(config, now);
+        }
+    }
+
+    // Score of the ip with expired values dropped, an untracked ip may evict another one
+    fn score_mut(&mut self, ip: IpAddr, config: &ReputationConfig, now: Instant) -> &mut IpScore {
+        if !self.scores.contains_key(&ip) && self.scores.len() >= config.max_tracked_ips {
+            self.prune(config, now);
+        }
+        if !self.scores.contains_key(&ip) && self.scores.len() >= config.max_tracked_ips {
+            let evicted = self
+                .scores
+                .iter()
+                .min_by_key(|(_, score)| (score.is_banned(now), score.reported_at))
+                .map(|(ip, _)| *ip);
+            if let Some(evicted) = evicted {
+                self.scores.remove(&evicted);
+            }
+        }
+        let score = self.scores.entry(ip).or_insert_with(|| IpScore::new(now));
+        score.expire(config, now);
+        score
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct IpScore {
+    penalty: u32,
+    // time of the last penalty, which keeps the accumulated one from expiring
+    reported_at: Instant,
+    bans_count: u32,
+    banned_until: Option<Instant>,
+}
+
+impl IpScore {
+    fn new(now: Instant) -> Self {
+        IpScore {
+            penalty: 0,
+            reported_at: now,
+            bans_count: 0,
+            banned_until: None,
+        }
+    }
+
+    fn is_banned(&self, now: Instant) -> bool {
+        self.banned_until.is_some_and(|until| until > now)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.penalty == 0 && self.bans_count == 0 && self.banned_until.is_none()
+    }
+
+    fn expire(&mut self, config: &ReputationConfig, now: Instant) {
+        if now.saturating_duration_since(self.reported_at) >= config.penalty_ttl {
+            self.penalty = 0;
+        }
+        if self.banned_until.is_some_and(|until| now.saturating_duration_since(until) >= config.bans_count_ttl) {
+            self.bans_count = 0;
+            self.banned_until = None;
+        }
+    }
+}
+
+impl PeerReputation {
+    pub fn new(config: ReputationConfig) -> Self {
+        PeerReputation {
+            config,
+            state: Mutex::new(ReputationState::default()),
+        }
+    }
+
+    pub fn config(&self) -> &ReputationConfig {
+        &self.config
+    }
+
+    /// Penalizes the ip, returns ban duration, if the ip got banned.
+    pub fn report(&self, ip: IpAddr, misbehavior: Misbehavior) -> Option<Duration> {
+        self.report_at(ip, misbehavior, Instant::now())
+    }
+
+    /// Whether the ip or its subnet is banned.
+    pub fn is_banned(&self, ip: IpAddr) -> bool {
+        self.is_banned_at(ip, Instant::now())
+    }
+
+    pub fn ban(&self, ip: IpAddr, duration: Duration) {
+        let now = Instant::now();
+        let mut state = self.lock();
+        state.prune_if_due(&self.config, now);
+        state.score_mut(ip.to_canonical(), &self.config, now).banned_until = Some(now + duration);
+    }
+
+    pub fn ban_subnet(&self, subnet: Subnet, duration: Duration) {
+        let now = Instant::now();
+        let mut state = self.lock();
+        state.prune_if_due(&self.config, now);
+        state.subnet_bans.insert(subnet, now + duration);
+    }
+
+    /// Removes bans and penalties of the ip and its subnet.
+    pub fn unban(&self, ip: IpAddr) {
+        let mut state = self.lock();
+        state.scores.remove(&ip.to_canonical());
+        state.subnet_bans.retain(|subnet, _| !subnet.contains(ip));
+    }
+
+    pub fn penalty(&self, ip: IpAddr) -> u32 {
+        self.penalty_at(ip, Instant::now())
+    }
+
+    fn penalty_at(&self, ip: IpAddr, now: Instant) -> u32 {
+        let mut score = match self.lock().scores.get(&ip.to_canonical()) {
+            Some(score) => *score,
+            None => return 0,
+        };
+        score.expire(&self.config, now);
+        score.penalty
+    }
+
+    fn report_at(&self, ip: IpAddr, misbehavior: Misbehavior, now: Instant) -> Option<Duration> {
+        let ip = ip.to_canonical();
+        let mut state = self.lock();
+        state.prune_if_due(&self.config, now);
+        let score = state.score_mut(ip, &self.config, now);
+        score.penalty = score.penalty.saturating_add(misbehavior.penalty());
+        score.reported_at = now;
+        if score.penalty < self.config.ban_threshold {
+            return None;
+        }
+
+        let duration = self.ban_duration(score.bans_count);
+        score.penalty = 0;
+        score.bans_count = score.bans_count.saturating_add(1);
+        score.banned_until = Some(now + duration);
+
+        // scores are scanned only on bans, which are rare
+        let subnet = Subnet::of(ip);
+        let banned_in_subnet = state
+            .scores
+            .iter()
+            .filter(|(other, score)| Subnet::of(**other) == subnet && score.is_banned(now))
+            .count();
+        if banned_in_subnet >= self.config.subnet_ban_threshold {
+            state.subnet_bans.insert(subnet, now + self.config.base_ban_duration);
+        }
+        Some(duration)
+    }
+
+    fn is_banned_at(&self, ip: IpAddr, now: Instant) -> bool {
+        let ip = ip.to_canonical();
+        let state = self.lock();
+        let is_ip_banned = state.scores.get(&ip).is_some_and(|score| score.is_banned(now));
+        let is_subnet_banned = state
+            .subnet_bans
+            .iter()
+            .any(|(subnet, until)| *until > now && subnet.contains(ip));
+        is_ip_banned || is_subnet_banned
+    }
+
+    fn ban_duration(&self, bans_count: u32) -> Duration {
+        let factor = 1u32.checked_shl(bans_count).unwrap_or(u32::MAX);
+        self.config
+            .base_ban_duration
+            .checked_mul(factor)
+            .map_or(self.config.max_ban_duration, |d| d.min(self.config.max_ban_duration))
+    }
+
+    // State is valid after a panic in another thread, because it's updated without intermediate states
+    fn lock(&self) -> MutexGuard<'_, ReputationState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+
+    fn ip(last: u8) -> IpAddr {
+        IpAddr::V4(Ipv4Addr::new(10, 0, 0, last))
+    }
+
+    #[test]
+    fn test_ban_after_threshold() {
+        let reputation = PeerReputation::default();
+        let now = Instant::now();
+        assert_eq!(reputation.report_at(ip(1), Misbehavior::MalformedHandshake, now), None);
+        assert!(!reputation.is_banned_at(ip(1), now));
+        assert_eq!(reputation.report_at(ip(1), Misbehavior::MalformedHandshake, now), Some(Duration::from_secs(600)));
+        assert!(reputation.is_banned_at(ip(1), now));
+        assert!(!reputation.is_banned_at(ip(1), now + Duration::from_secs(601)));
+        assert!(!reputation.is_banned_at(ip(2), now));
+    }
+
+    #[test]
+    fn test_exponential_ban_duration() {
+        let reputation = PeerReputation::default();
+        let now = Instant::now();
+        let durations = (0..10)
+            .map(|_| reputation.report_at(ip(1), Misbehavior::WrongNetwork, now))
+            .collect::<Vec<_>>();
+        let minutes = |m: u64| Some(Duration::from_secs(m * 60));
+        assert_eq!(&durations[..4], &[minutes(10), minutes(20), minutes(40), minutes(80)]);
+        assert_eq!(durations[9], minutes(24 * 60));
+    }
+
+    #[test]
+    fn test_subnet_ban() {
+        let reputation = PeerReputation::default();
+        let now = Instant::now();
+        for last in 1..4 {
+            reputation.report_at(ip(last), Misbehavior::WrongNetwork, now);
+        }
+        assert!(!reputation.is_banned_at(ip(100), now));
+        reputation.report_at(ip(4), Misbehavior::WrongNetwork, now);
+        assert!(reputation.is_banned_at(ip(100), now));
+        assert!(!reputation.is_banned_at(IpAddr::V4(Ipv4Addr::new(10, 0, 1, 1)), now));
+
+        reputation.unban(ip(100));
+        assert!(!reputation.is_banned_at(ip(100), now));
+        assert!(reputation.is_banned_at(ip(1), now));
+    }
+
+    #[test]
+    fn test_misbehavior_from_error() {
+        use std::io::{Error, ErrorKind};
+
+        let timeout = HandshakingError::FailedIoOp(Error::from(ErrorKind::WouldBlock));
+        assert_eq!(Misbehavior::from_error(&timeout), Some(Misbehavior::Timeout));
+        assert_eq!(Misbehavior::from_error(&HandshakingError::Banned(ip(1))), None);
+        assert_eq!(Misbehavior::from_error(&HandshakingError::SelfConnection), None);
+        let reset = HandshakingError::FailedIoOp(Error::from(ErrorKind::ConnectionReset));
+        assert_eq!(Misbehavior::from_error(&reset), Some(Misbehavior::ConnectionFailed));
+        for kind in [ErrorKind::NetworkUnreachable, ErrorKind::AddrNotAvailable, ErrorKind::PermissionDenied].iter() {
+            assert_eq!(Misbehavior::from_error(&HandshakingError::FailedIoOp(Error::from(*kind))), None);
+        }
+    }
+
+    #[test]
+    fn test_ban_escalates_after_expiry() {
+        let reputation = PeerReputation::default();
+        let now = Instant::now();
+        let minutes = |m: u64| Duration::from_secs(m * 60);
+        assert_eq!(reputation.report_at(ip(1), Misbehavior::WrongNetwork, now), Some(minutes(10)));
+
+        let after_first = now + minutes(11);
+        assert!(!reputation.is_banned_at(ip(1), after_first));
+        assert_eq!(reputation.report_at(ip(1), Misbehavior::WrongNetwork, after_first), Some(minutes(20)));
+
+        // bans count is forgotten long after the last ban
+        let forgotten = after_first + minutes(20) + reputation.config().bans_count_ttl;
+        assert_eq!(reputation.report_at(ip(1), Misbehavior::WrongNetwork, forgotten), Some(minutes(10)));
+    }
+
+    #[test]
+    fn test_penalty_expires() {
+        let reputation = PeerReputation::default();
+        let now = Instant::now();
+        for _ in 0..4 {
+            assert_eq!(reputation.report_at(ip(1), Misbehavior::Timeout, now), None);
+        }
+        assert_eq!(reputation.penalty_at(ip(1), now), 80);
+
+        let later = now + reputation.config().penalty_ttl;
+        assert_eq!(reputation.penalty_at(ip(1), later), 0);
+        assert_eq!(reputation.report_at(ip(1), Misbehavior::Timeout, later), None);
+        assert_eq!(reputation.penalty_at(ip(1), later), 20);
+    }
+
+    #[test]
+    fn test_tracked_ips_cap() {
+        let reputation = PeerReputation::new(ReputationConfig { max_tracked_ips: 2, ..ReputationConfig::default() });
+        let now = Instant::now();
+        reputation.report_at(ip(1), Misbehavior::WrongNetwork, now);
+        reputation.report_at(ip(2), Misbehavior::Timeout, now);
+        reputation.report_at(ip(3), Misbehavior::Timeout, now + Duration::from_secs(1));
+
+        // banned ip is kept, the least recently reported one is evicted
+        let state = reputation.lock();
+        assert_eq!(state.scores.len(), 2);
+        assert!(state.scores.contains_key(&ip(1)));
+        assert!(state.scores.contains_key(&ip(3)));
+    }
+
+    #[test]
+    fn test_expired_scores_are_evicted() {
+        let reputation = PeerReputation::default();
+        let now = Instant::now();
+        reputation.report_at(ip(1), Misbehavior::WrongNetwork, now);
+        reputation.report_at(ip(2), Misbehavior::Timeout, now);
+        assert_eq!(reputation.lock().scores.len(), 2);
+
+        // ban of the first ip has expired, but its count is kept, as well as the penalty of the second one
+        let later = now + Duration::from_secs(601);
+        reputation.report_at(ip(3), Misbehavior::Timeout, later);
+        assert_eq!(reputation.lock().scores.len(), 3);
+
+        let forgotten = later + reputation.config().bans_count_ttl;
+        reputation.report_at(ip(4), Misbehavior::Timeout, forgotten);
+        let state = reputation.lock();
+        assert_eq!(state.scores.keys().collect::<Vec<_>>(), vec![&ip(4)]);
+    }
+}
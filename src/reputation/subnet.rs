@@ -0,0 +1,66 @@
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// Network address with prefix length, i.e. "10.0.0.0/24".
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Subnet {
+    network: IpAddr,
+    prefix_len: u8,
+}
+
+impl Subnet {
+    pub const IPV4_PREFIX_LEN: u8 = 24;
+    pub const IPV6_PREFIX_LEN: u8 = 48;
+
+    /// Subnet of the ip, which is usually controlled by one party. IPv4-mapped addresses are treated as IPv4 ones.
+    pub fn of(ip: IpAddr) -> Self {
+        match ip.to_canonical() {
+            IpAddr::V4(ip) => Self::new(IpAddr::V4(ip), Self::IPV4_PREFIX_LEN),
+            IpAddr::V6(ip) => Self::new(IpAddr::V6(ip), Self::IPV6_PREFIX_LEN),
+        }
+    }
+
+    /// Creates subnet with host bits of the address cleared. Prefix length is capped by the address size.
+    pub fn new(ip: IpAddr, prefix_len: u8) -> Self {
+        match ip {
+            IpAddr::V4(ip) => {
+                let prefix_len = prefix_len.min(32);
+                let mask = u32::MAX.checked_shl(32 - prefix_len as u32).unwrap_or(0);
+                let network = IpAddr::V4(Ipv4Addr::from(u32::from(ip) & mask));
+                Subnet { network, prefix_len }
+            }
+            IpAddr::V6(ip) => {
+                let prefix_len = prefix_len.min(128);
+                let mask = u128::MAX.checked_shl(128 - prefix_len as u32).unwrap_or(0);
+                let network = IpAddr::V6(Ipv6Addr::from(u128::from(ip) & mask));
+                Subnet { network, prefix_len }
+            }
+        }
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        Subnet::new(ip.to_canonical(), self.prefix_len) == *self
+    }
+}
+
+impl fmt::Display for Subnet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix_len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_subnet_of() {
+        let ip = |s: &str| s.parse::<IpAddr>().expect("internal error: invalid ip");
+        assert_eq!(Subnet::of(ip("213.239.193.208")).to_string(), "213.239.193.0/24");
+        assert_eq!(Subnet::of(ip("::ffff:213.239.193.208")), Subnet::of(ip("213.239.193.1")));
+        assert_eq!(Subnet::of(ip("2001:db8:aa:bb::1")).to_string(), "2001:db8:aa::/48");
+        assert!(Subnet::new(ip("10.1.2.3"), 8).contains(ip("10.200.0.1")));
+        assert!(!Subnet::new(ip("10.1.2.3"), 0).contains(ip("::1")));
+        assert!(Subnet::new(ip("10.1.2.3"), 0).contains(ip("1.1.1.1")));
+    }
+}
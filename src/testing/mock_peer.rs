@@ -0,0 +1,195 @@
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::features::{Features, PeerFeature, SessionId};
+use crate::messages::{Handshake, HsSpecWriterError};
+use crate::models::{MagicBytes, PeerAddr};
+
+// Bounds how long a mock peer waits for the client's handshake and holds the connection after responding
+const MOCK_READ_TIMEOUT: Duration = Duration::from_secs(5);
+const MOCK_READ_BUF_SIZE: usize = 8 * 1024;
+
+/// How a [`MockPeer`] answers a single incoming connection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MockBehavior {
+    /// Responds with the configured handshake.
+    Honest,
+    /// Responds with the configured handshake after the delay.
+    Delay(Duration),
+    /// Sends only the first `n` bytes of the handshake and closes the connection.
+    Truncate(usize),
+    /// Responds with the handshake, which session id feature has the provided magic.
+    WrongMagic(MagicBytes),
+    /// Sends the handshake followed by `n` junk bytes.
+    TrailingJunk(usize),
+    /// Responds with the valid handshake of at least `n` bytes, grown with IPv6 local address features.
+    Oversized(usize),
+    /// Responds with the legacy handshake, which ends after the public address without features count.
+    WithoutFeaturesCount,
+    /// Closes the connection without responding.
+    CloseEarly,
+}
+
+/// Local ergo node stand-in, which answers handshakes on a loopback port.
+///
+/// Connections are served in the order of the script: the n-th connection gets the n-th behavior,
+/// the last behavior is used for all the following connections.
+pub struct MockPeer {
+    addr: SocketAddr,
+    received: Arc<Mutex<Vec<Vec<u8>>>>,
+    closed: Arc<AtomicUsize>,
+    is_stopped: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl MockPeer {
+    pub fn spawn(hs: Handshake, behavior: MockBehavior) -> io::Result<Self> {
+        Self::spawn_scripted(hs, vec![behavior])
+    }
+
+    pub fn spawn_scripted(hs: Handshake, script: Vec<MockBehavior>) -> io::Result<Self> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+        let addr = listener.local_addr()?;
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let closed = Arc::new(AtomicUsize::new(0));
+        let is_stopped = Arc::new(AtomicBool::new(false));
+
+        let handle = {
+            let received = Arc::clone(&received);
+            let closed = Arc::clone(&closed);
+            let is_stopped = Arc::clone(&is_stopped);
+            let hs = Arc::new(hs);
+            thread::spawn(move || {
+                for (i, conn) in listener.incoming().enumerate() {
+                    if is_stopped.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    let conn = match conn {
+                        Ok(conn) => conn,
+                        Err(_) => continue,
+                    };
+                    let behavior = script.get(i).or_else(|| script.last()).cloned().unwrap_or(MockBehavior::Honest);
+                    let hs = Arc::clone(&hs);
+                    let received = Arc::clone(&received);
+                    let closed = Arc::clone(&closed);
+                    thread::spawn(move || {
+                        // errors are a part of misbehaving scenarios, client observes them by itself
+                        if let Ok(true) = serve(conn, &hs, behavior, &received) {
+                            closed.fetch_add(1, Ordering::SeqCst);
+                        }
+                    });
+                }
+            })
+        };
+
+        Ok(MockPeer {
+            addr,
+            received,
+            closed,
+            is_stopped,
+            handle: Some(handle),
+        })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Raw bytes received from each served connection before responding.
+    pub fn received(&self) -> Vec<Vec<u8>> {
+        self.received.lock().map(|r| r.clone()).unwrap_or_default()
+    }
+
+    /// Count of connections, which the client closed after the response.
+    pub fn closed(&self) -> usize {
+        self.closed.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for MockPeer {
+    fn drop(&mut self) {
+        self.is_stopped.store(true, Ordering::SeqCst);
+        // wakes up the blocked accept loop
+        let _ = TcpStream::connect(self.addr);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+// Returns whether the client closed the connection after the response
+fn serve(mut conn: TcpStream, hs: &Handshake, behavior: MockBehavior, received: &Mutex<Vec<Vec<u8>>>) -> io::Result<bool> {
+    conn.set_read_timeout(Some(MOCK_READ_TIMEOUT))?;
+    let mut buf = vec![0; MOCK_READ_BUF_SIZE];
+    match conn.read(&mut buf) {
+        Ok(n) => {
+            if let Ok(mut received) = received.lock() {
+                received.push(buf[..n].to_vec());
+            }
+        }
+        // client may wait for our handshake first
+        Err(e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => {}
+        Err(e) => return Err(e),
+    }
+
+    let mut hs_bytes = match &behavior {
+        MockBehavior::WrongMagic(magic) => with_magic(hs, magic.clone()).serialize(),
+        MockBehavior::Oversized(len) => with_local_addrs(hs, *len).and_then(|hs| hs.serialize()),
+        MockBehavior::WithoutFeaturesCount => Handshake { features: None, ..hs.clone() }.serialize(),
+        _ => hs.serialize(),
+    }
+    .map_err(|e| io::Error::new(ErrorKind::InvalidData, e.to_string()))?;
+
+    match behavior {
+        MockBehavior::Honest | MockBehavior::WrongMagic(_) | MockBehavior::Oversized(_) => {}
+        MockBehavior::Delay(delay) => thread::sleep(delay),
+        MockBehavior::Truncate(len) => {
+            hs_bytes.truncate(len);
+            return conn.write_all(&hs_bytes).map(|_| false);
+        }
+        MockBehavior::TrailingJunk(extra) => hs_bytes.resize(hs_bytes.len() + extra, 0xff),
+        // `None` features are written as the zero count
+        MockBehavior::WithoutFeaturesCount => {
+            hs_bytes.pop();
+        }
+        MockBehavior::CloseEarly => return Ok(false),
+    }
+    conn.write_all(&hs_bytes)?;
+    conn.flush()?;
+
+    // holds the connection until the client closes it
+    while conn.read(&mut buf)? > 0 {}
+    Ok(true)
+}
+
+fn with_magic(hs: &Handshake, magic: MagicBytes) -> Handshake {
+    let mut hs = hs.clone();
+    let mut features = hs.features.take().map(|f| f.to_vec()).unwrap_or_default();
+    let session_id = features.iter_mut().find_map(|f| match f {
+        PeerFeature::SessionId(session_id) => Some(session_id),
+        _ => None,
+    });
+    match session_id {
+        Some(session_id) => session_id.magic = magic,
+        None => features.push(PeerFeature::SessionId(SessionId { magic, session_id: 0 })),
+    }
+    hs.features = Features::try_new(features).ok();
+    hs
+}
+
+fn with_local_addrs(hs: &Handshake, min_len: usize) -> Result<Handshake, HsSpecWriterError> {
+    let mut hs = hs.clone();
+    let mut features = hs.features.take().map(|f| f.to_vec()).unwrap_or_default();
+    for port in 1.. {
+        hs.features = Features::try_new(features.clone()).ok();
+        if hs.features.is_none() || hs.serialize()?.len() >= min_len {
+            break;
+        }
+        features.push(PeerFeature::LocalAddr(PeerAddr(SocketAddr::new(Ipv6Addr::LOCALHOST.into(), port))));
+    }
+    Ok(hs)
+}
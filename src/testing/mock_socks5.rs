@@ -0,0 +1,151 @@
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+const MOCK_READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Local SOCKS5 proxy stand-in, which relays CONNECT requests. Domain targets are resolved by the proxy.
+pub struct MockSocks5 {
+    addr: SocketAddr,
+    requests: Arc<Mutex<Vec<String>>>,
+    is_stopped: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl MockSocks5 {
+    /// Proxy, which requires username/password auth, if credentials are provided.
+    pub fn spawn(credentials: Option<(&str, &str)>) -> io::Result<Self> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+        let addr = listener.local_addr()?;
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let is_stopped = Arc::new(AtomicBool::new(false));
+        let credentials = credentials.map(|(u, p)| (u.to_string(), p.to_string()));
+
+        let handle = {
+            let requests = Arc::clone(&requests);
+            let is_stopped = Arc::clone(&is_stopped);
+            let credentials = Arc::new(credentials);
+            thread::spawn(move || {
+                for conn in listener.incoming() {
+                    if is_stopped.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    let conn = match conn {
+                        Ok(conn) => conn,
+                        Err(_) => continue,
+                    };
+                    let requests = Arc::clone(&requests);
+                    let credentials = Arc::clone(&credentials);
+                    thread::spawn(move || {
+                        // failed requests are observed by the client
+                        let _ = serve(conn, credentials.as_ref().as_ref(), &requests);
+                    });
+                }
+            })
+        };
+
+        Ok(MockSocks5 {
+            addr,
+            requests,
+            is_stopped,
+            handle: Some(handle),
+        })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Targets of CONNECT requests in "host:port" form, as the client sent them.
+    pub fn requests(&self) -> Vec<String> {
+        self.requests.lock().map(|r| r.clone()).unwrap_or_default()
+    }
+}
+
+impl Drop for MockSocks5 {
+    fn drop(&mut self) {
+        self.is_stopped.store(true, Ordering::SeqCst);
+        // wakes up the blocked accept loop
+        let _ = TcpStream::connect(self.addr);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+fn serve(mut conn: TcpStream, credentials: Option<&(String, String)>, requests: &Mutex<Vec<String>>) -> io::Result<()> {
+    conn.set_read_timeout(Some(MOCK_READ_TIMEOUT))?;
+
+    let [version, methods_len] = read_array::<2>(&mut conn)?;
+    let mut methods = vec![0u8; methods_len as usize];
+    conn.read_exact(&mut methods)?;
+    let method = if credentials.is_some() { 2 } else { 0 };
+    if version != 5 || !methods.contains(&method) {
+        return conn.write_all(&[5, 0xff]);
+    }
+    conn.write_all(&[5, method])?;
+
+    if let Some((username, password)) = credentials {
+        let [_, username_len] = read_array::<2>(&mut conn)?;
+        let received_username = read_vec(&mut conn, username_len as usize)?;
+        let [password_len] = read_array::<1>(&mut conn)?;
+        let received_password = read_vec(&mut conn, password_len as usize)?;
+        if received_username != username.as_bytes() || received_password != password.as_bytes() {
+            return conn.write_all(&[1, 1]);
+        }
+        conn.write_all(&[1, 0])?;
+    }
+
+    let [_, _, _, atyp] = read_array::<4>(&mut conn)?;
+    let host = match atyp {
+        1 => Ipv4Addr::from(read_array::<4>(&mut conn)?).to_string(),
+        4 => format!("[{}]", Ipv6Addr::from(read_array::<16>(&mut conn)?)),
+        3 => {
+            let [len] = read_array::<1>(&mut conn)?;
+            String::from_utf8_lossy(&read_vec(&mut conn, len as usize)?).to_string()
+        }
+        _ => return Err(io::Error::new(ErrorKind::InvalidData, "unknown address type")),
+    };
+    let port = u16::from_be_bytes(read_array::<2>(&mut conn)?);
+    let target = format!("{}:{}", host, port);
+    if let Ok(mut requests) = requests.lock() {
+        requests.push(target.clone());
+    }
+
+    let upstream = match TcpStream::connect(target.as_str()) {
+        Ok(upstream) => upstream,
+        // host unreachable reply
+        Err(_) => return conn.write_all(&[5, 4, 0, 1, 0, 0, 0, 0, 0, 0]),
+    };
+    conn.write_all(&[5, 0, 0, 1, 0, 0, 0, 0, 0, 0])?;
+    conn.set_read_timeout(None)?;
+    relay(conn, upstream)
+}
+
+fn relay(client: TcpStream, upstream: TcpStream) -> io::Result<()> {
+    let (mut client_reader, mut upstream_writer) = (client.try_clone()?, upstream.try_clone()?);
+    let forward = thread::spawn(move || {
+        let _ = io::copy(&mut client_reader, &mut upstream_writer);
+        let _ = upstream_writer.shutdown(Shutdown::Write);
+    });
+    let (mut upstream_reader, mut client_writer) = (upstream, client);
+    let _ = io::copy(&mut upstream_reader, &mut client_writer);
+    let _ = client_writer.shutdown(Shutdown::Write);
+    let _ = forward.join();
+    Ok(())
+}
+
+fn read_array<const N: usize>(conn: &mut TcpStream) -> io::Result<[u8; N]> {
+    let mut buf = [0u8; N];
+    conn.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+fn read_vec(conn: &mut TcpStream, len: usize) -> io::Result<Vec<u8>> {
+    let mut buf = vec![0u8; len];
+    conn.read_exact(&mut buf)?;
+    Ok(buf)
+}
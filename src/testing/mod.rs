@@ -0,0 +1,33 @@
+#[cfg(test)]
+use std::convert::TryFrom;
+
+#[cfg(test)]
+use crate::features::{Features, Mode, PeerFeature, SessionId};
+#[cfg(test)]
+use crate::messages::Handshake;
+#[cfg(test)]
+use crate::models::{MagicBytes, ShortString, Version};
+
+pub use mock_peer::{MockBehavior, MockPeer};
+pub use mock_socks5::MockSocks5;
+
+mod mock_peer;
+mod mock_socks5;
+
+/// Mainnet handshake with mode and session id features, peers with distinct session ids aren't taken for self connections.
+#[cfg(test)]
+pub(crate) fn create_hs(peer_name: &str, session_id: i64) -> Handshake {
+    let short_string = |s: &str| ShortString::try_from(s.as_bytes()).expect("internal error: invalid short string");
+    let features = Features::try_new(vec![
+        PeerFeature::Mode(Mode { state_type: 0, is_verifying: true, nipopow_suffix_len: None, blocks_to_keep: -1 }),
+        PeerFeature::SessionId(SessionId { magic: MagicBytes::MAINNET, session_id }),
+    ])
+    .expect("internal error: invalid features vec length");
+    Handshake {
+        agent_name: short_string("ergoref"),
+        version: Version([4, 0, 5]),
+        peer_name: short_string(peer_name),
+        pub_address: None,
+        features: Some(features),
+    }
+}
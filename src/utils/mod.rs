@@ -1,3 +1,11 @@
+#[cfg(feature = "serde")]
+pub(crate) use serde_str::*;
+pub(crate) use random::*;
+pub(crate) use siphash::*;
 pub(crate) use time::*;
 
+#[cfg(feature = "serde")]
+mod serde_str;
+mod random;
+mod siphash;
 mod time;
@@ -0,0 +1,7 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+
+// Randomly keyed hasher output, good enough for jitters and sampling, but not for cryptography
+pub(crate) fn random_u64() -> u64 {
+    RandomState::new().build_hasher().finish()
+}
@@ -0,0 +1,19 @@
+// Implements serde traits for models, which have human-friendly string form defined by their `Display` and `FromStr` impls
+macro_rules! impl_serde_via_str {
+    ($t:ty) => {
+        impl ::serde::Serialize for $t {
+            fn serialize<S: ::serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.collect_str(self)
+            }
+        }
+
+        impl<'de> ::serde::Deserialize<'de> for $t {
+            fn deserialize<D: ::serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                let s = <String as ::serde::Deserialize>::deserialize(deserializer)?;
+                s.parse::<$t>().map_err(::serde::de::Error::custom)
+            }
+        }
+    };
+}
+
+pub(crate) use impl_serde_via_str;
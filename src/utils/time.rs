@@ -1,8 +1,9 @@
 use std::time::{SystemTime, UNIX_EPOCH};
 
+// Clock set before unix epoch gives zero timestamp, which is ignored by the reference node
 pub(crate) fn make_timestamp() -> u64 {
-    let now = SystemTime::now()
+    SystemTime::now()
         .duration_since(UNIX_EPOCH)
-        .expect("internal error: current time is before unix epoch");
-    now.as_millis() as u64
+        .map(|now| now.as_millis() as u64)
+        .unwrap_or_default()
 }